@@ -0,0 +1,306 @@
+//! HTTP API for orchestration. Requests are executed the same way as requests to control socket, and replies are
+//! the same JSON objects:
+//!
+//! * `GET /status`: state of sources and sets, and local entries.
+//! * `GET /sources`: state of sources: time of last refresh, its error and timestamp from header of list.
+//! * `GET /sets`: sets and numbers of their entries.
+//! * `GET /local`: local entries of allowlist and denylist, with their comments and expiry dates.
+//! * `GET /sets/<name>/entries`: all entries of set.
+//! * `GET /sets/<name>/contains?addr=<address>`: entries of set which contain address.
+//! * `POST /refresh`: refresh all sources immediately.
+//! * `POST /sources/<name>/refresh`: refresh source immediately.
+//!
+//! Unknown sources and sets are reported with status 404, sets which are not updated yet with status 503.
+
+use std;
+
+use failure;
+use form_urlencoded;
+use futures;
+use hyper;
+use serde_json;
+use tokio_core;
+
+use config;
+use control;
+use daemon;
+
+/// What to do for request.
+#[derive(Debug, PartialEq)]
+enum Route {
+    Status,
+    Sources,
+    Sets,
+    Local,
+    Request(control::Request),
+}
+
+/// Find route for request. Error is status and message for client.
+fn route(
+    method: &hyper::Method,
+    path: &str,
+    query: Option<&str>,
+) -> Result<Route, (hyper::StatusCode, &'static str)> {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let route = match (segments.as_slice(), method) {
+        (&["status"], &hyper::Method::Get) => Route::Status,
+        (&["sources"], &hyper::Method::Get) => Route::Sources,
+        (&["sets"], &hyper::Method::Get) => Route::Sets,
+        (&["local"], &hyper::Method::Get) => Route::Local,
+        (&["sets", set, "entries"], &hyper::Method::Get) => Route::Request(control::Request::Dump { set: set.into() }),
+        (&["sets", set, "contains"], &hyper::Method::Get) => {
+            let address = query
+                .and_then(|query| {
+                    form_urlencoded::parse(query.as_bytes())
+                        .find(|(key, _)| key == "addr")
+                        .map(|(_, value)| value)
+                })
+                .ok_or((hyper::StatusCode::BadRequest, "Address is not specified"))?
+                .parse()
+                .map_err(|_| (hyper::StatusCode::BadRequest, "Invalid address"))?;
+            Route::Request(control::Request::Contains {
+                set: set.into(),
+                address,
+            })
+        },
+        (&["refresh"], &hyper::Method::Post) => Route::Request(control::Request::Refresh { source: None }),
+        (&["sources", source, "refresh"], &hyper::Method::Post) => Route::Request(control::Request::Refresh {
+            source: Some(source.into()),
+        }),
+        (&["status"], _)
+        | (&["sources"], _)
+        | (&["sets"], _)
+        | (&["local"], _)
+        | (&["sets", _, "entries"], _)
+        | (&["sets", _, "contains"], _)
+        | (&["refresh"], _)
+        | (&["sources", _, "refresh"], _) => {
+            return Err((hyper::StatusCode::MethodNotAllowed, "Method is not allowed"));
+        },
+        _ => return Err((hyper::StatusCode::NotFound, "Not found")),
+    };
+    Ok(route)
+}
+
+fn status_code(result: &Result<serde_json::Value, failure::Error>) -> hyper::StatusCode {
+    match *result {
+        Ok(_) => hyper::StatusCode::Ok,
+        Err(ref error) if error.downcast_ref::<control::NotFound>().is_some() => hyper::StatusCode::NotFound,
+        Err(_) => hyper::StatusCode::ServiceUnavailable,
+    }
+}
+
+fn respond(daemon: &daemon::Daemon, request: &hyper::Request) -> hyper::Response {
+    let (status, body) = match route(request.method(), request.path(), request.query()) {
+        Ok(route) => {
+            let result = match route {
+                Route::Status => serde_json::to_value(control::status(&daemon.state.borrow())).map_err(Into::into),
+                Route::Sources => {
+                    serde_json::to_value(control::status(&daemon.state.borrow()).sources).map_err(Into::into)
+                },
+                Route::Sets => serde_json::to_value(control::status(&daemon.state.borrow()).sets).map_err(Into::into),
+                Route::Local => serde_json::to_value(control::status(&daemon.state.borrow()).local).map_err(Into::into),
+                Route::Request(request) => control::handle(daemon, request),
+            };
+            (status_code(&result), control::reply(result))
+        },
+        Err((status, message)) => (status, control::reply(Err(format_err!("{}", message)))),
+    };
+    debug!("HTTP API: {} {} - {}", request.method(), request.uri(), status);
+
+    hyper::Response::new()
+        .with_status(status)
+        .with_header(hyper::header::ContentType::json())
+        .with_header(hyper::header::ContentLength(body.len() as u64))
+        .with_body(body)
+}
+
+/// Function producing response for request.
+pub type Respond = fn(&daemon::Daemon, &hyper::Request) -> hyper::Response;
+
+struct Service {
+    daemon: daemon::Daemon,
+    respond: Respond,
+}
+
+impl hyper::server::Service for Service {
+    type Request = hyper::Request;
+    type Response = hyper::Response;
+    type Error = hyper::Error;
+    type Future = futures::future::FutureResult<Self::Response, Self::Error>;
+
+    fn call(&self, request: Self::Request) -> Self::Future {
+        futures::future::ok((self.respond)(&self.daemon, &request))
+    }
+}
+
+/// Serve HTTP requests on reactor of daemon, on socket passed by service manager or, without it, on newly bound
+/// socket. Name is used in log messages. Returns address actually bound.
+pub fn serve(
+    daemon: &daemon::Daemon,
+    listen: &std::net::SocketAddr,
+    listener: Option<std::net::TcpListener>,
+    name: &'static str,
+    respond: Respond,
+) -> Result<std::net::SocketAddr, failure::Error> {
+    use self::futures::Future;
+    use self::futures::Stream;
+
+    let listener = match listener {
+        Some(listener) => listener,
+        None => std::net::TcpListener::bind(listen)?,
+    };
+    let address = listener.local_addr()?;
+    let listener = tokio_core::net::TcpListener::from_listener(listener, &address, &daemon.handle)?;
+    info!("{}: listening on {}", name, address);
+
+    let http = hyper::server::Http::<hyper::Chunk>::new();
+    let daemon_clone = daemon.clone();
+    daemon.handle.spawn(
+        listener
+            .incoming()
+            .then(move |result| {
+                // Failure to accept one connection should not stop accepting others.
+                if let Err(ref error) = result {
+                    warn!("{}: unable to accept connection: {}", name, error);
+                }
+                Ok(result.ok())
+            })
+            .for_each(move |connection| {
+                if let Some((stream, _)) = connection {
+                    let service = Service {
+                        daemon: daemon_clone.clone(),
+                        respond,
+                    };
+                    daemon_clone.handle.spawn(
+                        http.serve_connection(stream, service)
+                            .map(|_| ())
+                            .map_err(move |error| debug!("{}: connection failed: {}", name, error)),
+                    );
+                }
+                Ok(())
+            }),
+    );
+    Ok(address)
+}
+
+/// Start serving requests on reactor of daemon. Returns address actually bound.
+pub fn start(
+    daemon: &daemon::Daemon,
+    config: &config::Api,
+    listener: Option<std::net::TcpListener>,
+) -> Result<std::net::SocketAddr, failure::Error> {
+    serve(daemon, &config.listen, listener, "HTTP API", respond)
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+
+    use super::*;
+
+    #[test]
+    fn route() {
+        let get = |path, query| super::route(&hyper::Method::Get, path, query);
+
+        assert_eq!(get("/status", None), Ok(Route::Status));
+        assert_eq!(get("/sets/", None), Ok(Route::Sets));
+        assert_eq!(get("/local", None), Ok(Route::Local));
+        assert_eq!(
+            get("/sets/blocked/contains", Some("addr=192.0.2.1")),
+            Ok(Route::Request(control::Request::Contains {
+                set: "blocked".into(),
+                address: std::net::Ipv4Addr::new(192, 0, 2, 1).into(),
+            }))
+        );
+        assert_eq!(
+            get("/sets/blocked/contains", Some("address=192.0.2.1")),
+            Err((hyper::StatusCode::BadRequest, "Address is not specified"))
+        );
+        assert_eq!(
+            get("/sets/blocked/contains", Some("addr=192.0.2")),
+            Err((hyper::StatusCode::BadRequest, "Invalid address"))
+        );
+        assert_eq!(
+            super::route(&hyper::Method::Post, "/sources/local/refresh", None),
+            Ok(Route::Request(control::Request::Refresh {
+                source: Some("local".into()),
+            }))
+        );
+        assert_eq!(
+            get("/refresh", None),
+            Err((hyper::StatusCode::MethodNotAllowed, "Method is not allowed"))
+        );
+        assert_eq!(get("/sets/blocked", None), Err((hyper::StatusCode::NotFound, "Not found")));
+    }
+
+    /// Send request over new connection and read whole response.
+    fn send(address: std::net::SocketAddr, request: &str) -> String {
+        use std::io::Read;
+        use std::io::Write;
+
+        let mut stream = std::net::TcpStream::connect(address).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn loopback() {
+        let config: config::Config = r#"
+            [sources.local]
+            type = "file"
+            path = "dump.csv"
+
+            [sets.blocked]
+            source = "local"
+            types = ["ipv4"]
+        "#.parse()
+            .unwrap();
+        let mut core = tokio_core::reactor::Core::new().unwrap();
+        let daemon = daemon::Daemon::new(&core.handle(), &config);
+        let address = start(
+            &daemon,
+            &config::Api {
+                listen: "127.0.0.1:0".parse().unwrap(),
+            },
+            None,
+        ).unwrap();
+
+        // Client blocks, so it runs in separate thread while reactor serves requests.
+        let (sender, receiver) = futures::sync::oneshot::channel();
+        let client = std::thread::spawn(move || {
+            let responses: Vec<String> = [
+                "GET /sets HTTP/1.0\r\n\r\n",
+                "GET /sets/blocked/contains?addr=192.0.2.1 HTTP/1.0\r\n\r\n",
+                "GET /sets/missing/entries HTTP/1.0\r\n\r\n",
+                "DELETE /sets HTTP/1.0\r\n\r\n",
+            ].iter()
+                .map(|request| send(address, request))
+                .collect();
+            sender.send(()).unwrap();
+            responses
+        });
+        core.run(receiver).unwrap();
+        let responses = client.join().unwrap();
+
+        // Bodies are compared parsed, order of keys depends on features of `serde_json`.
+        let expected = [
+            ("200 OK", r#"{"result": {"blocked": {"deltas": null, "entries": null, "source": "local"}}}"#),
+            ("503 Service Unavailable", r#"{"error": "Set \"blocked\" is not updated yet"}"#),
+            ("404 Not Found", r#"{"error": "Unknown set \"missing\""}"#),
+            ("405 Method Not Allowed", r#"{"error": "Method is not allowed"}"#),
+        ];
+        for (response, &(status, body)) in responses.iter().zip(&expected) {
+            assert!(response.starts_with(&format!("HTTP/1.0 {}\r\n", status)), "{}", response);
+            assert!(response.contains("Content-Type: application/json\r\n"), "{}", response);
+            let (_, received) = response.split_at(response.find("\r\n\r\n").unwrap() + 4);
+            assert!(received.ends_with('\n'), "{}", response);
+            assert_eq!(
+                serde_json::from_str::<serde_json::Value>(received).unwrap(),
+                serde_json::from_str::<serde_json::Value>(body).unwrap()
+            );
+        }
+    }
+}
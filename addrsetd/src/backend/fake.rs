@@ -0,0 +1,174 @@
+//! In-memory backend for tests. Mimics behaviour of kernel sets closely enough to test logic built upon them.
+
+use std;
+
+use failure;
+use ipnet;
+
+use config;
+
+use super::{Backend, EntryError, SetKind};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FakeSet {
+    pub kind: SetKind,
+    pub family: config::AddressFamily,
+    pub max_entries: u32,
+    pub entries: std::collections::BTreeSet<ipnet::IpNet>,
+}
+
+impl FakeSet {
+    fn new(kind: SetKind, family: config::AddressFamily, max_entries: u32) -> Self {
+        Self {
+            kind,
+            family,
+            max_entries,
+            entries: std::collections::BTreeSet::new(),
+        }
+    }
+
+    fn add(&mut self, entries: &[ipnet::IpNet], disjoint: bool) -> Vec<EntryError> {
+        let mut errors = Vec::new();
+        for entry in entries {
+            let family = match *entry {
+                ipnet::IpNet::V4(_) => config::AddressFamily::IPv4,
+                ipnet::IpNet::V6(_) => config::AddressFamily::IPv6,
+            };
+            let error = if family != self.family {
+                Some("Protocol family not supported by the set")
+            } else if self.kind == SetKind::Addresses && entry.prefix_len() != entry.max_prefix_len() {
+                Some("Network can not be stored in set of single addresses")
+            } else if entry.prefix_len() == 0 {
+                Some("The value of the CIDR parameter of the IP address is invalid")
+            } else if !self.entries.contains(entry) && self.entries.len() >= self.max_entries as usize {
+                Some("Hash is full, cannot add more elements")
+            } else if disjoint && self.entries.iter().any(|existing| {
+                existing != entry && (existing.contains(entry) || entry.contains(existing))
+            }) {
+                Some("Conflicts with existing element")
+            } else {
+                None
+            };
+
+            match error {
+                Some(error) => errors.push(EntryError {
+                    entry: *entry,
+                    error: error.into(),
+                }),
+                None => {
+                    let _ = self.entries.insert(*entry);
+                },
+            }
+        }
+        errors
+    }
+}
+
+/// Sets are shared between clones, so test can inspect them after backend is moved into daemon.
+#[derive(Clone, Default)]
+pub struct Fake {
+    pub sets: std::rc::Rc<std::cell::RefCell<std::collections::BTreeMap<String, FakeSet>>>,
+    /// Sets of networks reject overlapping entries, as sets of ranges do.
+    disjoint: bool,
+}
+
+impl Fake {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn disjoint() -> Self {
+        Self {
+            disjoint: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn entries(&self, name: &str) -> Vec<String> {
+        self.sets.borrow()[name]
+            .entries
+            .iter()
+            .map(|entry| format!("{}", entry))
+            .collect()
+    }
+
+    fn with_set<Output, Function>(&self, name: &str, function: Function) -> Result<Output, failure::Error>
+    where
+        Function: FnOnce(&mut FakeSet) -> Output,
+    {
+        match self.sets.borrow_mut().get_mut(name) {
+            Some(set) => Ok(function(set)),
+            None => bail!("The set with the given name does not exist"),
+        }
+    }
+}
+
+impl Backend for Fake {
+    fn create(
+        &mut self,
+        name: &str,
+        kind: SetKind,
+        family: config::AddressFamily,
+        max_entries: u32,
+    ) -> Result<(), failure::Error> {
+        let mut sets = self.sets.borrow_mut();
+        if let Some(set) = sets.get(name) {
+            // Kernel accepts only the same set, including its maximum number of entries.
+            ensure!(
+                set.kind == kind && set.family == family && set.max_entries == max_entries,
+                "Set cannot be created: set with the same name already exists"
+            );
+            return Ok(());
+        }
+
+        let _ = sets.insert(name.into(), FakeSet::new(kind, family, max_entries));
+        Ok(())
+    }
+
+    /// Swaps filled temporary set with existing one, as ipset backend does.
+    fn replace(
+        &mut self,
+        name: &str,
+        kind: SetKind,
+        family: config::AddressFamily,
+        max_entries: u32,
+        entries: &[ipnet::IpNet],
+    ) -> Result<Vec<EntryError>, failure::Error> {
+        let mut temporary = FakeSet::new(kind, family, max_entries);
+        let errors = temporary.add(entries, self.disjoint);
+        self.with_set(name, |set| {
+            ensure!(
+                set.kind == kind && set.family == family,
+                "The sets cannot be swapped: their type does not match"
+            );
+            *set = temporary;
+            Ok(errors)
+        })?
+    }
+
+    fn add(&mut self, name: &str, entries: &[ipnet::IpNet]) -> Result<Vec<EntryError>, failure::Error> {
+        let disjoint = self.disjoint;
+        self.with_set(name, |set| set.add(entries, disjoint))
+    }
+
+    fn delete(&mut self, name: &str, entries: &[ipnet::IpNet]) -> Result<Vec<EntryError>, failure::Error> {
+        self.with_set(name, |set| {
+            for entry in entries {
+                let _ = set.entries.remove(entry);
+            }
+            Vec::new()
+        })
+    }
+
+    fn count(&mut self, name: &str) -> Result<Option<usize>, failure::Error> {
+        Ok(self.sets.borrow().get(name).map(|set| set.entries.len()))
+    }
+
+    fn list(&mut self, name: &str) -> Result<Option<std::collections::BTreeSet<ipnet::IpNet>>, failure::Error> {
+        Ok(self.sets.borrow().get(name).map(|set| set.entries.clone()))
+    }
+
+    fn requires_disjoint_entries(&self) -> bool {
+        self.disjoint
+    }
+}
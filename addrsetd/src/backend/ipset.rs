@@ -0,0 +1,478 @@
+//! Linux ipset backend. Talks to kernel over netlink directly, see `include/uapi/linux/netfilter/ipset/ip_set.h`.
+
+use std;
+
+use failure;
+use ipnet;
+
+use config;
+
+use super::netlink;
+use super::{Backend, EntryError, SetKind};
+
+/// Maximum length of set name supported by kernel, without terminating zero.
+const IPSET_MAXNAMELEN: usize = 31;
+/// Suffix of temporary set, which receives new contents before being swapped with live set. Character '~' is not
+/// allowed in names of configured sets, so temporary set never clashes with them.
+const TEMPORARY_SUFFIX: &str = "~tmp";
+/// Maximum length of name of configured set, leaves room for suffix of temporary set.
+pub const MAX_NAME_LENGTH: usize = IPSET_MAXNAMELEN - TEMPORARY_SUFFIX.len();
+
+/// Number of entries sent to kernel in one message.
+const BATCH_SIZE: usize = 1024;
+
+const NFNL_SUBSYS_IPSET: u16 = 6;
+const NFPROTO_IPV4: u8 = 2;
+const NFPROTO_IPV6: u8 = 10;
+/// Oldest version of protocol, supported by all kernels since 2.6.39.
+const IPSET_PROTOCOL: u8 = 6;
+
+const IPSET_CMD_CREATE: u8 = 2;
+const IPSET_CMD_DESTROY: u8 = 3;
+const IPSET_CMD_SWAP: u8 = 6;
+const IPSET_CMD_LIST: u8 = 7;
+const IPSET_CMD_ADD: u8 = 9;
+const IPSET_CMD_DEL: u8 = 10;
+
+// Attributes of command.
+const IPSET_ATTR_PROTOCOL: u16 = 1;
+const IPSET_ATTR_SETNAME: u16 = 2;
+const IPSET_ATTR_TYPENAME: u16 = 3;
+const IPSET_ATTR_SETNAME2: u16 = IPSET_ATTR_TYPENAME;
+const IPSET_ATTR_REVISION: u16 = 4;
+const IPSET_ATTR_FAMILY: u16 = 5;
+const IPSET_ATTR_FLAGS: u16 = 6;
+const IPSET_ATTR_DATA: u16 = 7;
+const IPSET_ATTR_ADT: u16 = 8;
+const IPSET_ATTR_LINENO: u16 = 9;
+
+// Attributes of data.
+const IPSET_ATTR_IP: u16 = 1;
+const IPSET_ATTR_CIDR: u16 = 3;
+const IPSET_ATTR_MAXELEM: u16 = 19;
+const IPSET_ATTR_ELEMENTS: u16 = 24;
+
+/// Flag of list command: only header of set, without entries.
+const IPSET_FLAG_LIST_HEADER: u32 = 1 << 2;
+
+// Attributes of address.
+const IPSET_ATTR_IPADDR_IPV4: u16 = 1;
+const IPSET_ATTR_IPADDR_IPV6: u16 = 2;
+
+const ENOENT: i32 = 2;
+const EEXIST: i32 = 17;
+const IPSET_ERR_HASH_FULL: i32 = 4352;
+
+/// Messages for error codes specific to ipset, as reported by ipset(8).
+const ERRORS: &[(i32, &str)] = &[
+    (4097, "Kernel error received: ipset protocol error"),
+    (4098, "Kernel error received: set type not supported"),
+    (4099, "Kernel error received: maximal number of sets reached, cannot create more"),
+    (4100, "Set cannot be destroyed: it is in use by a kernel component"),
+    (4102, "The sets cannot be swapped: their type does not match"),
+    (4103, "Set cannot be created: set with the same name already exists"),
+    (4104, "The value of the CIDR parameter of the IP address is invalid"),
+    (4106, "Protocol family not supported by the set"),
+    (4108, "Set cannot be destroyed: it is in use by a kernel component"),
+    (4109, "An IPv4 address is expected, but not received"),
+    (4110, "An IPv6 address is expected, but not received"),
+    (IPSET_ERR_HASH_FULL, "Hash is full, cannot add more elements"),
+    (4353, "Null-valued element, cannot be stored in a hash type of set"),
+];
+
+fn describe_error(code: i32) -> String {
+    // Generic error codes have special meaning for commands over sets.
+    match code {
+        ENOENT => return "The set with the given name does not exist".into(),
+        EEXIST => return "Set cannot be created: set with the same name already exists".into(),
+        _ => {},
+    }
+
+    match ERRORS.iter().find(|&&(error_code, _)| error_code == code) {
+        Some(&(_, message)) => message.into(),
+        None if code < 4096 => format!("{}", std::io::Error::from_raw_os_error(code)),
+        None => format!("Unknown ipset error {}", code),
+    }
+}
+
+fn family_code(family: config::AddressFamily) -> u8 {
+    match family {
+        config::AddressFamily::IPv4 => NFPROTO_IPV4,
+        config::AddressFamily::IPv6 => NFPROTO_IPV6,
+    }
+}
+
+/// Family in header of messages is not used by kernel, family of set is given by attribute on creation.
+fn message(command: u8) -> netlink::Message {
+    let mut message = netlink::Message::new(
+        NFNL_SUBSYS_IPSET << 8 | u16::from(command),
+        netlink::NLM_F_ACK,
+        NFPROTO_IPV4,
+        0,
+    );
+    message.put_u8(IPSET_ATTR_PROTOCOL, IPSET_PROTOCOL);
+    message
+}
+
+fn put_entry(message: &mut netlink::Message, entry: &ipnet::IpNet, lineno: u32) {
+    message.begin_nested(IPSET_ATTR_DATA);
+    message.begin_nested(IPSET_ATTR_IP);
+    match *entry {
+        ipnet::IpNet::V4(entry) => message.put_u32_be(IPSET_ATTR_IPADDR_IPV4, u32::from(entry.addr())),
+        ipnet::IpNet::V6(entry) => message.put_be(IPSET_ATTR_IPADDR_IPV6, &entry.addr().octets()),
+    }
+    message.end_nested();
+    // Without CIDR attribute kernel uses longest prefix. Sets of single addresses never get networks.
+    if entry.prefix_len() < entry.max_prefix_len() {
+        message.put_u8(IPSET_ATTR_CIDR, entry.prefix_len());
+    }
+    message.put_u32(IPSET_ATTR_LINENO, lineno);
+    message.end_nested();
+}
+
+/// Address from nested IP attribute.
+fn read_address(ip: &[u8]) -> Option<std::net::IpAddr> {
+    if let Some(address) = netlink::find_attribute(ip, IPSET_ATTR_IPADDR_IPV4).and_then(netlink::read_u32_be) {
+        return Some(std::net::Ipv4Addr::from(address).into());
+    }
+
+    let address = netlink::find_attribute(ip, IPSET_ATTR_IPADDR_IPV6).filter(|value| value.len() == 16)?;
+    let mut octets = [0; 16];
+    octets.copy_from_slice(address);
+    Some(std::net::Ipv6Addr::from(octets).into())
+}
+
+/// Entries from message of set dump. Entries without CIDR attribute are single addresses.
+fn read_entries(attributes: &[u8]) -> Vec<ipnet::IpNet> {
+    let adt = match netlink::find_attribute(attributes, IPSET_ATTR_ADT) {
+        Some(adt) => adt,
+        None => return Vec::new(),
+    };
+    netlink::attributes(adt)
+        .into_iter()
+        .filter(|&(kind, _)| kind == IPSET_ATTR_DATA)
+        .filter_map(|(_, data)| {
+            let address = netlink::find_attribute(data, IPSET_ATTR_IP).and_then(read_address)?;
+            let max_prefix_len = if address.is_ipv4() { 32 } else { 128 };
+            let prefix_len = netlink::find_attribute(data, IPSET_ATTR_CIDR)
+                .filter(|value| value.len() == 1)
+                .map_or(max_prefix_len, |value| value[0]);
+            ipnet::IpNet::new(address, prefix_len).ok()
+        })
+        .collect()
+}
+
+/// Error code and, for failed entry of batch request, its line number. Kernel stores line number into command
+/// attribute of copy of request sent back in error message.
+fn failure(acks: &[netlink::Ack]) -> Result<Option<(i32, Option<u32>)>, failure::Error> {
+    let ack = acks.iter()
+        .find(|ack| ack.index == 0)
+        .ok_or_else(|| format_err!("No acknowledgement received from kernel"))?;
+    if ack.code == 0 {
+        return Ok(None);
+    }
+
+    let lineno = netlink::find_attribute(&ack.attributes, IPSET_ATTR_LINENO)
+        .and_then(|value| if value.len() == 4 { Some(netlink::read_u32(value, 0)) } else { None })
+        .and_then(|lineno| if lineno != 0 { Some(lineno) } else { None });
+    Ok(Some((ack.code, lineno)))
+}
+
+pub struct IPSet {
+    socket: netlink::Socket,
+}
+
+impl IPSet {
+    pub fn new() -> Self {
+        Self {
+            socket: netlink::Socket::new(),
+        }
+    }
+
+    fn check(&mut self, message: netlink::Message, action: &str) -> Result<(), failure::Error> {
+        match failure(&self.socket.request(vec![message])?)? {
+            None => Ok(()),
+            Some((code, _)) => bail!("Unable to {} set: {}", action, describe_error(code)),
+        }
+    }
+
+    /// Messages of set dump, `None` if set does not exist. Header only dump has no entries.
+    fn dump(&mut self, name: &str, header_only: bool) -> Result<Option<Vec<Vec<u8>>>, failure::Error> {
+        let mut message = netlink::Message::new(
+            NFNL_SUBSYS_IPSET << 8 | u16::from(IPSET_CMD_LIST),
+            netlink::NLM_F_DUMP,
+            NFPROTO_IPV4,
+            0,
+        );
+        message.put_u8(IPSET_ATTR_PROTOCOL, IPSET_PROTOCOL);
+        message.put_string(IPSET_ATTR_SETNAME, name);
+        if header_only {
+            message.put_u32_be(IPSET_ATTR_FLAGS, IPSET_FLAG_LIST_HEADER);
+        }
+
+        let dump = self.socket.dump(message)?;
+        match dump.code {
+            0 => Ok(Some(dump.messages)),
+            ENOENT => Ok(None),
+            code => bail!("Unable to list set: {}", describe_error(code)),
+        }
+    }
+
+    /// Destroy set if it exists.
+    fn destroy(&mut self, name: &str) -> Result<(), failure::Error> {
+        let mut message = message(IPSET_CMD_DESTROY);
+        message.put_string(IPSET_ATTR_SETNAME, name);
+        match failure(&self.socket.request(vec![message])?)? {
+            None | Some((ENOENT, _)) => Ok(()),
+            Some((code, _)) => bail!("Unable to destroy set: {}", describe_error(code)),
+        }
+    }
+
+    /// Fill temporary set and exchange it with live one. Kernel swaps sets atomically, even when they are referenced
+    /// by iptables rules.
+    fn fill_and_swap(
+        &mut self,
+        name: &str,
+        temporary: &str,
+        entries: &[ipnet::IpNet],
+    ) -> Result<Vec<EntryError>, failure::Error> {
+        let errors = self.update(IPSET_CMD_ADD, temporary, entries)?;
+
+        let mut message = message(IPSET_CMD_SWAP);
+        message.put_string(IPSET_ATTR_SETNAME, temporary);
+        message.put_string(IPSET_ATTR_SETNAME2, name);
+        self.check(message, "swap")?;
+        Ok(errors)
+    }
+
+    /// Add or delete entries in batches. Kernel stops processing of batch on first failed entry, so batch is resent
+    /// starting with entry following failed one.
+    fn update(
+        &mut self,
+        command: u8,
+        name: &str,
+        entries: &[ipnet::IpNet],
+    ) -> Result<Vec<EntryError>, failure::Error> {
+        let mut errors = Vec::new();
+        let mut remaining = entries;
+        while !remaining.is_empty() {
+            let batch = &remaining[..std::cmp::min(BATCH_SIZE, remaining.len())];
+
+            let mut message = message(command);
+            message.put_string(IPSET_ATTR_SETNAME, name);
+            // Placeholder for line number of failed entry.
+            message.put_u32(IPSET_ATTR_LINENO, 0);
+            message.begin_nested(IPSET_ATTR_ADT);
+            for (index, entry) in batch.iter().enumerate() {
+                put_entry(&mut message, entry, index as u32 + 1);
+            }
+            message.end_nested();
+
+            match failure(&self.socket.request(vec![message])?)? {
+                None => remaining = &remaining[batch.len()..],
+                Some((code, Some(lineno))) if code != IPSET_ERR_HASH_FULL && lineno as usize <= batch.len() => {
+                    let index = lineno as usize - 1;
+                    errors.push(EntryError {
+                        entry: batch[index],
+                        error: describe_error(code),
+                    });
+                    remaining = &remaining[index + 1..];
+                },
+                Some((code, _)) => bail!("Unable to update set: {}", describe_error(code)),
+            }
+        }
+
+        Ok(errors)
+    }
+}
+
+impl Backend for IPSet {
+    fn create(
+        &mut self,
+        name: &str,
+        kind: SetKind,
+        family: config::AddressFamily,
+        max_entries: u32,
+    ) -> Result<(), failure::Error> {
+        let mut message = message(IPSET_CMD_CREATE);
+        message.put_string(IPSET_ATTR_SETNAME, name);
+        message.put_string(
+            IPSET_ATTR_TYPENAME,
+            match kind {
+                SetKind::Addresses => "hash:ip",
+                SetKind::Networks => "hash:net",
+            },
+        );
+        message.put_u8(IPSET_ATTR_REVISION, 0);
+        message.put_u8(IPSET_ATTR_FAMILY, family_code(family));
+        message.begin_nested(IPSET_ATTR_DATA);
+        message.put_u32_be(IPSET_ATTR_MAXELEM, max_entries);
+        message.end_nested();
+        self.check(message, "create")
+    }
+
+    /// New contents are collected in temporary set, which is swapped with live set and then destroyed along with old
+    /// contents. Temporary set left by interrupted replacement is destroyed first.
+    fn replace(
+        &mut self,
+        name: &str,
+        kind: SetKind,
+        family: config::AddressFamily,
+        max_entries: u32,
+        entries: &[ipnet::IpNet],
+    ) -> Result<Vec<EntryError>, failure::Error> {
+        let temporary = format!("{}{}", name, TEMPORARY_SUFFIX);
+        self.destroy(&temporary)?;
+        self.create(&temporary, kind, family, max_entries)?;
+
+        let result = self.fill_and_swap(name, &temporary, entries);
+        let destroyed = self.destroy(&temporary);
+        let errors = result?;
+        destroyed.map_err(|error| error.context("Set is replaced, but temporary set is left"))?;
+        Ok(errors)
+    }
+
+    fn add(&mut self, name: &str, entries: &[ipnet::IpNet]) -> Result<Vec<EntryError>, failure::Error> {
+        self.update(IPSET_CMD_ADD, name, entries)
+    }
+
+    fn delete(&mut self, name: &str, entries: &[ipnet::IpNet]) -> Result<Vec<EntryError>, failure::Error> {
+        self.update(IPSET_CMD_DEL, name, entries)
+    }
+
+    /// Uses number of entries reported in header of set, old kernels do not report it.
+    fn count(&mut self, name: &str) -> Result<Option<usize>, failure::Error> {
+        let messages = match self.dump(name, true)? {
+            Some(messages) => messages,
+            None => return Ok(None),
+        };
+
+        let count = messages
+            .iter()
+            .filter_map(|attributes| netlink::find_attribute(attributes, IPSET_ATTR_DATA))
+            .filter_map(|data| netlink::find_attribute(data, IPSET_ATTR_ELEMENTS))
+            .find_map(netlink::read_u32_be)
+            .ok_or_else(|| format_err!("Unable to list set: number of entries is not reported by kernel"))?;
+        Ok(Some(count as usize))
+    }
+
+    fn list(&mut self, name: &str) -> Result<Option<std::collections::BTreeSet<ipnet::IpNet>>, failure::Error> {
+        Ok(self.dump(name, false)?.map(|messages| {
+            messages
+                .iter()
+                .flat_map(|attributes| read_entries(attributes))
+                .collect()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn message() {
+        let mut message = super::message(IPSET_CMD_ADD);
+        message.put_string(IPSET_ATTR_SETNAME, "test");
+        message.put_u32(IPSET_ATTR_LINENO, 0);
+        message.begin_nested(IPSET_ATTR_ADT);
+        put_entry(&mut message, &"192.0.2.1/32".parse().unwrap(), 1);
+        put_entry(&mut message, &"198.51.100.0/24".parse().unwrap(), 2);
+        message.end_nested();
+        let data = message.finish(7);
+
+        let mut expected = Vec::new();
+        expected.extend_from_slice(&(data.len() as u32).to_ne_bytes());
+        expected.extend_from_slice(&0x0609u16.to_ne_bytes());
+        expected.extend_from_slice(&0x0005u16.to_ne_bytes());
+        expected.extend_from_slice(&7u32.to_ne_bytes());
+        expected.extend_from_slice(&0u32.to_ne_bytes());
+        expected.extend_from_slice(&[2, 0, 0, 0]);
+        for &(length, attribute, ref value) in &[
+            (5u16, 1u16, vec![6u8, 0, 0, 0]),
+            (9, 2, b"test\0\0\0\0".to_vec()),
+            (8, 9, 0u32.to_ne_bytes().to_vec()),
+        ] {
+            expected.extend_from_slice(&length.to_ne_bytes());
+            expected.extend_from_slice(&attribute.to_ne_bytes());
+            expected.extend_from_slice(value);
+        }
+        expected.extend_from_slice(&60u16.to_ne_bytes());
+        expected.extend_from_slice(&0x8008u16.to_ne_bytes());
+        for &(address, cidr, lineno) in &[([192u8, 0, 2, 1], None, 1u32), ([198, 51, 100, 0], Some(24u8), 2)] {
+            let length: u16 = if cidr.is_some() { 32 } else { 24 };
+            expected.extend_from_slice(&length.to_ne_bytes());
+            expected.extend_from_slice(&0x8007u16.to_ne_bytes());
+            expected.extend_from_slice(&12u16.to_ne_bytes());
+            expected.extend_from_slice(&0x8001u16.to_ne_bytes());
+            expected.extend_from_slice(&8u16.to_ne_bytes());
+            expected.extend_from_slice(&0x4001u16.to_ne_bytes());
+            expected.extend_from_slice(&address);
+            if let Some(cidr) = cidr {
+                expected.extend_from_slice(&5u16.to_ne_bytes());
+                expected.extend_from_slice(&3u16.to_ne_bytes());
+                expected.extend_from_slice(&[cidr, 0, 0, 0]);
+            }
+            expected.extend_from_slice(&8u16.to_ne_bytes());
+            expected.extend_from_slice(&9u16.to_ne_bytes());
+            expected.extend_from_slice(&lineno.to_ne_bytes());
+        }
+
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn read_entries() {
+        // Entries in dump have the same layout as in requests.
+        let mut message = super::message(IPSET_CMD_LIST);
+        message.put_string(IPSET_ATTR_SETNAME, "test");
+        message.begin_nested(IPSET_ATTR_ADT);
+        let list = ["192.0.2.1/32", "198.51.100.0/24", "2001:db8::1/128", "2001:db8:1::/48"];
+        for (index, entry) in list.iter().enumerate() {
+            put_entry(&mut message, &entry.parse().unwrap(), index as u32);
+        }
+        message.end_nested();
+
+        let entries: Vec<String> = super::read_entries(&message.finish(1)[20..])
+            .iter()
+            .map(|entry| format!("{}", entry))
+            .collect();
+        assert_eq!(entries, list);
+        assert!(super::read_entries(&super::message(IPSET_CMD_LIST).finish(1)[20..]).is_empty());
+    }
+
+    #[test]
+    fn failure() {
+        let ack = |index, code, lineno: Option<u32>| {
+            let mut message = super::message(IPSET_CMD_ADD);
+            message.put_string(IPSET_ATTR_SETNAME, "test");
+            if let Some(lineno) = lineno {
+                message.put_u32(IPSET_ATTR_LINENO, lineno);
+            }
+            netlink::Ack {
+                index,
+                code,
+                attributes: message.finish(1)[20..].to_vec(),
+            }
+        };
+
+        assert_eq!(super::failure(&[ack(0, 0, None)]).unwrap(), None);
+        assert_eq!(super::failure(&[ack(0, 4104, Some(5))]).unwrap(), Some((4104, Some(5))));
+        assert_eq!(super::failure(&[ack(0, ENOENT, Some(0))]).unwrap(), Some((ENOENT, None)));
+        assert_eq!(super::failure(&[ack(0, ENOENT, None)]).unwrap(), Some((ENOENT, None)));
+        assert!(super::failure(&[ack(1, 0, None)]).is_err());
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(describe_error(ENOENT), "The set with the given name does not exist");
+        assert_eq!(
+            describe_error(EEXIST),
+            "Set cannot be created: set with the same name already exists"
+        );
+        assert_eq!(describe_error(4102), "The sets cannot be swapped: their type does not match");
+        assert_eq!(describe_error(IPSET_ERR_HASH_FULL), "Hash is full, cannot add more elements");
+        assert_eq!(describe_error(1), "Operation not permitted (os error 1)");
+        assert_eq!(describe_error(5000), "Unknown ipset error 5000");
+    }
+}
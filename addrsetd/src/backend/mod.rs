@@ -0,0 +1,87 @@
+use std;
+
+use failure;
+use ipnet;
+
+use config;
+
+#[cfg(test)]
+pub mod fake;
+pub mod ipset;
+mod netlink;
+pub mod nftables;
+
+/// Kind of set, depends on types of addresses stored in it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SetKind {
+    /// Single addresses only, "hash:ip" in terms of ipset.
+    Addresses,
+    /// Networks and single addresses (as networks with longest prefix), "hash:net" in terms of ipset.
+    Networks,
+}
+
+/// Entry rejected by backend. Does not prevent other entries from being applied.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EntryError {
+    pub entry: ipnet::IpNet,
+    pub error: String,
+}
+
+impl std::fmt::Display for EntryError {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter, "{}: {}", self.entry, self.error)
+    }
+}
+
+/// Target which stores sets of addresses, e.g. kernel firewall.
+pub trait Backend {
+    /// Create set if it does not exist yet. Existing set of different kind or family is an error, as is existing set
+    /// of different maximum number of entries for ipset.
+    fn create(
+        &mut self,
+        name: &str,
+        kind: SetKind,
+        family: config::AddressFamily,
+        max_entries: u32,
+    ) -> Result<(), failure::Error>;
+
+    /// Replace all entries of existing set at once, so set is never seen empty or partially filled. Failure leaves
+    /// old entries in place. Maximum number of entries of set is changed too.
+    fn replace(
+        &mut self,
+        name: &str,
+        kind: SetKind,
+        family: config::AddressFamily,
+        max_entries: u32,
+        entries: &[ipnet::IpNet],
+    ) -> Result<Vec<EntryError>, failure::Error>;
+
+    /// Add entries to set. Entries which are already present are ignored.
+    fn add(&mut self, name: &str, entries: &[ipnet::IpNet]) -> Result<Vec<EntryError>, failure::Error>;
+
+    /// Delete entries from set. Entries which are not present are ignored.
+    fn delete(&mut self, name: &str, entries: &[ipnet::IpNet]) -> Result<Vec<EntryError>, failure::Error>;
+
+    /// Number of entries in set, `None` if set does not exist. Reveals changes made to set by somebody else.
+    fn count(&mut self, name: &str) -> Result<Option<usize>, failure::Error>;
+
+    /// Entries of set, `None` if set does not exist. Expensive for large sets, so it is used only to learn contents
+    /// of sets left by previous run.
+    fn list(&mut self, name: &str) -> Result<Option<std::collections::BTreeSet<ipnet::IpNet>>, failure::Error>;
+
+    /// Whether sets of networks can not store overlapping entries, as sets of ranges.
+    fn requires_disjoint_entries(&self) -> bool {
+        false
+    }
+}
+
+/// Create backend for set. Backends connect to their targets lazily, on first operation.
+pub fn create(config: &config::Set) -> Box<Backend> {
+    match config.backend {
+        config::BackendType::IPSet => Box::new(ipset::IPSet::new()),
+        config::BackendType::NFTables => Box::new(nftables::NFTables::new(
+            config.table.as_ref().map_or(nftables::DEFAULT_TABLE, |table| table),
+            config.family.unwrap_or_default(),
+        )),
+    }
+}
@@ -0,0 +1,470 @@
+//! Minimal client of netfilter netlink subsystems, shared by backends which talk to kernel directly.
+
+use std;
+
+use failure;
+use nix;
+
+pub const NLM_F_ACK: u16 = 0x4;
+pub const NLM_F_DUMP: u16 = 0x300;
+pub const NLM_F_CREATE: u16 = 0x400;
+
+const NLM_F_REQUEST: u16 = 0x1;
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+
+const NLA_F_NESTED: u16 = 0x8000;
+const NLA_F_NET_BYTEORDER: u16 = 0x4000;
+
+const NETLINK_HEADER_SIZE: usize = 16;
+const NFNETLINK_HEADER_SIZE: usize = 4;
+
+/// Error replies contain copy of request, so buffer should fit the largest request.
+const RECEIVE_BUFFER_SIZE: usize = 128 * 1024;
+/// Default limits of socket buffers are too small for large batches.
+const MIN_FORCED_BUFFER_SIZE: usize = 128 * 1024;
+
+fn align(length: usize) -> usize {
+    (length + 3) & !3
+}
+
+fn read_u16(buffer: &[u8], offset: usize) -> u16 {
+    u16::from_ne_bytes([buffer[offset], buffer[offset + 1]])
+}
+
+/// Value of attribute with 32-bit number in network byte order, `None` if attribute has wrong size.
+pub fn read_u32_be(value: &[u8]) -> Option<u32> {
+    if value.len() == 4 {
+        Some(u32::from_be_bytes([value[0], value[1], value[2], value[3]]))
+    } else {
+        None
+    }
+}
+
+pub fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes([
+        buffer[offset],
+        buffer[offset + 1],
+        buffer[offset + 2],
+        buffer[offset + 3],
+    ])
+}
+
+/// Netlink message of netfilter subsystem under construction.
+pub struct Message {
+    buffer: Vec<u8>,
+    /// Offsets of nested attributes which are not finished yet.
+    nested: Vec<usize>,
+}
+
+impl Message {
+    pub fn new(message_type: u16, flags: u16, family: u8, resource_id: u16) -> Self {
+        let mut buffer = Vec::new();
+        // struct nlmsghdr, length and sequence number are filled by `finish()`.
+        buffer.extend_from_slice(&0u32.to_ne_bytes());
+        buffer.extend_from_slice(&message_type.to_ne_bytes());
+        buffer.extend_from_slice(&(NLM_F_REQUEST | flags).to_ne_bytes());
+        buffer.extend_from_slice(&0u32.to_ne_bytes());
+        buffer.extend_from_slice(&0u32.to_ne_bytes());
+        // struct nfgenmsg: family, version, resource id.
+        buffer.extend_from_slice(&[family, 0]);
+        buffer.extend_from_slice(&resource_id.to_be_bytes());
+
+        Self {
+            buffer,
+            nested: Vec::new(),
+        }
+    }
+
+    pub fn put(&mut self, attribute: u16, value: &[u8]) {
+        self.buffer.extend_from_slice(&(4 + value.len() as u16).to_ne_bytes());
+        self.buffer.extend_from_slice(&attribute.to_ne_bytes());
+        self.buffer.extend_from_slice(value);
+        let padded = align(self.buffer.len());
+        self.buffer.resize(padded, 0);
+    }
+
+    pub fn put_u8(&mut self, attribute: u16, value: u8) {
+        self.put(attribute, &[value]);
+    }
+
+    /// Put integer in host byte order.
+    pub fn put_u32(&mut self, attribute: u16, value: u32) {
+        self.put(attribute, &value.to_ne_bytes());
+    }
+
+    /// Put integer in network byte order, marking attribute accordingly.
+    pub fn put_u32_be(&mut self, attribute: u16, value: u32) {
+        self.put_be(attribute, &value.to_be_bytes());
+    }
+
+    /// Put value which is already in network byte order, e.g. IPv6 address, marking attribute accordingly.
+    pub fn put_be(&mut self, attribute: u16, value: &[u8]) {
+        self.put(attribute | NLA_F_NET_BYTEORDER, value);
+    }
+
+    /// Put zero-terminated string.
+    pub fn put_string(&mut self, attribute: u16, value: &str) {
+        let mut bytes = value.as_bytes().to_vec();
+        bytes.push(0);
+        self.put(attribute, &bytes);
+    }
+
+    pub fn begin_nested(&mut self, attribute: u16) {
+        self.nested.push(self.buffer.len());
+        self.buffer.extend_from_slice(&0u16.to_ne_bytes());
+        self.buffer.extend_from_slice(&(attribute | NLA_F_NESTED).to_ne_bytes());
+    }
+
+    pub fn end_nested(&mut self) {
+        let start = self.nested.pop().expect("No nested attribute to end");
+        let length = (self.buffer.len() - start) as u16;
+        self.buffer[start..start + 2].copy_from_slice(&length.to_ne_bytes());
+    }
+
+    pub fn finish(mut self, seq: u32) -> Vec<u8> {
+        assert!(self.nested.is_empty(), "Nested attribute is not ended");
+        let length = self.buffer.len() as u32;
+        self.buffer[0..4].copy_from_slice(&length.to_ne_bytes());
+        self.buffer[8..12].copy_from_slice(&seq.to_ne_bytes());
+        self.buffer
+    }
+}
+
+/// Types and values of attributes in buffer, without flags. Parsing stops at malformed attribute.
+pub fn attributes(buffer: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut attributes = Vec::new();
+    let mut offset = 0;
+    while offset + 4 <= buffer.len() {
+        let length = read_u16(buffer, offset) as usize;
+        if length < 4 || offset + length > buffer.len() {
+            break;
+        }
+        attributes.push((
+            read_u16(buffer, offset + 2) & !(NLA_F_NESTED | NLA_F_NET_BYTEORDER),
+            &buffer[offset + 4..offset + length],
+        ));
+        offset += align(length);
+    }
+    attributes
+}
+
+/// Find attribute of given type among attributes in buffer.
+pub fn find_attribute(buffer: &[u8], attribute: u16) -> Option<&[u8]> {
+    attributes(buffer)
+        .into_iter()
+        .find(|&(kind, _)| kind == attribute)
+        .map(|(_, value)| value)
+}
+
+/// Acknowledgement of one of sent messages.
+#[derive(Debug, PartialEq)]
+pub struct Ack {
+    /// Index of acknowledged message.
+    pub index: usize,
+    /// Error code, zero on success.
+    pub code: i32,
+    /// Attributes of failed message, as copied by kernel into error message.
+    pub attributes: Vec<u8>,
+}
+
+/// Find acknowledgements of messages with sequence numbers starting with given one among received messages.
+fn parse_acks(buffer: &[u8], first_seq: u32, count: usize) -> Result<Vec<Ack>, failure::Error> {
+    let mut acks = Vec::new();
+    let mut offset = 0;
+    while offset + NETLINK_HEADER_SIZE <= buffer.len() {
+        let length = read_u32(buffer, offset) as usize;
+        ensure!(
+            length >= NETLINK_HEADER_SIZE && offset + length <= buffer.len(),
+            "Malformed netlink message"
+        );
+
+        let index = read_u32(buffer, offset + 8).wrapping_sub(first_seq) as usize;
+        if read_u16(buffer, offset + 4) == NLMSG_ERROR && index < count {
+            ensure!(length >= NETLINK_HEADER_SIZE + 4, "Malformed netlink error message");
+            let attributes_offset = offset + NETLINK_HEADER_SIZE + 4 + NETLINK_HEADER_SIZE + NFNETLINK_HEADER_SIZE;
+            acks.push(Ack {
+                index,
+                code: -(read_u32(buffer, offset + NETLINK_HEADER_SIZE) as i32),
+                attributes: if attributes_offset <= offset + length {
+                    buffer[attributes_offset..offset + length].to_vec()
+                } else {
+                    Vec::new()
+                },
+            });
+        }
+
+        offset += align(length);
+    }
+
+    Ok(acks)
+}
+
+/// Reply to dump request.
+#[derive(Debug, PartialEq)]
+pub struct Dump {
+    /// Error code, zero on success.
+    pub code: i32,
+    /// Attributes of each received message.
+    pub messages: Vec<Vec<u8>>,
+}
+
+/// Collect messages of dump with given sequence number among received messages. Returns whether dump is finished.
+fn parse_dump(buffer: &[u8], seq: u32, dump: &mut Dump) -> Result<bool, failure::Error> {
+    let mut offset = 0;
+    while offset + NETLINK_HEADER_SIZE <= buffer.len() {
+        let length = read_u32(buffer, offset) as usize;
+        ensure!(
+            length >= NETLINK_HEADER_SIZE && offset + length <= buffer.len(),
+            "Malformed netlink message"
+        );
+
+        if read_u32(buffer, offset + 8) == seq {
+            match read_u16(buffer, offset + 4) {
+                NLMSG_DONE => return Ok(true),
+                NLMSG_ERROR => {
+                    ensure!(length >= NETLINK_HEADER_SIZE + 4, "Malformed netlink error message");
+                    dump.code = -(read_u32(buffer, offset + NETLINK_HEADER_SIZE) as i32);
+                    return Ok(true);
+                },
+                _ => {
+                    let attributes_offset = offset + NETLINK_HEADER_SIZE + NFNETLINK_HEADER_SIZE;
+                    ensure!(attributes_offset <= offset + length, "Malformed netlink message");
+                    dump.messages.push(buffer[attributes_offset..offset + length].to_vec());
+                },
+            }
+        }
+
+        offset += align(length);
+    }
+
+    Ok(false)
+}
+
+/// Netfilter netlink socket, opened on first request and closed after I/O error.
+pub struct Socket {
+    fd: Option<std::os::unix::io::OwnedFd>,
+    seq: u32,
+}
+
+impl Socket {
+    pub fn new() -> Self {
+        Self { fd: None, seq: 0 }
+    }
+
+    fn open() -> Result<std::os::unix::io::OwnedFd, failure::Error> {
+        use std::os::unix::io::AsRawFd;
+        use self::nix::sys::socket;
+
+        let fd = socket::socket(
+            socket::AddressFamily::Netlink,
+            socket::SockType::Raw,
+            socket::SockFlag::SOCK_CLOEXEC,
+            socket::SockProtocol::NetlinkNetFilter,
+        )?;
+        socket::bind(fd.as_raw_fd(), &socket::NetlinkAddr::new(0, 0))?;
+        Ok(fd)
+    }
+
+    /// Send messages in one datagram and return acknowledgements of them. Messages without `NLM_F_ACK` flag are
+    /// acknowledged only if they fail. Netfilter handles requests synchronously, so all acknowledgements are already
+    /// queued when sending completes.
+    pub fn request(&mut self, messages: Vec<Message>) -> Result<Vec<Ack>, failure::Error> {
+        self.exchange(|fd, seq| {
+            let count = messages.len();
+            let first_seq = send(fd, seq, messages)?;
+
+            let mut acks = Vec::new();
+            let mut buffer = vec![0; RECEIVE_BUFFER_SIZE];
+            while let Some(length) = receive(fd, &mut buffer, false)? {
+                acks.extend(parse_acks(&buffer[..length], first_seq, count)?);
+            }
+            Ok(acks)
+        })
+    }
+
+    /// Send dump request, message should have `NLM_F_DUMP` flag, and collect all messages of reply. Kernel produces
+    /// dump while it is being read, so reading blocks until dump is finished.
+    pub fn dump(&mut self, message: Message) -> Result<Dump, failure::Error> {
+        self.exchange(|fd, seq| {
+            let seq = send(fd, seq, vec![message])?;
+
+            let mut dump = Dump {
+                code: 0,
+                messages: Vec::new(),
+            };
+            let mut buffer = vec![0; RECEIVE_BUFFER_SIZE];
+            while let Some(length) = receive(fd, &mut buffer, true)? {
+                if parse_dump(&buffer[..length], seq, &mut dump)? {
+                    break;
+                }
+            }
+            Ok(dump)
+        })
+    }
+
+    fn exchange<Output, Function>(&mut self, function: Function) -> Result<Output, failure::Error>
+    where
+        Function: FnOnce(&std::os::unix::io::OwnedFd, &mut u32) -> Result<Output, failure::Error>,
+    {
+        // Socket is put back only if exchange succeeds, so broken one is reopened on next request.
+        let fd = match self.fd.take() {
+            Some(fd) => fd,
+            None => Self::open().map_err(|error| error.context("Unable to open netlink socket"))?,
+        };
+        let output = function(&fd, &mut self.seq)?;
+        self.fd = Some(fd);
+        Ok(output)
+    }
+}
+
+/// Send messages in one datagram, assigning sequence numbers to them. Returns sequence number of first message.
+fn send(fd: &std::os::unix::io::OwnedFd, seq: &mut u32, messages: Vec<Message>) -> Result<u32, failure::Error> {
+    use std::os::unix::io::AsRawFd;
+    use self::nix::sys::socket;
+
+    let first_seq = seq.wrapping_add(1);
+    let mut data = Vec::new();
+    for message in messages {
+        *seq = seq.wrapping_add(1);
+        data.extend(message.finish(*seq));
+    }
+
+    if data.len() > MIN_FORCED_BUFFER_SIZE {
+        socket::setsockopt(fd, socket::sockopt::SndBufForce, &data.len())?;
+        // Replies are queued while batch is processed, errors contain copies of failed messages.
+        socket::setsockopt(fd, socket::sockopt::RcvBufForce, &data.len())?;
+    }
+    let sent = socket::send(fd.as_raw_fd(), &data, socket::MsgFlags::empty())?;
+    ensure!(sent == data.len(), "Netlink message was sent partially");
+    Ok(first_seq)
+}
+
+/// Receive one datagram. Returns `None` if nothing is queued and reading should not block.
+fn receive(fd: &std::os::unix::io::OwnedFd, buffer: &mut [u8], block: bool) -> Result<Option<usize>, failure::Error> {
+    use std::os::unix::io::AsRawFd;
+    use self::nix::sys::socket;
+
+    let mut flags = socket::MsgFlags::MSG_TRUNC;
+    if !block {
+        flags |= socket::MsgFlags::MSG_DONTWAIT;
+    }
+    let length = match socket::recv(fd.as_raw_fd(), buffer, flags) {
+        Ok(length) => length,
+        Err(nix::errno::Errno::EAGAIN) => return Ok(None),
+        Err(nix::errno::Errno::ENOBUFS) => bail!("Netlink replies are lost, result of request is unknown"),
+        Err(error) => return Err(error.into()),
+    };
+    ensure!(length <= buffer.len(), "Netlink reply is too long ({} bytes)", length);
+    Ok(Some(length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reply of kernel to request with given error code.
+    fn reply(request: &[u8], code: i32) -> Vec<u8> {
+        let mut reply = Vec::new();
+        reply.extend_from_slice(&((NETLINK_HEADER_SIZE + 4 + request.len()) as u32).to_ne_bytes());
+        reply.extend_from_slice(&NLMSG_ERROR.to_ne_bytes());
+        reply.extend_from_slice(&0u16.to_ne_bytes());
+        reply.extend_from_slice(&request[8..12]);
+        reply.extend_from_slice(&0u32.to_ne_bytes());
+        reply.extend_from_slice(&(-code).to_ne_bytes());
+        reply.extend_from_slice(request);
+        reply
+    }
+
+    #[test]
+    fn message() {
+        let mut message = Message::new(0x0a0c, NLM_F_ACK, 2, 10);
+        message.put_string(1, "test");
+        message.begin_nested(3);
+        message.put_u8(4, 5);
+        message.put_u32_be(6, 0x0102_0304);
+        message.end_nested();
+        message.put_u32(7, 8);
+
+        let mut expected = Vec::new();
+        expected.extend_from_slice(&60u32.to_ne_bytes());
+        expected.extend_from_slice(&0x0a0cu16.to_ne_bytes());
+        expected.extend_from_slice(&5u16.to_ne_bytes());
+        expected.extend_from_slice(&9u32.to_ne_bytes());
+        expected.extend_from_slice(&0u32.to_ne_bytes());
+        expected.extend_from_slice(&[2, 0, 0, 10]);
+        for &(length, attribute, ref value) in &[
+            (9u16, 1u16, b"test\0\0\0\0".to_vec()),
+            (20, 0x8003, vec![]),
+            (5, 4, vec![5, 0, 0, 0]),
+            (8, 0x4006, vec![1, 2, 3, 4]),
+            (8, 7, 8u32.to_ne_bytes().to_vec()),
+        ] {
+            expected.extend_from_slice(&length.to_ne_bytes());
+            expected.extend_from_slice(&attribute.to_ne_bytes());
+            expected.extend_from_slice(value);
+        }
+
+        assert_eq!(message.finish(9), expected);
+    }
+
+    #[test]
+    fn acks() {
+        let mut message = Message::new(0x0609, NLM_F_ACK, 2, 0);
+        message.put_u32(9, 5);
+        let request = message.finish(3);
+        let attributes = request[NETLINK_HEADER_SIZE + NFNETLINK_HEADER_SIZE..].to_vec();
+        assert_eq!(find_attribute(&attributes, 9), Some(&5u32.to_ne_bytes()[..]));
+        assert_eq!(find_attribute(&attributes, 1), None);
+
+        let mut replies = reply(&request[..NETLINK_HEADER_SIZE], 0);
+        replies.extend(reply(&request, 17));
+        assert_eq!(
+            parse_acks(&replies, 3, 1).unwrap(),
+            vec![
+                Ack {
+                    index: 0,
+                    code: 0,
+                    attributes: vec![],
+                },
+                Ack {
+                    index: 0,
+                    code: 17,
+                    attributes: attributes.clone(),
+                },
+            ]
+        );
+        assert_eq!(parse_acks(&replies, 1, 2).unwrap(), vec![]);
+        assert_eq!(parse_acks(&replies, 2, 2).unwrap().len(), 2);
+        assert!(parse_acks(&replies[..20], 3, 1).is_err());
+    }
+
+    #[test]
+    fn dump() {
+        let mut message = Message::new(0x0a0d, NLM_F_DUMP, 2, 0);
+        message.put_u32(1, 5);
+        message.put_u32(2, 6);
+        let data = message.finish(4);
+        let attributes = data[NETLINK_HEADER_SIZE + NFNETLINK_HEADER_SIZE..].to_vec();
+        assert_eq!(
+            super::attributes(&attributes),
+            vec![(1, &5u32.to_ne_bytes()[..]), (2, &6u32.to_ne_bytes()[..])]
+        );
+
+        let mut done = reply(&data[..NETLINK_HEADER_SIZE], 0);
+        done[4..6].copy_from_slice(&NLMSG_DONE.to_ne_bytes());
+
+        let mut dump = Dump {
+            code: 0,
+            messages: Vec::new(),
+        };
+        assert!(!parse_dump(&data, 4, &mut dump).unwrap());
+        assert!(!parse_dump(&done, 3, &mut dump).unwrap());
+        assert!(parse_dump(&[&data[..], &data[..], &done[..]].concat(), 4, &mut dump).unwrap());
+        assert_eq!(dump.code, 0);
+        assert_eq!(dump.messages, vec![attributes.clone(), attributes.clone(), attributes]);
+
+        assert!(parse_dump(&reply(&data, 2), 4, &mut dump).unwrap());
+        assert_eq!(dump.code, 2);
+    }
+}
@@ -0,0 +1,652 @@
+//! nftables backend. Talks to kernel over netlink directly, see `include/uapi/linux/netfilter/nf_tables.h`.
+//! Changes are applied in batch transactions, so each of them either succeeds as a whole or fails without effect.
+
+use std;
+
+use failure;
+use ipnet;
+
+use config;
+
+use super::netlink;
+use super::{Backend, EntryError, SetKind};
+
+/// Table used when it is not specified in configuration.
+pub const DEFAULT_TABLE: &str = "addrsetd";
+/// Maximum length of table and set names, without terminating zero.
+pub const MAX_NAME_LENGTH: usize = 255;
+
+/// Number of entries sent to kernel in one message, several messages may form one transaction.
+const BATCH_SIZE: usize = 1024;
+
+const NFNL_SUBSYS_NFTABLES: u16 = 10;
+const NFNL_MSG_BATCH_BEGIN: u16 = 0x10;
+const NFNL_MSG_BATCH_END: u16 = 0x11;
+
+const NFT_MSG_NEWTABLE: u16 = 0;
+const NFT_MSG_NEWSET: u16 = 9;
+const NFT_MSG_GETSET: u16 = 10;
+const NFT_MSG_NEWSETELEM: u16 = 12;
+const NFT_MSG_GETSETELEM: u16 = 13;
+const NFT_MSG_DELSETELEM: u16 = 14;
+
+const NFTA_TABLE_NAME: u16 = 1;
+
+const NFTA_SET_TABLE: u16 = 1;
+const NFTA_SET_NAME: u16 = 2;
+const NFTA_SET_FLAGS: u16 = 3;
+const NFTA_SET_KEY_TYPE: u16 = 4;
+const NFTA_SET_KEY_LEN: u16 = 5;
+const NFTA_SET_ID: u16 = 10;
+
+const NFTA_SET_ELEM_LIST_TABLE: u16 = 1;
+const NFTA_SET_ELEM_LIST_SET: u16 = 2;
+const NFTA_SET_ELEM_LIST_ELEMENTS: u16 = 3;
+const NFTA_LIST_ELEM: u16 = 1;
+const NFTA_SET_ELEM_KEY: u16 = 1;
+const NFTA_SET_ELEM_FLAGS: u16 = 3;
+const NFTA_DATA_VALUE: u16 = 1;
+
+const NFT_SET_INTERVAL: u32 = 0x4;
+const NFT_SET_ELEM_INTERVAL_END: u32 = 0x1;
+/// Data types of IPv4 and IPv6 addresses in terms of nft(8), let it show set contents properly.
+const TYPE_IPADDR: u32 = 7;
+const TYPE_IP6ADDR: u32 = 8;
+
+const ENOENT: i32 = 2;
+const EEXIST: i32 = 17;
+const ERANGE: i32 = 34;
+const ENOTEMPTY: i32 = 39;
+
+fn describe_error(code: i32) -> String {
+    match code {
+        ENOENT => "Table or set does not exist".into(),
+        EEXIST | ENOTEMPTY => "Conflicts with existing element".into(),
+        _ => format!("{}", std::io::Error::from_raw_os_error(code)),
+    }
+}
+
+/// Whether error may be caused by particular entry rather than by whole request.
+fn is_entry_error(message_type: u16, code: i32) -> bool {
+    code == EEXIST || code == ENOTEMPTY || code == ERANGE || (message_type == NFT_MSG_DELSETELEM && code == ENOENT)
+}
+
+fn family_code(family: config::NFTablesFamily) -> u8 {
+    match family {
+        config::NFTablesFamily::Inet => 1,
+        config::NFTablesFamily::IP => 2,
+        config::NFTablesFamily::Netdev => 5,
+        config::NFTablesFamily::Bridge => 7,
+    }
+}
+
+/// Key in network byte order.
+fn put_element(message: &mut netlink::Message, key: &[u8], flags: u32) {
+    message.begin_nested(NFTA_LIST_ELEM);
+    message.begin_nested(NFTA_SET_ELEM_KEY);
+    message.put(NFTA_DATA_VALUE, key);
+    message.end_nested();
+    if flags != 0 {
+        message.put_u32_be(NFTA_SET_ELEM_FLAGS, flags);
+    }
+    message.end_nested();
+}
+
+/// Put elements representing entry: single address for plain sets, bounds of range for interval sets. Range ends
+/// with element following its last address, range which reaches end of address space has no end element.
+fn put_entry(message: &mut netlink::Message, entry: &ipnet::IpNet, interval: bool) {
+    match *entry {
+        ipnet::IpNet::V4(entry) => {
+            put_element(message, &entry.network().octets(), 0);
+            if interval {
+                if let Some(end) = u32::from(entry.broadcast()).checked_add(1) {
+                    put_element(message, &end.to_be_bytes(), NFT_SET_ELEM_INTERVAL_END);
+                }
+            }
+        },
+        ipnet::IpNet::V6(entry) => {
+            put_element(message, &entry.network().octets(), 0);
+            if interval {
+                if let Some(end) = u128::from(entry.broadcast()).checked_add(1) {
+                    put_element(message, &end.to_be_bytes(), NFT_SET_ELEM_INTERVAL_END);
+                }
+            }
+        },
+    }
+}
+
+/// Key of IPv4 or IPv6 element as number.
+fn read_key(value: &[u8]) -> Option<u128> {
+    if value.len() == 4 || value.len() == 16 {
+        Some(value.iter().fold(0, |key, &byte| key << 8 | u128::from(byte)))
+    } else {
+        None
+    }
+}
+
+/// Elements in message with list of elements: keys and whether elements are end elements of ranges.
+fn read_elements(attributes: &[u8]) -> Vec<(u128, bool)> {
+    let elements = match netlink::find_attribute(attributes, NFTA_SET_ELEM_LIST_ELEMENTS) {
+        Some(elements) => elements,
+        None => return Vec::new(),
+    };
+    netlink::attributes(elements)
+        .into_iter()
+        .filter(|&(kind, _)| kind == NFTA_LIST_ELEM)
+        .filter_map(|(_, element)| {
+            let key = netlink::find_attribute(element, NFTA_SET_ELEM_KEY)
+                .and_then(|key| netlink::find_attribute(key, NFTA_DATA_VALUE))
+                .and_then(read_key)?;
+            let flags = netlink::find_attribute(element, NFTA_SET_ELEM_FLAGS)
+                .and_then(netlink::read_u32_be)
+                .unwrap_or(0);
+            Some((key, flags & NFT_SET_ELEM_INTERVAL_END != 0))
+        })
+        .collect()
+}
+
+/// Number of entries in message with list of elements, end elements of ranges are not counted.
+fn count_elements(attributes: &[u8]) -> usize {
+    read_elements(attributes).iter().filter(|&&(_, end)| !end).count()
+}
+
+/// Entries stored by elements, in any order. Ranges are split into networks, as they were built from networks.
+fn to_entries(
+    mut elements: Vec<(u128, bool)>,
+    interval: bool,
+    family: config::AddressFamily,
+) -> Vec<ipnet::IpNet> {
+    let address = |key: u128| -> std::net::IpAddr {
+        match family {
+            config::AddressFamily::IPv4 => std::net::Ipv4Addr::from(key as u32).into(),
+            config::AddressFamily::IPv6 => std::net::Ipv6Addr::from(key).into(),
+        }
+    };
+
+    if !interval {
+        return elements
+            .into_iter()
+            .map(|(key, _)| ipnet::IpNet::from(address(key)))
+            .collect();
+    }
+
+    // End element of range goes before start element of adjacent range.
+    elements.sort_by_key(|&(key, end)| (key, !end));
+    let mut ranges = Vec::new();
+    let mut start = None;
+    for (key, end) in elements {
+        match (start, end) {
+            (None, false) => start = Some(key),
+            (Some(first), true) => {
+                ranges.push((first, key - 1));
+                start = None;
+            },
+            // Unpaired elements are not expected, they are skipped.
+            (Some(_), false) | (None, true) => {},
+        }
+    }
+    // Range which reaches end of address space has no end element.
+    if let Some(first) = start {
+        ranges.push((
+            first,
+            match family {
+                config::AddressFamily::IPv4 => u128::from(u32::MAX),
+                config::AddressFamily::IPv6 => u128::MAX,
+            },
+        ));
+    }
+
+    ranges
+        .into_iter()
+        .flat_map(|(first, last)| match (address(first), address(last)) {
+            (std::net::IpAddr::V4(first), std::net::IpAddr::V4(last)) => ipnet::Ipv4Subnets::new(first, last, 0)
+                .map(ipnet::IpNet::V4)
+                .collect::<Vec<_>>(),
+            (std::net::IpAddr::V6(first), std::net::IpAddr::V6(last)) => ipnet::Ipv6Subnets::new(first, last, 0)
+                .map(ipnet::IpNet::V6)
+                .collect(),
+            _ => unreachable!("Bounds of range have different families"),
+        })
+        .collect()
+}
+
+pub struct NFTables {
+    socket: netlink::Socket,
+    table: String,
+    family: u8,
+    /// Kinds and families of sets created by this backend, interval sets store networks.
+    kinds: std::collections::BTreeMap<String, (SetKind, config::AddressFamily)>,
+}
+
+impl NFTables {
+    pub fn new(table: &str, family: config::NFTablesFamily) -> Self {
+        Self {
+            socket: netlink::Socket::new(),
+            table: table.into(),
+            family: family_code(family),
+            kinds: std::collections::BTreeMap::new(),
+        }
+    }
+
+    /// Messages of transactions are not acknowledged: kernel reports failed ones anyway, and acknowledgements of all
+    /// messages of large transaction could overflow receive buffer of socket.
+    fn message(&self, message_type: u16, flags: u16) -> netlink::Message {
+        netlink::Message::new(
+            NFNL_SUBSYS_NFTABLES << 8 | message_type,
+            flags,
+            self.family,
+            0,
+        )
+    }
+
+    fn elements_message(&self, message_type: u16, name: &str) -> netlink::Message {
+        let mut message = self.message(message_type, netlink::NLM_F_CREATE);
+        message.put_string(NFTA_SET_ELEM_LIST_TABLE, &self.table);
+        message.put_string(NFTA_SET_ELEM_LIST_SET, name);
+        message
+    }
+
+    /// Request for dump of all elements of set. Dump is not acknowledged.
+    fn elements_dump_message(&self, name: &str) -> netlink::Message {
+        let mut message = netlink::Message::new(
+            NFNL_SUBSYS_NFTABLES << 8 | NFT_MSG_GETSETELEM,
+            netlink::NLM_F_DUMP,
+            self.family,
+            0,
+        );
+        message.put_string(NFTA_SET_ELEM_LIST_TABLE, &self.table);
+        message.put_string(NFTA_SET_ELEM_LIST_SET, name);
+        message
+    }
+
+    /// Messages adding or deleting entries.
+    fn elements_messages(
+        &self,
+        message_type: u16,
+        name: &str,
+        entries: &[ipnet::IpNet],
+        interval: bool,
+    ) -> Vec<netlink::Message> {
+        entries
+            .chunks(BATCH_SIZE)
+            .map(|chunk| {
+                let mut message = self.elements_message(message_type, name);
+                message.begin_nested(NFTA_SET_ELEM_LIST_ELEMENTS);
+                for entry in chunk {
+                    put_entry(&mut message, entry, interval);
+                }
+                message.end_nested();
+                message
+            })
+            .collect()
+    }
+
+    /// Messages replacing all entries of set: request without elements removes all of them.
+    fn replace_messages(&self, name: &str, entries: &[ipnet::IpNet], interval: bool) -> Vec<netlink::Message> {
+        let mut messages = vec![self.elements_message(NFT_MSG_DELSETELEM, name)];
+        messages.extend(self.elements_messages(NFT_MSG_NEWSETELEM, name, entries, interval));
+        messages
+    }
+
+    /// Apply messages in one transaction. Returns code of first error, if transaction failed. Without commit
+    /// transaction is only checked: kernel aborts batch which lacks end message.
+    fn transaction(&mut self, messages: Vec<netlink::Message>, commit: bool) -> Result<Option<i32>, failure::Error> {
+        let mut batch = vec![netlink::Message::new(NFNL_MSG_BATCH_BEGIN, 0, 0, NFNL_SUBSYS_NFTABLES)];
+        batch.extend(messages);
+        if commit {
+            batch.push(netlink::Message::new(NFNL_MSG_BATCH_END, 0, 0, NFNL_SUBSYS_NFTABLES));
+        }
+
+        let mut acks = self.socket.request(batch)?;
+        acks.sort_by_key(|ack| ack.index);
+        Ok(acks.iter().find(|ack| ack.code != 0).map(|ack| ack.code))
+    }
+
+    fn set_exists(&mut self, name: &str) -> Result<bool, failure::Error> {
+        let mut message = self.message(NFT_MSG_GETSET, netlink::NLM_F_ACK);
+        message.put_string(NFTA_SET_TABLE, &self.table);
+        message.put_string(NFTA_SET_NAME, name);
+
+        match self.socket.request(vec![message])?.iter().find(|ack| ack.code != 0) {
+            None => Ok(true),
+            Some(ack) if ack.code == ENOENT => Ok(false),
+            Some(ack) => bail!("Unable to get set: {}", describe_error(ack.code)),
+        }
+    }
+
+    /// Whether set created by this backend stores ranges, and its family.
+    fn interval(&self, name: &str) -> Result<(bool, config::AddressFamily), failure::Error> {
+        match self.kinds.get(name) {
+            Some(&(kind, family)) => Ok((kind == SetKind::Networks, family)),
+            None => bail!("Set is not created"),
+        }
+    }
+
+    /// Apply transactions built from entries. Failed transaction has no effect, so entries rejected by kernel are
+    /// found by splitting failed transactions in halves until single entries remain. Kernel refuses to delete missing
+    /// elements, such entries are skipped. Without commit transactions are only checked, and entries which passed
+    /// check are included into following transactions, as they would be present in set after commit.
+    fn bisect<Messages>(
+        &mut self,
+        message_type: u16,
+        name: &str,
+        entries: &[ipnet::IpNet],
+        commit: bool,
+        messages: Messages,
+    ) -> Result<Vec<EntryError>, failure::Error>
+    where
+        Messages: Fn(&Self, &[ipnet::IpNet]) -> Vec<netlink::Message>,
+    {
+        let mut set_checked = false;
+        let mut accepted = Vec::new();
+        let mut errors = Vec::new();
+        let mut pending = vec![entries];
+        while let Some(entries) = pending.pop() {
+            if entries.is_empty() {
+                continue;
+            }
+
+            let messages = if commit {
+                messages(self, entries)
+            } else {
+                messages(self, &[&accepted[..], entries].concat())
+            };
+            match self.transaction(messages, commit)? {
+                None => {
+                    if !commit {
+                        accepted.extend_from_slice(entries);
+                    }
+                },
+                Some(code) if is_entry_error(message_type, code) => {
+                    if code == ENOENT {
+                        // Missing set is reported the same way as missing element.
+                        ensure!(
+                            set_checked || self.set_exists(name)?,
+                            "Unable to update set: {}",
+                            describe_error(code)
+                        );
+                        set_checked = true;
+                    }
+
+                    if entries.len() > 1 {
+                        let (first, second) = entries.split_at(entries.len() / 2);
+                        pending.push(second);
+                        pending.push(first);
+                    } else if code != ENOENT {
+                        errors.push(EntryError {
+                            entry: entries[0],
+                            error: describe_error(code),
+                        });
+                    }
+                },
+                Some(code) => bail!("Unable to update set: {}", describe_error(code)),
+            }
+        }
+
+        Ok(errors)
+    }
+
+    fn update(
+        &mut self,
+        message_type: u16,
+        name: &str,
+        entries: &[ipnet::IpNet],
+    ) -> Result<Vec<EntryError>, failure::Error> {
+        let (interval, _) = self.interval(name)?;
+        self.bisect(message_type, name, entries, true, |nftables, entries| {
+            nftables.elements_messages(message_type, name, entries, interval)
+        })
+    }
+}
+
+impl Backend for NFTables {
+    /// Table is created too, if needed. Sets are not limited in size, so maximum number of entries is not used.
+    fn create(
+        &mut self,
+        name: &str,
+        kind: SetKind,
+        family: config::AddressFamily,
+        _max_entries: u32,
+    ) -> Result<(), failure::Error> {
+        let mut table = self.message(NFT_MSG_NEWTABLE, netlink::NLM_F_CREATE);
+        table.put_string(NFTA_TABLE_NAME, &self.table);
+
+        let mut set = self.message(NFT_MSG_NEWSET, netlink::NLM_F_CREATE);
+        set.put_string(NFTA_SET_TABLE, &self.table);
+        set.put_string(NFTA_SET_NAME, name);
+        set.put_u32_be(
+            NFTA_SET_FLAGS,
+            match kind {
+                SetKind::Addresses => 0,
+                SetKind::Networks => NFT_SET_INTERVAL,
+            },
+        );
+        match family {
+            config::AddressFamily::IPv4 => {
+                set.put_u32_be(NFTA_SET_KEY_TYPE, TYPE_IPADDR);
+                set.put_u32_be(NFTA_SET_KEY_LEN, 4);
+            },
+            config::AddressFamily::IPv6 => {
+                set.put_u32_be(NFTA_SET_KEY_TYPE, TYPE_IP6ADDR);
+                set.put_u32_be(NFTA_SET_KEY_LEN, 16);
+            },
+        }
+        set.put_u32_be(NFTA_SET_ID, 1);
+
+        match self.transaction(vec![table, set], true)? {
+            None => {
+                let _ = self.kinds.insert(name.into(), (kind, family));
+                Ok(())
+            },
+            Some(EEXIST) => bail!("Unable to create set: set with the same name and different type already exists"),
+            Some(code) => bail!("Unable to create set: {}", describe_error(code)),
+        }
+    }
+
+    /// Old entries are removed and new ones are added in one transaction. If kernel rejects some entries, they are
+    /// found by checking parts of transaction without commit, and the rest is applied. Set existing already is not
+    /// necessarily created by this backend, so its kind is checked first.
+    fn replace(
+        &mut self,
+        name: &str,
+        kind: SetKind,
+        family: config::AddressFamily,
+        max_entries: u32,
+        entries: &[ipnet::IpNet],
+    ) -> Result<Vec<EntryError>, failure::Error> {
+        if !self.kinds.contains_key(name) {
+            self.create(name, kind, family, max_entries)?;
+        }
+        let (interval, _) = self.interval(name)?;
+
+        let messages = self.replace_messages(name, entries, interval);
+        match self.transaction(messages, true)? {
+            None => return Ok(Vec::new()),
+            Some(code) if is_entry_error(NFT_MSG_NEWSETELEM, code) => {},
+            Some(code) => bail!("Unable to replace set: {}", describe_error(code)),
+        }
+
+        let errors = self.bisect(NFT_MSG_NEWSETELEM, name, entries, false, |nftables, entries| {
+            nftables.replace_messages(name, entries, interval)
+        })?;
+        let rejected: std::collections::BTreeSet<_> = errors.iter().map(|error| error.entry).collect();
+        let accepted: Vec<_> = entries.iter().filter(|entry| !rejected.contains(entry)).cloned().collect();
+
+        let messages = self.replace_messages(name, &accepted, interval);
+        match self.transaction(messages, true)? {
+            None => Ok(errors),
+            Some(code) => bail!("Unable to replace set: {}", describe_error(code)),
+        }
+    }
+
+    fn add(&mut self, name: &str, entries: &[ipnet::IpNet]) -> Result<Vec<EntryError>, failure::Error> {
+        self.update(NFT_MSG_NEWSETELEM, name, entries)
+    }
+
+    fn delete(&mut self, name: &str, entries: &[ipnet::IpNet]) -> Result<Vec<EntryError>, failure::Error> {
+        self.update(NFT_MSG_DELSETELEM, name, entries)
+    }
+
+    /// Ranges are counted by their starting elements.
+    fn count(&mut self, name: &str) -> Result<Option<usize>, failure::Error> {
+        let dump = self.socket.dump(self.elements_dump_message(name))?;
+        match dump.code {
+            0 => Ok(Some(dump.messages.iter().map(|attributes| count_elements(attributes)).sum())),
+            ENOENT => Ok(None),
+            code => bail!("Unable to list set: {}", describe_error(code)),
+        }
+    }
+
+    /// Set has to be created by this backend first, as elements of plain and interval sets look the same.
+    fn list(&mut self, name: &str) -> Result<Option<std::collections::BTreeSet<ipnet::IpNet>>, failure::Error> {
+        let dump = self.socket.dump(self.elements_dump_message(name))?;
+        match dump.code {
+            0 => {},
+            ENOENT => return Ok(None),
+            code => bail!("Unable to list set: {}", describe_error(code)),
+        }
+
+        let elements = dump.messages.iter().flat_map(|attributes| read_elements(attributes)).collect();
+        let (interval, family) = self.interval(name)?;
+        Ok(Some(to_entries(elements, interval, family).into_iter().collect()))
+    }
+
+    fn requires_disjoint_entries(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn elements(entries: &[&str], interval: bool) -> Vec<(u32, u32)> {
+        let mut message = netlink::Message::new(0, 0, 0, 0);
+        for entry in entries {
+            put_entry(&mut message, &entry.parse().unwrap(), interval);
+        }
+        let data = message.finish(0);
+
+        // Each element: list element (4 bytes), key (4), value (4 + 4) and optional flags (4 + 4).
+        let mut elements = Vec::new();
+        let mut offset = 20;
+        while offset < data.len() {
+            let length = u16::from_ne_bytes([data[offset], data[offset + 1]]) as usize;
+            let key = u32::from_be_bytes([data[offset + 12], data[offset + 13], data[offset + 14], data[offset + 15]]);
+            let flags = if length == 24 {
+                u32::from_be_bytes([data[offset + 20], data[offset + 21], data[offset + 22], data[offset + 23]])
+            } else {
+                0
+            };
+            elements.push((key, flags));
+            offset += length;
+        }
+        elements
+    }
+
+    #[test]
+    fn entries() {
+        assert_eq!(
+            elements(&["192.0.2.1/32", "198.51.100.0/24"], true),
+            vec![
+                (0xc000_0201, 0),
+                (0xc000_0202, NFT_SET_ELEM_INTERVAL_END),
+                (0xc633_6400, 0),
+                (0xc633_6500, NFT_SET_ELEM_INTERVAL_END),
+            ]
+        );
+        assert_eq!(
+            elements(&["0.0.0.0/0", "255.255.255.255/32"], true),
+            vec![(0, 0), (0xffff_ffff, 0)]
+        );
+        assert_eq!(elements(&["192.0.2.1/32"], false), vec![(0xc000_0201, 0)]);
+
+        let mut message = netlink::Message::new(0, 0, 0, 0);
+        message.begin_nested(NFTA_SET_ELEM_LIST_ELEMENTS);
+        for entry in &["192.0.2.1/32", "198.51.100.0/24", "255.255.255.0/24"] {
+            put_entry(&mut message, &entry.parse().unwrap(), true);
+        }
+        message.end_nested();
+        assert_eq!(count_elements(&message.finish(0)[20..]), 3);
+    }
+
+    #[test]
+    fn to_entries() {
+        let entries = |entries: &[&str], interval: bool, family| -> Vec<String> {
+            let mut message = netlink::Message::new(0, 0, 0, 0);
+            message.begin_nested(NFTA_SET_ELEM_LIST_ELEMENTS);
+            // Kernel dumps elements of interval sets in reverse order.
+            for entry in entries.iter().rev() {
+                put_entry(&mut message, &entry.parse().unwrap(), interval);
+            }
+            message.end_nested();
+
+            let elements = read_elements(&message.finish(0)[20..]);
+            super::to_entries(elements, interval, family)
+                .iter()
+                .map(|entry| format!("{}", entry))
+                .collect()
+        };
+
+        // Adjacent ranges are kept apart.
+        let ipv4 = config::AddressFamily::IPv4;
+        let list = ["0.0.0.0/8", "1.0.0.0/8", "192.0.2.1/32", "255.255.255.0/24"];
+        assert_eq!(entries(&list, true, ipv4), list);
+        assert_eq!(entries(&["10.0.0.0/7"], true, ipv4), vec!["10.0.0.0/7"]);
+        assert_eq!(entries(&["0.0.0.0/0"], true, ipv4), vec!["0.0.0.0/0"]);
+        assert_eq!(
+            entries(&["192.0.2.1/32", "192.0.2.2/32"], false, ipv4),
+            vec!["192.0.2.2/32", "192.0.2.1/32"]
+        );
+
+        let ipv6 = config::AddressFamily::IPv6;
+        let list = ["2001:db8::/33", "2001:db8:8000::1/128", "ffff::/16"];
+        assert_eq!(entries(&list, true, ipv6), list);
+        assert_eq!(entries(&["::/0"], true, ipv6), vec!["::/0"]);
+        assert_eq!(
+            entries(&["2001:db8::1/128", "2001:db8::2/128"], false, ipv6),
+            vec!["2001:db8::2/128", "2001:db8::1/128"]
+        );
+    }
+
+    #[test]
+    fn transaction() {
+        let table = NFTables::new("filter", config::NFTablesFamily::IP);
+        let message = table.elements_message(NFT_MSG_NEWSETELEM, "blocked");
+        let data = message.finish(1);
+
+        // Type, flags and nfgenmsg.
+        assert_eq!(&data[4..6], &0x0a0cu16.to_ne_bytes()[..]);
+        assert_eq!(&data[6..8], &0x0401u16.to_ne_bytes()[..]);
+        assert_eq!(&data[16..20], &[2, 0, 0, 0][..]);
+        assert_eq!(
+            netlink::find_attribute(&data[20..], NFTA_SET_ELEM_LIST_TABLE),
+            Some(&b"filter\0"[..])
+        );
+        assert_eq!(
+            netlink::find_attribute(&data[20..], NFTA_SET_ELEM_LIST_SET),
+            Some(&b"blocked\0"[..])
+        );
+
+        // Replacement removes all elements first.
+        let entries = ["192.0.2.1/32".parse().unwrap()];
+        let messages: Vec<_> = table
+            .replace_messages("blocked", &entries, false)
+            .into_iter()
+            .map(|message| message.finish(1))
+            .collect();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(&messages[0][4..6], &0x0a0eu16.to_ne_bytes()[..]);
+        assert_eq!(netlink::find_attribute(&messages[0][20..], NFTA_SET_ELEM_LIST_ELEMENTS), None);
+        assert_eq!(&messages[1][4..6], &0x0a0cu16.to_ne_bytes()[..]);
+        assert!(netlink::find_attribute(&messages[1][20..], NFTA_SET_ELEM_LIST_ELEMENTS).is_some());
+
+        let begin = netlink::Message::new(NFNL_MSG_BATCH_BEGIN, 0, 0, NFNL_SUBSYS_NFTABLES).finish(1);
+        assert_eq!(&begin[16..20], &[0, 0, 0, 10][..]);
+
+        assert!(is_entry_error(NFT_MSG_NEWSETELEM, EEXIST));
+        assert!(!is_entry_error(NFT_MSG_NEWSETELEM, ENOENT));
+        assert!(is_entry_error(NFT_MSG_DELSETELEM, ENOENT));
+        assert_eq!(describe_error(ENOENT), "Table or set does not exist");
+    }
+}
@@ -0,0 +1,221 @@
+//! Cache of last successfully parsed lists, so sets may be filled at start even if sources are unreachable. Cache of
+//! source is gzip-compressed file with two header lines followed by list in the same CSV format as fetched one:
+//!
+//! * `addrsetd-cache <version>`: files of other versions are not read.
+//! * JSON object with location of source, validators of fetched list and number of records.
+//!
+//! Corrupt caches are detected by checksum of gzip, by number of records and by errors of parsing, they are ignored.
+
+use std;
+
+use failure;
+use flate2;
+use serde_json;
+use zicsv;
+
+use config;
+use source;
+
+const MAGIC: &str = "addrsetd-cache";
+const VERSION: u32 = 1;
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+struct Header {
+    /// Type and location of source. Cache of source with different location is not used.
+    source: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    /// Number of records in list.
+    records: usize,
+}
+
+/// Type and location of source, e.g. "http https://example.com/dump.csv".
+fn location(config: &config::Source) -> String {
+    match config.source_type {
+        config::SourceType::File => format!("file {}", config.path.clone().unwrap_or_default()),
+        config::SourceType::Directory => format!("directory {}", config.path.clone().unwrap_or_default()),
+        config::SourceType::Http => format!("http {}", config.url.clone().unwrap_or_default()),
+    }
+}
+
+/// Path of cache file of source.
+pub fn path(directory: &str, name: &str) -> std::path::PathBuf {
+    std::path::Path::new(directory).join(format!("{}.cache", name))
+}
+
+fn save_no_context(
+    path: &std::path::Path,
+    config: &config::Source,
+    list: &source::List,
+    validators: &source::Validators,
+) -> Result<(), failure::Error> {
+    use std::io::Write;
+
+    if let Some(directory) = path.parent() {
+        std::fs::create_dir_all(directory)?;
+    }
+
+    // Write into temporary file and rename it, so valid cache is never replaced with partially written one.
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = std::path::PathBuf::from(temporary);
+
+    let mut encoder = flate2::write::GzEncoder::new(std::fs::File::create(&temporary)?, flate2::Compression::default());
+    let header = Header {
+        source: location(config),
+        etag: validators.etag.clone(),
+        last_modified: validators.last_modified.clone(),
+        records: list.records.len(),
+    };
+    writeln!(encoder, "{} {}", MAGIC, VERSION)?;
+    writeln!(encoder, "{}", serde_json::to_string(&header)?)?;
+    {
+        let mut writer = zicsv::Writer::from_writer(&mut encoder, &list.updated)?;
+        for record in &list.records {
+            writer.write_record(record)?;
+        }
+        writer.flush()?;
+    }
+    encoder.finish()?.sync_all()?;
+
+    std::fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+/// Store list with its validators.
+pub fn save(
+    path: &std::path::Path,
+    config: &config::Source,
+    list: &source::List,
+    validators: &source::Validators,
+) -> Result<(), failure::Error> {
+    save_no_context(path, config, list, validators)
+        .map_err(|error| error.context(format!("Cache \"{}\"", path.display())).into())
+}
+
+fn read_line(reader: &mut std::io::BufRead) -> Result<String, failure::Error> {
+    let mut line = String::new();
+    let _ = reader.read_line(&mut line)?;
+    ensure!(line.ends_with('\n'), "Unexpected end of file");
+    let _ = line.pop();
+    Ok(line)
+}
+
+fn load_no_context(
+    path: &std::path::Path,
+    config: &config::Source,
+) -> Result<Option<(source::List, source::Validators)>, failure::Error> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error.into()),
+    };
+    let mut reader = std::io::BufReader::new(flate2::read::GzDecoder::new(file));
+
+    let magic = read_line(&mut reader)?;
+    ensure!(
+        magic == format!("{} {}", MAGIC, VERSION),
+        "Unknown format \"{}\" (expected \"{} {}\")",
+        magic,
+        MAGIC,
+        VERSION
+    );
+
+    let header: Header = serde_json::from_str(&read_line(&mut reader)?)?;
+    let expected_location = location(config);
+    ensure!(
+        header.source == expected_location,
+        "Made for other source \"{}\" (expected \"{}\")",
+        header.source,
+        expected_location
+    );
+
+    let list = source::List::parse(Box::new(zicsv::Reader::from_buf_reader(reader)?))?;
+    ensure!(
+        list.records.len() == header.records,
+        "Number of records {} does not match header ({})",
+        list.records.len(),
+        header.records
+    );
+
+    let validators = source::Validators {
+        etag: header.etag,
+        last_modified: header.last_modified,
+    };
+    Ok(Some((list, validators)))
+}
+
+/// Load list with its validators. Returns `None` if there is no cache.
+pub fn load(
+    path: &std::path::Path,
+    config: &config::Source,
+) -> Result<Option<(source::List, source::Validators)>, failure::Error> {
+    load_no_context(path, config).map_err(|error| error.context(format!("Cache \"{}\"", path.display())).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+
+    use super::*;
+
+    const LIST: &str = "Updated: 2017-12-01 12:00:00 +0000\n\
+                        192.0.2.1 | 198.51.100.0/24;example.com;;Court;1;2017-01-01\n\
+                        192.0.2.3;;http://example.com/path;Court;2;2017-01-02\n";
+
+    fn source_config(path: &str) -> config::Source {
+        let config: config::Config = format!(
+            r#"
+                [sources.local]
+                type = "file"
+                path = "{}"
+
+                [sets.blocked]
+                source = "local"
+                types = ["ipv4"]
+            "#,
+            path
+        ).parse()
+            .unwrap();
+        config.sources["local"].clone()
+    }
+
+    #[test]
+    fn save_load() {
+        let dir = std::env::temp_dir().join(format!("addrsetd-test-cache-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = path(dir.join("cache").to_str().unwrap(), "local");
+        let config = source_config("dump.csv");
+
+        assert!(load(&path, &config).unwrap().is_none());
+
+        let list = source::List::parse(Box::new(zicsv::Reader::from_reader(LIST.as_bytes()).unwrap())).unwrap();
+        let validators = source::Validators {
+            etag: Some("\"v1\"".into()),
+            last_modified: None,
+        };
+        save(&path, &config, &list, &validators).unwrap();
+
+        let (loaded, loaded_validators) = load(&path, &config).unwrap().unwrap();
+        assert_eq!(loaded.updated, list.updated);
+        assert_eq!(format!("{:?}", loaded.records), format!("{:?}", list.records));
+        assert_eq!(loaded_validators, validators);
+
+        assert!(load(&path, &source_config("other.csv")).is_err());
+
+        // Damage compressed data, checksum should not match.
+        let mut data = std::fs::read(&path).unwrap();
+        let middle = data.len() / 2;
+        data[middle] ^= 0xff;
+        std::fs::write(&path, &data).unwrap();
+        assert!(load(&path, &config).is_err());
+
+        save(&path, &config, &list, &validators).unwrap();
+        let data = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &data[..data.len() - 10]).unwrap();
+        assert!(load(&path, &config).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
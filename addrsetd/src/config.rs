@@ -0,0 +1,1421 @@
+use std;
+
+use failure;
+use ipnet;
+use toml;
+use zicsv;
+
+use backend;
+
+/// Type of source of list.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+pub enum SourceType {
+    /// Single local CSV file.
+    #[serde(rename = "file")]
+    File,
+    /// Local directory with list split into several parts.
+    #[serde(rename = "directory")]
+    Directory,
+    /// HTTP(S) URL.
+    #[serde(rename = "http")]
+    Http,
+}
+
+/// Type of target which stores sets.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+pub enum BackendType {
+    /// Linux ipset.
+    #[default]
+    #[serde(rename = "ipset")]
+    IPSet,
+    /// Named sets of nftables.
+    #[serde(rename = "nftables")]
+    NFTables,
+}
+
+/// Family of nftables table, only families able to match IPv4 addresses are supported.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+pub enum NFTablesFamily {
+    #[serde(rename = "ip")]
+    IP,
+    #[default]
+    #[serde(rename = "inet")]
+    Inet,
+    #[serde(rename = "bridge")]
+    Bridge,
+    #[serde(rename = "netdev")]
+    Netdev,
+}
+
+/// Version of IP of addresses stored in set.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Ord, PartialEq, PartialOrd)]
+pub enum AddressFamily {
+    #[default]
+    #[serde(rename = "ipv4")]
+    IPv4,
+    #[serde(rename = "ipv6")]
+    IPv6,
+}
+
+fn default_http_timeout() -> u64 {
+    300
+}
+
+fn default_interval() -> u64 {
+    3600
+}
+
+fn default_jitter() -> u64 {
+    300
+}
+
+fn default_retry_interval() -> u64 {
+    60
+}
+
+fn default_max_retry_interval() -> u64 {
+    3600
+}
+
+fn default_max_entries() -> u32 {
+    1_048_576
+}
+
+fn default_max_deltas() -> u32 {
+    24
+}
+
+fn default_control_mode() -> String {
+    "0600".into()
+}
+
+fn default_resolver_timeout() -> u64 {
+    5
+}
+
+fn default_resolver_attempts() -> u32 {
+    2
+}
+
+fn default_resolver_concurrency() -> usize {
+    16
+}
+
+fn default_resolver_grace() -> u64 {
+    300
+}
+
+fn default_resolver_min_ttl() -> u64 {
+    60
+}
+
+fn default_resolver_max_ttl() -> u64 {
+    86400
+}
+
+fn default_resolver_retry_interval() -> u64 {
+    60
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Source {
+    #[serde(rename = "type")]
+    pub source_type: SourceType,
+    /// Path to file or directory, for "file" and "directory" sources.
+    #[serde(default)]
+    pub path: Option<String>,
+    /// URL, for "http" sources.
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Timeout for whole HTTP request, in seconds.
+    #[serde(default = "default_http_timeout")]
+    pub timeout: u64,
+    /// Maximum size of HTTP response body, in bytes. Applies to decompressed body too.
+    #[serde(default)]
+    pub max_size: Option<u64>,
+
+    /// Interval between refreshes, in seconds.
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+    /// Maximum random delay added to each interval, in seconds.
+    #[serde(default = "default_jitter")]
+    pub jitter: u64,
+    /// Delay before first retry after failure, in seconds. Doubled after each consecutive failure.
+    #[serde(default = "default_retry_interval")]
+    pub retry_interval: u64,
+    /// Maximum delay before retry, in seconds.
+    #[serde(default = "default_max_retry_interval")]
+    pub max_retry_interval: u64,
+
+    /// Checks of fetched lists.
+    #[serde(default)]
+    pub guards: Guards,
+}
+
+/// Checks of fetched list, see `guards` module. List which fails any of them is rejected, previous one is kept.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Guards {
+    /// Minimum number of records, not entries in sets: one record may have several addresses.
+    #[serde(default)]
+    pub min_records: usize,
+    /// Minimum number of entries, i.e. addresses in all records.
+    #[serde(default)]
+    pub min_entries: usize,
+    /// Maximum decrease of number of records since previous list, in percent.
+    #[serde(default)]
+    pub max_shrink: Option<u32>,
+    /// Maximum increase of number of records since previous list, in percent.
+    #[serde(default)]
+    pub max_growth: Option<u32>,
+    /// Minimum prefix length of IPv4 networks.
+    #[serde(default)]
+    pub min_prefix_len: u8,
+    /// Reject list with "Updated" timestamp older than one of previous list.
+    #[serde(default)]
+    pub reject_older: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Set {
+    /// Name of source of records.
+    pub source: String,
+    /// Types of addresses to put into set. Domain names and hosts of URLs are resolved, see `resolver` module, such
+    /// types can not be mixed with network addresses in one set.
+    pub types: std::collections::BTreeSet<zicsv::AddressType>,
+    /// Version of IP of addresses in set, "ipv4" by default. IPv6 is supported only by sets of resolved names.
+    #[serde(default)]
+    pub address_family: AddressFamily,
+    /// Optional filter expression over records and addresses, see `zicsv::Filter`.
+    #[serde(default)]
+    pub filter: Option<String>,
+    /// Target which stores set.
+    #[serde(default)]
+    pub backend: BackendType,
+    /// Maximum number of entries in set. Update which does not fit is not applied.
+    #[serde(default = "default_max_entries")]
+    pub max_entries: u32,
+    /// Number of updates which apply only changes since previous update, before contents of set are replaced as a
+    /// whole again. Zero disables such updates.
+    #[serde(default = "default_max_deltas")]
+    pub max_deltas: u32,
+    /// Table containing set, for "nftables" backend. Created if needed, "addrsetd" by default.
+    #[serde(default)]
+    pub table: Option<String>,
+    /// Family of table, for "nftables" backend. "inet" by default.
+    #[serde(default)]
+    pub family: Option<NFTablesFamily>,
+}
+
+/// Resolution of domain names for sets of resolved names, see `resolver` module.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Resolver {
+    /// DNS servers, "address" or "address:port" ("[address]:port" for IPv6). Tried in order until one answers.
+    pub servers: Vec<String>,
+    /// Timeout of single query, in seconds.
+    #[serde(default = "default_resolver_timeout")]
+    pub timeout: u64,
+    /// Number of queries sent to each server before next one is tried.
+    #[serde(default = "default_resolver_attempts")]
+    pub attempts: u32,
+    /// Maximum number of names resolved at once.
+    #[serde(default = "default_resolver_concurrency")]
+    pub concurrency: usize,
+    /// Time addresses stay in sets after their TTL passes, in seconds. Covers delayed or failed resolutions.
+    #[serde(default = "default_resolver_grace")]
+    pub grace: u64,
+    /// Limits of TTL, in seconds. Names are resolved again once TTL passes.
+    #[serde(default = "default_resolver_min_ttl")]
+    pub min_ttl: u64,
+    #[serde(default = "default_resolver_max_ttl")]
+    pub max_ttl: u64,
+    /// Delay before next resolution of name after failure, in seconds.
+    #[serde(default = "default_resolver_retry_interval")]
+    pub retry_interval: u64,
+}
+
+/// DNS forwarder which fills sets of resolved names with answers to queries of their names, see `forwarder` module.
+/// Settings are not reloaded.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Forwarder {
+    /// Address and port to listen on, for both UDP and TCP, e.g. "127.0.0.1:53".
+    pub listen: std::net::SocketAddr,
+    /// Upstream DNS servers, as servers of resolver.
+    pub servers: Vec<String>,
+    /// Timeout of query to upstream server, in seconds.
+    #[serde(default = "default_resolver_timeout")]
+    pub timeout: u64,
+    /// Maximum number of queries forwarded at once.
+    #[serde(default = "default_resolver_concurrency")]
+    pub concurrency: usize,
+    /// Time addresses stay in sets after their TTL passes, in seconds.
+    #[serde(default = "default_resolver_grace")]
+    pub grace: u64,
+    /// Limits of TTL of addresses, in seconds.
+    #[serde(default = "default_resolver_min_ttl")]
+    pub min_ttl: u64,
+    #[serde(default = "default_resolver_max_ttl")]
+    pub max_ttl: u64,
+    /// Answer queries of names listed for sets with NXDOMAIN instead of forwarding them.
+    #[serde(default)]
+    pub sinkhole: bool,
+}
+
+/// Unix socket for management of running daemon, see `control` module for protocol.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Control {
+    /// Path to socket. Existing file is replaced.
+    pub socket: String,
+    /// Permissions of socket file, octal.
+    #[serde(default = "default_control_mode")]
+    pub mode: String,
+    /// IDs of users allowed to connect, in addition to root and user of daemon.
+    #[serde(default)]
+    pub users: Vec<u32>,
+    /// IDs of groups allowed to connect: primary group of connecting process, or any group its user is member of
+    /// according to group database.
+    #[serde(default)]
+    pub groups: Vec<u32>,
+}
+
+/// HTTP API, see `api` module for requests.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Api {
+    /// Address and port to listen on, e.g. "127.0.0.1:8080".
+    pub listen: std::net::SocketAddr,
+}
+
+/// Metrics in Prometheus text format, see `metrics` module.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Metrics {
+    /// Address and port to listen on, e.g. "127.0.0.1:9100". Metrics are served at "/metrics".
+    pub listen: std::net::SocketAddr,
+}
+
+/// Cache of last successfully parsed lists, see `cache` module.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Cache {
+    /// Directory for cache files, one per source. Created if needed.
+    pub directory: String,
+}
+
+/// Addresses never added to sets, see `protected` module.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Protected {
+    /// Networks or single addresses, e.g. "192.0.2.0/24", "192.0.2.1" or "2001:db8::/32".
+    #[serde(default)]
+    pub networks: Vec<String>,
+    /// Domain names, subdomains are protected too.
+    #[serde(default)]
+    pub domains: Vec<String>,
+}
+
+/// Entry of local allowlist or denylist, see `local` module.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct LocalEntry {
+    /// IPv4 address or network, domain name or wildcard domain, e.g. "192.0.2.1", "192.0.2.0/24", "example.com" or
+    /// "*.example.com". Allowlist takes networks, single addresses and domain names, as protected addresses.
+    pub address: String,
+    /// Reason of entry, e.g. number of document.
+    pub comment: String,
+    /// Last day when entry is in effect, "YYYY-MM-DD" in UTC. Entry does not expire if omitted.
+    #[serde(default)]
+    pub expires: Option<String>,
+    /// File entry is loaded from, `None` for entries from configuration file.
+    #[serde(skip)]
+    pub file: Option<String>,
+}
+
+/// Local entries merged with lists of sources, see `local` module.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Local {
+    /// Files with more entries: TOML with `allow` and `deny` arrays, as in this section. Read with configuration.
+    #[serde(default)]
+    pub files: Vec<String>,
+    #[serde(default)]
+    pub allow: Vec<LocalEntry>,
+    #[serde(default)]
+    pub deny: Vec<LocalEntry>,
+}
+
+/// Separate file with local entries.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LocalFile {
+    #[serde(default)]
+    allow: Vec<LocalEntry>,
+    #[serde(default)]
+    deny: Vec<LocalEntry>,
+}
+
+/// Integration with systemd, see `systemd` module. Notifications and socket activation are enabled by environment
+/// set by service manager, these settings only tune them.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Systemd {
+    /// Report readiness once daemon is started, instead of after first update of all sets.
+    #[serde(default)]
+    pub ready_on_start: bool,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub sources: std::collections::BTreeMap<String, Source>,
+    #[serde(default)]
+    pub sets: std::collections::BTreeMap<String, Set>,
+    #[serde(default)]
+    pub control: Option<Control>,
+    #[serde(default)]
+    pub api: Option<Api>,
+    #[serde(default)]
+    pub metrics: Option<Metrics>,
+    #[serde(default)]
+    pub systemd: Systemd,
+    #[serde(default)]
+    pub cache: Option<Cache>,
+    #[serde(default)]
+    pub protected: Protected,
+    #[serde(default)]
+    pub resolver: Option<Resolver>,
+    #[serde(default)]
+    pub forwarder: Option<Forwarder>,
+    #[serde(default)]
+    pub local: Local,
+}
+
+/// Types of addresses which may be stored in sets of network addresses.
+const SET_ADDRESS_TYPES: &[zicsv::AddressType] = &[zicsv::AddressType::IPv4, zicsv::AddressType::IPv4Network];
+/// Types of addresses with names which are resolved into addresses stored in sets.
+const RESOLVED_ADDRESS_TYPES: &[zicsv::AddressType] = &[
+    zicsv::AddressType::DomainName,
+    zicsv::AddressType::WildcardDomainName,
+    zicsv::AddressType::URL,
+];
+
+fn validate_name(name: &str) -> Result<(), failure::Error> {
+    ensure!(!name.is_empty(), "Empty name");
+    ensure!(
+        name.chars()
+            .all(|chr| chr.is_ascii_alphanumeric() || chr == '_' || chr == '-' || chr == '.'),
+        "Invalid name \"{}\" (only ASCII letters, digits, '_', '-' and '.' are allowed)",
+        name
+    );
+    Ok(())
+}
+
+impl Source {
+    fn validate(&self) -> Result<(), failure::Error> {
+        match self.source_type {
+            SourceType::File | SourceType::Directory => {
+                ensure!(self.url.is_none(), "URL is not supported by this type of source");
+                let path = self.path
+                    .as_ref()
+                    .ok_or_else(|| format_err!("No path specified"))?;
+                ensure!(!path.is_empty(), "Empty path");
+            },
+            SourceType::Http => {
+                ensure!(self.path.is_none(), "Path is not supported by this type of source");
+                let url = self.url
+                    .as_ref()
+                    .ok_or_else(|| format_err!("No URL specified"))?;
+                ensure!(
+                    url.starts_with("http://") || url.starts_with("https://"),
+                    "Unsupported URL \"{}\" (should start with \"http://\" or \"https://\")",
+                    url
+                );
+                ensure!(self.timeout > 0, "Timeout should be greater than zero");
+            },
+        }
+
+        ensure!(self.interval > 0, "Interval should be greater than zero");
+        ensure!(self.retry_interval > 0, "Retry interval should be greater than zero");
+        ensure!(
+            self.max_retry_interval >= self.retry_interval,
+            "Maximum retry interval should not be less than retry interval"
+        );
+
+        if let Some(max_shrink) = self.guards.max_shrink {
+            ensure!(max_shrink <= 100, "Maximum shrink should not be greater than 100%");
+        }
+        ensure!(
+            self.guards.min_prefix_len <= 32,
+            "Minimum prefix length should not be greater than 32"
+        );
+
+        Ok(())
+    }
+}
+
+impl Set {
+    fn validate(
+        &self,
+        name: &str,
+        sources: &std::collections::BTreeMap<String, Source>,
+        resolver: Option<&Resolver>,
+        forwarder: Option<&Forwarder>,
+    ) -> Result<(), failure::Error> {
+        match self.backend {
+            BackendType::IPSet => {
+                ensure!(
+                    name.len() <= backend::ipset::MAX_NAME_LENGTH,
+                    "Name is too long for ipset (maximum is {} characters)",
+                    backend::ipset::MAX_NAME_LENGTH
+                );
+                ensure!(self.table.is_none(), "Table is not supported by this backend");
+                ensure!(self.family.is_none(), "Family is not supported by this backend");
+            },
+            BackendType::NFTables => {
+                ensure!(
+                    name.len() <= backend::nftables::MAX_NAME_LENGTH,
+                    "Name is too long for nftables (maximum is {} characters)",
+                    backend::nftables::MAX_NAME_LENGTH
+                );
+                if let Some(ref table) = self.table {
+                    validate_name(table).map_err(|error| error.context("Table"))?;
+                    ensure!(
+                        table.len() <= backend::nftables::MAX_NAME_LENGTH,
+                        "Table name is too long (maximum is {} characters)",
+                        backend::nftables::MAX_NAME_LENGTH
+                    );
+                }
+            },
+        }
+
+        ensure!(
+            sources.contains_key(&self.source),
+            "Unknown source \"{}\"",
+            self.source
+        );
+
+        ensure!(!self.types.is_empty(), "No address types selected");
+        for address_type in &self.types {
+            ensure!(
+                SET_ADDRESS_TYPES.contains(address_type) || RESOLVED_ADDRESS_TYPES.contains(address_type),
+                "Address type \"{}\" can not be stored in set of network addresses",
+                address_type
+            );
+        }
+        if self.resolves_names() {
+            ensure!(
+                self.types.iter().all(|address_type| RESOLVED_ADDRESS_TYPES.contains(address_type)),
+                "Names to resolve can not be mixed with network addresses in one set"
+            );
+            if self.types.contains(&zicsv::AddressType::WildcardDomainName) {
+                ensure!(forwarder.is_some(), "Wildcard domains can not be matched without forwarder");
+            }
+            ensure!(
+                resolver.is_some() || forwarder.is_some(),
+                "Names can not be resolved without resolver or forwarder"
+            );
+        } else {
+            ensure!(
+                self.address_family == AddressFamily::IPv4,
+                "IPv6 is supported only by sets of resolved names"
+            );
+        }
+
+        ensure!(self.max_entries > 0, "Maximum number of entries should be greater than zero");
+
+        let _ = self.compile_filter()?;
+        Ok(())
+    }
+
+    /// Whether set is filled with addresses of resolved names instead of addresses from list.
+    pub fn resolves_names(&self) -> bool {
+        self.types
+            .iter()
+            .any(|address_type| RESOLVED_ADDRESS_TYPES.contains(address_type))
+    }
+
+    /// Parse filter expression, if any.
+    pub fn compile_filter(&self) -> Result<Option<zicsv::Filter>, failure::Error> {
+        match self.filter {
+            Some(ref expression) => zicsv::Filter::parse(expression)
+                .map(Some)
+                .map_err(|error| error.context(format!("Filter \"{}\"", expression)).into()),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Control {
+    fn validate(&self) -> Result<(), failure::Error> {
+        ensure!(!self.socket.is_empty(), "Empty path to socket");
+        let _ = self.permissions()?;
+        Ok(())
+    }
+
+    /// Permissions of socket file, parsed.
+    pub fn permissions(&self) -> Result<u32, failure::Error> {
+        match u32::from_str_radix(&self.mode, 8) {
+            Ok(mode) if mode <= 0o777 => Ok(mode),
+            _ => bail!("Invalid mode \"{}\" (should be octal number, e.g. \"0660\")", self.mode),
+        }
+    }
+}
+
+impl Resolver {
+    fn validate(&self) -> Result<(), failure::Error> {
+        ensure!(!self.servers.is_empty(), "No servers specified");
+        let _ = self.parse_servers()?;
+        ensure!(self.timeout > 0, "Timeout should be greater than zero");
+        ensure!(self.attempts > 0, "Number of attempts should be greater than zero");
+        ensure!(self.concurrency > 0, "Concurrency should be greater than zero");
+        ensure!(self.min_ttl > 0, "Minimum TTL should be greater than zero");
+        ensure!(self.max_ttl >= self.min_ttl, "Maximum TTL should not be less than minimum TTL");
+        ensure!(self.retry_interval > 0, "Retry interval should be greater than zero");
+        Ok(())
+    }
+
+    /// Servers, parsed.
+    pub fn parse_servers(&self) -> Result<Vec<std::net::SocketAddr>, failure::Error> {
+        parse_servers(&self.servers)
+    }
+}
+
+impl Forwarder {
+    fn validate(&self) -> Result<(), failure::Error> {
+        ensure!(!self.servers.is_empty(), "No servers specified");
+        let _ = self.parse_servers()?;
+        ensure!(self.timeout > 0, "Timeout should be greater than zero");
+        ensure!(self.concurrency > 0, "Concurrency should be greater than zero");
+        ensure!(self.min_ttl > 0, "Minimum TTL should be greater than zero");
+        ensure!(self.max_ttl >= self.min_ttl, "Maximum TTL should not be less than minimum TTL");
+        Ok(())
+    }
+
+    /// Upstream servers, parsed.
+    pub fn parse_servers(&self) -> Result<Vec<std::net::SocketAddr>, failure::Error> {
+        parse_servers(&self.servers)
+    }
+}
+
+/// DNS servers, "address" or "address:port". Port 53 is used if it is not specified.
+fn parse_servers(servers: &[String]) -> Result<Vec<std::net::SocketAddr>, failure::Error> {
+    servers
+        .iter()
+        .map(|server| {
+            server
+                .parse::<std::net::SocketAddr>()
+                .or_else(|_| {
+                    server
+                        .parse::<std::net::IpAddr>()
+                        .map(|address| std::net::SocketAddr::new(address, 53))
+                })
+                .map_err(|_| format_err!("Invalid server \"{}\"", server))
+        })
+        .collect()
+}
+
+impl Cache {
+    fn validate(&self) -> Result<(), failure::Error> {
+        ensure!(!self.directory.is_empty(), "Empty directory");
+        Ok(())
+    }
+}
+
+impl Protected {
+    fn validate(&self) -> Result<(), failure::Error> {
+        let _ = self.parse_networks()?;
+        for domain in &self.domains {
+            ensure!(
+                !domain.trim_matches('.').is_empty() && !domain.contains('*'),
+                "Invalid domain \"{}\"",
+                domain
+            );
+        }
+        Ok(())
+    }
+
+    /// Networks, parsed. Single addresses are taken as networks with longest prefix, host bits are cleared.
+    pub fn parse_networks(&self) -> Result<Vec<ipnet::IpNet>, failure::Error> {
+        self.networks.iter().map(|network| parse_network(network)).collect()
+    }
+}
+
+/// Network or single address, see `Protected::parse_networks()`.
+pub fn parse_network(network: &str) -> Result<ipnet::IpNet, failure::Error> {
+    network
+        .parse::<ipnet::IpNet>()
+        .map(|network| network.trunc())
+        .or_else(|_| network.parse::<std::net::IpAddr>().map(ipnet::IpNet::from))
+        .map_err(|_| format_err!("Invalid network \"{}\"", network))
+}
+
+/// Domain name of local entry. It should not be mistaken for address, network or URL.
+fn validate_domain(domain: &str) -> Result<(), failure::Error> {
+    ensure!(
+        !domain.trim_matches('.').is_empty() && !domain.contains(|character| "*/: ".contains(character)),
+        "Invalid domain \"{}\"",
+        domain
+    );
+    Ok(())
+}
+
+impl LocalEntry {
+    /// Address of entry of denylist, typed as in lists.
+    pub fn parse_address(&self) -> Result<zicsv::Address, failure::Error> {
+        if let Ok(address) = self.address.parse() {
+            return Ok(zicsv::Address::IPv4(address));
+        }
+        if let Ok(network) = self.address.parse() {
+            return Ok(zicsv::Address::IPv4Network(network));
+        }
+        if self.address.starts_with("*.") {
+            validate_domain(&self.address[2..])?;
+            return Ok(zicsv::Address::WildcardDomainName(self.address.clone()));
+        }
+        validate_domain(&self.address)?;
+        Ok(zicsv::Address::DomainName(self.address.clone()))
+    }
+
+    /// Expiry date, parsed.
+    pub fn parse_expires(&self) -> Result<Option<zicsv::Date>, failure::Error> {
+        match self.expires {
+            Some(ref expires) => expires
+                .parse()
+                .map(Some)
+                .map_err(|_| format_err!("Invalid expiry date \"{}\"", expires)),
+            None => Ok(None),
+        }
+    }
+
+    /// Whether entry is in effect at given date.
+    pub fn is_active(&self, today: zicsv::Date) -> bool {
+        self.parse_expires()
+            .ok()
+            .and_then(|expires| expires)
+            .is_none_or(|expires| today <= expires)
+    }
+}
+
+impl Local {
+    fn validate(&self) -> Result<(), failure::Error> {
+        for entry in &self.allow {
+            let _ = parse_network(&entry.address)
+                .map(|_| ())
+                .or_else(|_| validate_domain(&entry.address))
+                .and_then(|_| entry.parse_expires())
+                .map_err(|error| error.context(format!("Allowlist entry \"{}\"", entry.address)))?;
+        }
+        for entry in &self.deny {
+            let _ = entry
+                .parse_address()
+                .and_then(|_| entry.parse_expires())
+                .map_err(|error| error.context(format!("Denylist entry \"{}\"", entry.address)))?;
+        }
+        Ok(())
+    }
+
+    fn load_file_no_context(&mut self, path: &str) -> Result<(), failure::Error> {
+        use std::io::Read;
+
+        let mut text = String::new();
+        let _ = std::fs::File::open(path)?.read_to_string(&mut text)?;
+        let file: LocalFile = toml::from_str(&text)?;
+        let mut local = Local {
+            files: Vec::new(),
+            allow: file.allow,
+            deny: file.deny,
+        };
+        local.validate()?;
+
+        for entry in local.allow.iter_mut().chain(local.deny.iter_mut()) {
+            entry.file = Some(path.into());
+        }
+        self.allow.extend(local.allow);
+        self.deny.extend(local.deny);
+        Ok(())
+    }
+
+    /// Read entries from files, in addition to ones from configuration file.
+    fn load_files(&mut self) -> Result<(), failure::Error> {
+        for path in self.files.clone() {
+            self.load_file_no_context(&path)
+                .map_err(|error| error.context(format!("Local entries file \"{}\"", path)))?;
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    /// Load and validate configuration from TOML file.
+    pub fn load(path: &str) -> Result<Self, failure::Error> {
+        use std::io::Read;
+
+        let mut text = String::new();
+        let _ = std::fs::File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut text))
+            .map_err(|error| failure::Error::from(error).context(format!("Configuration file \"{}\"", path)))?;
+
+        let mut config: Self = text.parse()
+            .map_err(|error: failure::Error| error.context(format!("Configuration file \"{}\"", path)))?;
+        config
+            .local
+            .load_files()
+            .map_err(|error| error.context(format!("Configuration file \"{}\"", path)))?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), failure::Error> {
+        ensure!(!self.sets.is_empty(), "No sets defined");
+
+        for (name, source) in &self.sources {
+            validate_name(name)
+                .and_then(|_| source.validate())
+                .map_err(|error| error.context(format!("Source \"{}\"", name)))?;
+        }
+
+        for (name, set) in &self.sets {
+            validate_name(name)
+                .and_then(|_| set.validate(name, &self.sources, self.resolver.as_ref(), self.forwarder.as_ref()))
+                .map_err(|error| error.context(format!("Set \"{}\"", name)))?;
+        }
+
+        if let Some(ref control) = self.control {
+            control.validate().map_err(|error| error.context("Control socket"))?;
+        }
+        if let Some(ref cache) = self.cache {
+            cache.validate().map_err(|error| error.context("Cache"))?;
+        }
+        self.protected.validate().map_err(|error| error.context("Protected addresses"))?;
+        if let Some(ref resolver) = self.resolver {
+            resolver.validate().map_err(|error| error.context("Resolver"))?;
+        }
+        if let Some(ref forwarder) = self.forwarder {
+            forwarder.validate().map_err(|error| error.context("Forwarder"))?;
+        }
+        self.local.validate().map_err(|error| error.context("Local entries"))?;
+
+        for name in self.sources.keys() {
+            if !self.sets.values().any(|set| &set.source == name) {
+                warn!("Source \"{}\" is not used by any set", name);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Config {
+    type Err = failure::Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let config: Self = toml::from_str(text)?;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use zicsv;
+
+    use super::*;
+
+    /// Valid configuration with single source and set. Tests of optional sections append them to it.
+    const MINIMAL: &str = r#"
+        [sources.local]
+        type = "file"
+        path = "dump.csv"
+
+        [sets.blocked]
+        source = "local"
+        types = ["ipv4"]
+    "#;
+
+    fn with_minimal(text: &str) -> Config {
+        format!("{}{}", MINIMAL, text).parse().unwrap()
+    }
+
+    fn error_chain(text: &str) -> Vec<String> {
+        text.parse::<Config>()
+            .unwrap_err()
+            .causes()
+            .map(|cause| format!("{}", cause))
+            .collect()
+    }
+
+    fn error_chain_with_minimal(text: &str) -> Vec<String> {
+        error_chain(&format!("{}{}", MINIMAL, text))
+    }
+
+    #[test]
+    fn sources() {
+        let config: Config = r#"
+            [sources.zapret-info]
+            type = "http"
+            url = "https://example.com/dump.csv"
+
+            [sources.local]
+            type = "file"
+            path = "/var/lib/addrsetd/dump.csv"
+            interval = 60
+            jitter = 0
+
+            [sets.blocked]
+            source = "zapret-info"
+            types = ["ipv4"]
+
+            [sets.blocked_locally]
+            source = "local"
+            types = ["ipv4"]
+        "#.parse()
+            .unwrap();
+
+        assert_eq!(config.sources.len(), 2);
+        assert_eq!(config.sources["zapret-info"].source_type, SourceType::Http);
+        assert_eq!(
+            config.sources["zapret-info"].url,
+            Some("https://example.com/dump.csv".into())
+        );
+        assert_eq!(config.sources["zapret-info"].timeout, 300);
+        assert_eq!(config.sources["zapret-info"].interval, 3600);
+        assert_eq!(config.sources["local"].interval, 60);
+        assert_eq!(config.sources["local"].jitter, 0);
+
+        assert_eq!(
+            error_chain(
+                r#"
+                    [sources."bad name"]
+                    type = "http"
+                    url = "https://example.com/dump.csv"
+
+                    [sets.blocked]
+                    source = "bad name"
+                    types = ["ipv4"]
+                "#
+            ),
+            vec![
+                "Source \"bad name\"",
+                "Invalid name \"bad name\" (only ASCII letters, digits, '_', '-' and '.' are allowed)",
+            ]
+        );
+
+        assert_eq!(
+            error_chain(
+                r#"
+                    [sources.remote]
+                    type = "http"
+                    url = "ftp://example.com/dump.csv"
+
+                    [sets.blocked]
+                    source = "remote"
+                    types = ["ipv4"]
+                "#
+            ),
+            vec![
+                "Source \"remote\"",
+                "Unsupported URL \"ftp://example.com/dump.csv\" (should start with \"http://\" or \"https://\")",
+            ]
+        );
+
+        assert_eq!(
+            error_chain(
+                r#"
+                    [sources.local]
+                    type = "file"
+
+                    [sets.blocked]
+                    source = "local"
+                    types = ["ipv4"]
+                "#
+            ),
+            vec!["Source \"local\"", "No path specified"]
+        );
+
+        assert!(
+            r#"
+                [sources.local]
+                type = "file"
+                pth = "dump.csv"
+
+                [sets.blocked]
+                source = "local"
+                types = ["ipv4"]
+            "#.parse::<Config>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn guards() {
+        let config: Config = r#"
+            [sources.local]
+            type = "file"
+            path = "dump.csv"
+
+            [sources.local.guards]
+            min_records = 1000
+            min_entries = 2000
+            max_shrink = 10
+            min_prefix_len = 8
+
+            [sets.blocked]
+            source = "local"
+            types = ["ipv4"]
+        "#.parse()
+            .unwrap();
+
+        assert_eq!(config.sources["local"].guards.min_records, 1000);
+        assert_eq!(config.sources["local"].guards.min_entries, 2000);
+        assert_eq!(config.sources["local"].guards.max_shrink, Some(10));
+        assert_eq!(config.sources["local"].guards.max_growth, None);
+        assert_eq!(config.sources["local"].guards.min_prefix_len, 8);
+        assert!(!config.sources["local"].guards.reject_older);
+
+        assert_eq!(
+            error_chain(
+                r#"
+                    [sources.local]
+                    type = "file"
+                    path = "dump.csv"
+
+                    [sources.local.guards]
+                    max_shrink = 150
+
+                    [sets.blocked]
+                    source = "local"
+                    types = ["ipv4"]
+                "#
+            ),
+            vec![
+                "Source \"local\"",
+                "Maximum shrink should not be greater than 100%",
+            ]
+        );
+    }
+
+    #[test]
+    fn sets() {
+        let config = with_minimal(
+            r#"
+                [sets.blocked_ips]
+                source = "local"
+                types = ["ipv4", "ipv4_network"]
+
+                [sets.blocked_by_court]
+                source = "local"
+                types = ["ipv4"]
+                filter = 'org ~ "court"'
+
+                [sets.blocked_networks]
+                source = "local"
+                types = ["ipv4", "ipv4_network"]
+                backend = "nftables"
+                table = "filter"
+                family = "ip"
+            "#,
+        );
+
+        assert_eq!(
+            config.sets["blocked_ips"].types.iter().cloned().collect::<Vec<_>>(),
+            vec![zicsv::AddressType::IPv4, zicsv::AddressType::IPv4Network]
+        );
+        assert!(!config.sets["blocked_ips"].resolves_names());
+        assert_eq!(config.sets["blocked_ips"].address_family, AddressFamily::IPv4);
+        assert_eq!(config.sets["blocked_by_court"].filter, Some("org ~ \"court\"".into()));
+        assert_eq!(config.sets["blocked_by_court"].backend, BackendType::IPSet);
+        assert_eq!(config.sets["blocked_by_court"].max_entries, 1_048_576);
+        assert_eq!(config.sets["blocked_by_court"].max_deltas, 24);
+        assert!(config.sets["blocked_by_court"].compile_filter().unwrap().is_some());
+        assert_eq!(config.sets["blocked_by_court"].table, None);
+        assert_eq!(config.sets["blocked_networks"].backend, BackendType::NFTables);
+        assert_eq!(config.sets["blocked_networks"].table, Some("filter".into()));
+        assert_eq!(config.sets["blocked_networks"].family, Some(NFTablesFamily::IP));
+
+        assert_eq!(error_chain(""), vec!["No sets defined"]);
+
+        assert_eq!(
+            error_chain(
+                r#"
+                    [sets.blocked]
+                    source = "missing"
+                    types = ["ipv4"]
+                "#
+            ),
+            vec!["Set \"blocked\"", "Unknown source \"missing\""]
+        );
+
+        let causes = error_chain(
+            r#"
+                [sources.local]
+                type = "file"
+                path = "dump.csv"
+
+                [sets.blocked]
+                source = "local"
+                types = ["ipv4"]
+                filter = "org ~"
+            "#,
+        );
+        assert_eq!(causes[..2], ["Set \"blocked\"", "Filter \"org ~\""]);
+
+        assert_eq!(
+            error_chain_with_minimal(
+                r#"
+                    [sets.blocked_by_court_and_other_authorities]
+                    source = "local"
+                    types = ["ipv4"]
+                "#
+            ),
+            vec![
+                "Set \"blocked_by_court_and_other_authorities\"",
+                "Name is too long for ipset (maximum is 27 characters)",
+            ]
+        );
+
+        assert_eq!(
+            error_chain_with_minimal(
+                r#"
+                    [sets.filtered]
+                    source = "local"
+                    types = ["ipv4"]
+                    table = "filter"
+                "#
+            ),
+            vec!["Set \"filtered\"", "Table is not supported by this backend"]
+        );
+
+        assert_eq!(
+            error_chain_with_minimal(
+                r#"
+                    [sets.filtered]
+                    source = "local"
+                    types = ["ipv4"]
+                    backend = "nftables"
+                    table = "bad table"
+                "#
+            ),
+            vec![
+                "Set \"filtered\"",
+                "Table",
+                "Invalid name \"bad table\" (only ASCII letters, digits, '_', '-' and '.' are allowed)",
+            ]
+        );
+
+        assert_eq!(
+            error_chain_with_minimal(
+                r#"
+                    [sets.blocked6]
+                    source = "local"
+                    types = ["ipv4"]
+                    address_family = "ipv6"
+                "#
+            ),
+            vec!["Set \"blocked6\"", "IPv6 is supported only by sets of resolved names"]
+        );
+    }
+
+    #[test]
+    fn control() {
+        let control = with_minimal(
+            r#"
+                [control]
+                socket = "/run/addrsetd/control.sock"
+                users = [1000]
+            "#,
+        ).control
+            .unwrap();
+        assert_eq!(control.socket, "/run/addrsetd/control.sock");
+        assert_eq!(control.permissions().unwrap(), 0o600);
+        assert_eq!(control.users, vec![1000]);
+        assert!(control.groups.is_empty());
+
+        assert_eq!(
+            error_chain_with_minimal(
+                r#"
+                    [control]
+                    socket = "control.sock"
+                    mode = "0999"
+                "#
+            ),
+            vec![
+                "Control socket",
+                "Invalid mode \"0999\" (should be octal number, e.g. \"0660\")",
+            ]
+        );
+    }
+
+    #[test]
+    fn api() {
+        let config = with_minimal(
+            r#"
+                [api]
+                listen = "127.0.0.1:8080"
+            "#,
+        );
+        assert_eq!(config.api.unwrap().listen, "127.0.0.1:8080".parse().unwrap());
+
+        assert!(
+            format!(
+                r#"{}
+                    [api]
+                    listen = "localhost"
+                "#,
+                MINIMAL
+            ).parse::<Config>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn metrics() {
+        let config = with_minimal(
+            r#"
+                [metrics]
+                listen = "[::1]:9100"
+            "#,
+        );
+        assert_eq!(config.metrics.unwrap().listen, "[::1]:9100".parse().unwrap());
+    }
+
+    #[test]
+    fn systemd() {
+        assert!(!with_minimal("").systemd.ready_on_start);
+
+        let config = with_minimal(
+            r#"
+                [systemd]
+                ready_on_start = true
+            "#,
+        );
+        assert!(config.systemd.ready_on_start);
+    }
+
+    #[test]
+    fn cache() {
+        let config = with_minimal(
+            r#"
+                [cache]
+                directory = "/var/cache/addrsetd"
+            "#,
+        );
+        assert_eq!(config.cache.unwrap().directory, "/var/cache/addrsetd");
+
+        assert_eq!(
+            error_chain_with_minimal(
+                r#"
+                    [cache]
+                    directory = ""
+                "#
+            ),
+            vec!["Cache", "Empty directory"]
+        );
+    }
+
+    #[test]
+    fn protected() {
+        let config = with_minimal(
+            r#"
+                [protected]
+                networks = ["192.0.2.7/24", "198.51.100.1"]
+                domains = ["example.com"]
+            "#,
+        );
+        assert_eq!(
+            config.protected.parse_networks().unwrap(),
+            vec!["192.0.2.0/24".parse().unwrap(), "198.51.100.1/32".parse().unwrap()]
+        );
+        assert_eq!(config.protected.domains, vec!["example.com"]);
+
+        assert_eq!(
+            error_chain_with_minimal(
+                r#"
+                    [protected]
+                    networks = ["192.0.2.0/33"]
+                "#
+            ),
+            vec!["Protected addresses", "Invalid network \"192.0.2.0/33\""]
+        );
+
+        assert_eq!(
+            error_chain_with_minimal(
+                r#"
+                    [protected]
+                    domains = ["*.example.com"]
+                "#
+            ),
+            vec!["Protected addresses", "Invalid domain \"*.example.com\""]
+        );
+    }
+
+    #[test]
+    fn resolver() {
+        let config = with_minimal(
+            r#"
+                [sets.blocked_names]
+                source = "local"
+                types = ["domain", "url"]
+                address_family = "ipv6"
+
+                [resolver]
+                servers = ["127.0.0.1", "[::1]:5353"]
+                concurrency = 4
+            "#,
+        );
+        assert!(config.sets["blocked_names"].resolves_names());
+        assert_eq!(config.sets["blocked_names"].address_family, AddressFamily::IPv6);
+        let resolver = config.resolver.unwrap();
+        assert_eq!(
+            resolver.parse_servers().unwrap(),
+            vec!["127.0.0.1:53".parse().unwrap(), "[::1]:5353".parse().unwrap()]
+        );
+        assert_eq!(resolver.timeout, 5);
+        assert_eq!(resolver.concurrency, 4);
+        assert_eq!(resolver.grace, 300);
+
+        assert_eq!(
+            error_chain_with_minimal(
+                r#"
+                    [sets.blocked_names]
+                    source = "local"
+                    types = ["domain"]
+                "#
+            ),
+            vec!["Set \"blocked_names\"", "Names can not be resolved without resolver or forwarder"]
+        );
+
+        assert_eq!(
+            error_chain_with_minimal(
+                r#"
+                    [sets.blocked_names]
+                    source = "local"
+                    types = ["ipv4", "url"]
+
+                    [resolver]
+                    servers = ["127.0.0.1"]
+                "#
+            ),
+            vec![
+                "Set \"blocked_names\"",
+                "Names to resolve can not be mixed with network addresses in one set",
+            ]
+        );
+
+        assert_eq!(
+            error_chain_with_minimal(
+                r#"
+                    [sets.blocked_names]
+                    source = "local"
+                    types = ["domain"]
+
+                    [resolver]
+                    servers = ["localhost:53"]
+                "#
+            ),
+            vec!["Resolver", "Invalid server \"localhost:53\""]
+        );
+    }
+
+    #[test]
+    fn forwarder() {
+        let config = with_minimal(
+            r#"
+                [sets.blocked_wildcards]
+                source = "local"
+                types = ["wildcard_domain"]
+
+                [forwarder]
+                listen = "127.0.0.1:53"
+                servers = ["192.0.2.53"]
+                sinkhole = true
+            "#,
+        );
+        assert!(config.sets["blocked_wildcards"].resolves_names());
+        let forwarder = config.forwarder.unwrap();
+        assert_eq!(forwarder.listen, "127.0.0.1:53".parse().unwrap());
+        assert_eq!(forwarder.parse_servers().unwrap(), vec!["192.0.2.53:53".parse().unwrap()]);
+        assert_eq!(forwarder.max_ttl, 86400);
+        assert!(forwarder.sinkhole);
+
+        assert_eq!(
+            error_chain_with_minimal(
+                r#"
+                    [sets.blocked_wildcards]
+                    source = "local"
+                    types = ["wildcard_domain"]
+                "#
+            ),
+            vec!["Set \"blocked_wildcards\"", "Wildcard domains can not be matched without forwarder"]
+        );
+
+        assert_eq!(
+            error_chain_with_minimal(
+                r#"
+                    [sets.blocked_names]
+                    source = "local"
+                    types = ["domain", "wildcard_domain"]
+
+                    [forwarder]
+                    listen = "127.0.0.1:53"
+                    servers = ["192.0.2.53"]
+                    min_ttl = 600
+                    max_ttl = 300
+                "#
+            ),
+            vec!["Forwarder", "Maximum TTL should not be less than minimum TTL"]
+        );
+    }
+
+    #[test]
+    fn local() {
+        let config = with_minimal(
+            r#"
+                [[local.allow]]
+                address = "203.0.113.0/25"
+                comment = "Exempted by request of legal"
+
+                [[local.deny]]
+                address = "*.example.net"
+                comment = "Court order"
+                expires = "2018-12-31"
+            "#,
+        );
+        assert!(config.local.files.is_empty());
+        assert_eq!(config.local.allow[0].address, "203.0.113.0/25");
+        assert_eq!(config.local.allow[0].expires, None);
+        assert_eq!(
+            config.local.deny[0].parse_address().unwrap(),
+            zicsv::Address::WildcardDomainName("*.example.net".into())
+        );
+        assert!(config.local.deny[0].is_active("2018-12-31".parse().unwrap()));
+        assert!(!config.local.deny[0].is_active("2019-01-01".parse().unwrap()));
+
+        assert_eq!(
+            error_chain_with_minimal(
+                r#"
+                    [[local.deny]]
+                    address = "http://example.com/"
+                    comment = "Court order"
+                "#
+            ),
+            vec!["Local entries", "Denylist entry \"http://example.com/\"", "Invalid domain \"http://example.com/\""]
+        );
+
+        assert_eq!(
+            error_chain_with_minimal(
+                r#"
+                    [[local.allow]]
+                    address = "example.com"
+                    comment = "Exempted"
+                    expires = "31.12.2018"
+                "#
+            ),
+            vec!["Local entries", "Allowlist entry \"example.com\"", "Invalid expiry date \"31.12.2018\""]
+        );
+    }
+}
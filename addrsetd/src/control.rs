@@ -0,0 +1,578 @@
+//! Control socket: Unix socket for management of running daemon. Protocol is line-delimited JSON, each request is
+//! a single line with object, field "command" selects command:
+//!
+//! * `{"command": "status"}`: state of sources and sets, and local entries.
+//! * `{"command": "refresh", "source": "name"}`: refresh source immediately, all sources if name is omitted.
+//! * `{"command": "contains", "set": "name", "address": "192.0.2.1"}`: entries of set which contain address.
+//! * `{"command": "dump", "set": "name"}`: all entries of set.
+//!
+//! Reply is a single line too, `{"result": ...}` on success or `{"error": "message"}` on failure. Contents of sets
+//! are reported as applied by daemon, backends are not queried. Requests are executed the same way for HTTP API.
+
+use std;
+
+use failure;
+use futures;
+use ipnet;
+use nix;
+use serde_json;
+use tokio_io;
+use tokio_uds;
+
+use config;
+use daemon;
+use local;
+use scheduler;
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(tag = "command", deny_unknown_fields)]
+pub enum Request {
+    #[serde(rename = "status")]
+    Status {},
+    #[serde(rename = "refresh")]
+    Refresh {
+        #[serde(default)]
+        source: Option<String>,
+    },
+    #[serde(rename = "contains")]
+    Contains {
+        set: String,
+        address: std::net::IpAddr,
+    },
+    #[serde(rename = "dump")]
+    Dump { set: String },
+}
+
+#[derive(Debug, Serialize)]
+enum Reply {
+    #[serde(rename = "result")]
+    Result(serde_json::Value),
+    #[serde(rename = "error")]
+    Error(String),
+}
+
+/// Request refers to source or set which does not exist.
+#[derive(Debug)]
+pub struct NotFound(String);
+
+impl std::fmt::Display for NotFound {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for NotFound {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SourceStatus {
+    /// Timestamp from header of last fetched list.
+    updated: Option<String>,
+    records: Option<usize>,
+    /// Time of last successful refresh, in seconds since Unix epoch.
+    refreshed: Option<u64>,
+    /// Number of consecutive failures.
+    failures: u32,
+    /// Error of last refresh, if it failed.
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SetStatus {
+    source: String,
+    /// Number of entries in set, unknown until first successful update.
+    entries: Option<usize>,
+    /// Number of updates applied as changes since contents were replaced as a whole.
+    deltas: Option<u32>,
+}
+
+/// Entry of local allowlist or denylist.
+#[derive(Debug, Serialize)]
+pub struct LocalStatus {
+    /// "allow" or "deny".
+    list: &'static str,
+    address: String,
+    comment: String,
+    expires: Option<String>,
+    /// File entry is loaded from, `None` for entries from configuration file.
+    file: Option<String>,
+    /// Whether entry is in effect, i.e. not expired.
+    active: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Status {
+    pub sources: std::collections::BTreeMap<String, SourceStatus>,
+    pub sets: std::collections::BTreeMap<String, SetStatus>,
+    /// Local entries, they are merged with lists of sources.
+    pub local: Vec<LocalStatus>,
+}
+
+#[derive(Debug, Serialize)]
+struct Contains {
+    contains: bool,
+    /// Entries which contain address: address itself and networks.
+    entries: Vec<String>,
+}
+
+pub fn status(state: &daemon::State) -> Status {
+    let today = local::today();
+    let local_status = |list, entry: &config::LocalEntry| LocalStatus {
+        list,
+        address: entry.address.clone(),
+        comment: entry.comment.clone(),
+        expires: entry.expires.clone(),
+        file: entry.file.clone(),
+        active: entry.is_active(today),
+    };
+
+    Status {
+        sources: state
+            .sources
+            .iter()
+            .map(|(name, source_state)| {
+                let status = SourceStatus {
+                    updated: source_state.list.as_ref().map(|list| format!("{}", list.updated)),
+                    records: source_state.list.as_ref().map(|list| list.records.len()),
+                    refreshed: source_state
+                        .refreshed
+                        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                        .map(|duration| duration.as_secs()),
+                    failures: source_state.failures,
+                    error: source_state.last_error.clone(),
+                };
+                (name.clone(), status)
+            })
+            .collect(),
+        sets: state
+            .sets
+            .iter()
+            .map(|(name, set_state)| {
+                let status = SetStatus {
+                    source: set_state.config.source.clone(),
+                    entries: set_state.applied.as_ref().map(|applied| applied.entries.len()),
+                    deltas: set_state.applied.as_ref().map(|applied| applied.deltas),
+                };
+                (name.clone(), status)
+            })
+            .collect(),
+        local: state
+            .config
+            .local
+            .allow
+            .iter()
+            .map(|entry| local_status("allow", entry))
+            .chain(state.config.local.deny.iter().map(|entry| local_status("deny", entry)))
+            .collect(),
+    }
+}
+
+fn applied_entries<'a>(
+    state: &'a daemon::State,
+    name: &str,
+) -> Result<&'a std::collections::BTreeSet<ipnet::IpNet>, failure::Error> {
+    let set_state = state
+        .sets
+        .get(name)
+        .ok_or_else(|| NotFound(format!("Unknown set \"{}\"", name)))?;
+    match set_state.applied {
+        Some(ref applied) => Ok(&applied.entries),
+        None => bail!("Set \"{}\" is not updated yet", name),
+    }
+}
+
+/// Entries which contain address. Every prefix length is checked, so search does not depend on size of set.
+fn containing(
+    entries: &std::collections::BTreeSet<ipnet::IpNet>,
+    address: std::net::IpAddr,
+) -> Vec<ipnet::IpNet> {
+    let max_prefix_len = if address.is_ipv4() { 32 } else { 128 };
+    (0..max_prefix_len + 1)
+        .filter_map(|prefix_len| ipnet::IpNet::new(address, prefix_len).ok())
+        .map(|network| network.trunc())
+        .filter(|network| entries.contains(network))
+        .collect()
+}
+
+/// Execute request. Returns result and names of sources to refresh, refresh can be started only after state is
+/// released.
+fn execute(state: &mut daemon::State, request: Request) -> Result<(serde_json::Value, Vec<String>), failure::Error> {
+    let mut to_refresh = Vec::new();
+    let result = match request {
+        Request::Status {} => serde_json::to_value(status(state))?,
+
+        Request::Refresh { source } => {
+            let names = match source {
+                Some(name) => vec![name],
+                None => state.sources.keys().cloned().collect(),
+            };
+            for name in &names {
+                if !state.renew_source(name) {
+                    return Err(NotFound(format!("Unknown source \"{}\"", name)).into());
+                }
+            }
+            to_refresh = names.clone();
+            serde_json::to_value(names)?
+        },
+
+        Request::Contains { set, address } => {
+            let entries: Vec<String> = containing(applied_entries(state, &set)?, address)
+                .iter()
+                .map(|entry| format!("{}", entry))
+                .collect();
+            serde_json::to_value(Contains {
+                contains: !entries.is_empty(),
+                entries,
+            })?
+        },
+
+        Request::Dump { set } => {
+            let entries: Vec<String> = applied_entries(state, &set)?
+                .iter()
+                .map(|entry| format!("{}", entry))
+                .collect();
+            serde_json::to_value(entries)?
+        },
+    };
+    Ok((result, to_refresh))
+}
+
+/// Reply to request, terminated by newline.
+pub fn reply(result: Result<serde_json::Value, failure::Error>) -> String {
+    let reply = match result {
+        Ok(result) => Reply::Result(result),
+        Err(error) => Reply::Error(::error_chain(&error)),
+    };
+    let mut line = serde_json::to_string(&reply).expect("Unable to serialize reply");
+    line.push('\n');
+    line
+}
+
+/// Execute request and start refreshes requested by it.
+pub fn handle(daemon: &daemon::Daemon, request: Request) -> Result<serde_json::Value, failure::Error> {
+    let (result, to_refresh) = execute(&mut daemon.state.borrow_mut(), request)?;
+    scheduler::start(daemon, &to_refresh);
+    Ok(result)
+}
+
+fn handle_line(daemon: &daemon::Daemon, line: &str) -> String {
+    reply(
+        serde_json::from_str(line)
+            .map_err(|error| format_err!("Invalid request: {}", error))
+            .and_then(|request| handle(daemon, request)),
+    )
+}
+
+/// Whether process with given credentials is allowed to use control socket. Groups are primary group of process and
+/// supplementary groups of its user.
+fn is_allowed(config: &config::Control, uid: u32, groups: &[u32]) -> bool {
+    uid == 0
+        || uid == nix::unistd::geteuid().as_raw()
+        || config.users.contains(&uid)
+        || groups.iter().any(|gid| config.groups.contains(gid))
+}
+
+/// Primary group of process, together with supplementary groups of its user from group database. Credentials of
+/// socket do not include supplementary groups of process itself.
+fn groups_of_user(uid: u32, gid: u32) -> Vec<u32> {
+    let mut groups = vec![gid];
+
+    let user = match nix::unistd::User::from_uid(nix::unistd::Uid::from_raw(uid)) {
+        Ok(Some(user)) => user,
+        Ok(None) => return groups,
+        Err(error) => {
+            warn!("Control socket: unable to look up user {}: {}", uid, error);
+            return groups;
+        },
+    };
+    let name = match std::ffi::CString::new(user.name) {
+        Ok(name) => name,
+        Err(_) => return groups,
+    };
+    match nix::unistd::getgrouplist(&name, nix::unistd::Gid::from_raw(gid)) {
+        Ok(supplementary) => groups.extend(supplementary.into_iter().map(|gid| gid.as_raw()).filter(|&id| id != gid)),
+        Err(error) => warn!("Control socket: unable to look up groups of user {}: {}", uid, error),
+    }
+    groups
+}
+
+/// Check credentials of peer and serve its requests one by one.
+fn accept(daemon: &daemon::Daemon, config: &config::Control, stream: tokio_uds::UnixStream) {
+    use self::futures::Future;
+    use self::futures::Stream;
+    use self::tokio_io::AsyncRead;
+
+    let credentials = match stream.peer_cred() {
+        Ok(credentials) => credentials,
+        Err(error) => {
+            warn!("Control socket: unable to get credentials of peer: {}", error);
+            return;
+        },
+    };
+    // Group database is consulted only if it may change the decision.
+    let groups = if config.groups.is_empty() {
+        vec![credentials.gid]
+    } else {
+        groups_of_user(credentials.uid, credentials.gid)
+    };
+    if !is_allowed(config, credentials.uid, &groups) {
+        warn!(
+            "Control socket: connection of user {} (group {}) rejected",
+            credentials.uid, credentials.gid
+        );
+        return;
+    }
+    debug!("Control socket: connection of user {} accepted", credentials.uid);
+
+    let (reader, writer) = stream.split();
+    let daemon_clone = daemon.clone();
+    daemon.handle.spawn(
+        tokio_io::io::lines(std::io::BufReader::new(reader))
+            .fold(writer, move |writer, line| {
+                tokio_io::io::write_all(writer, handle_line(&daemon_clone, &line)).map(|(writer, _)| writer)
+            })
+            .map(|_| debug!("Control socket: connection closed"))
+            .map_err(|error| debug!("Control socket: connection failed: {}", error)),
+    );
+}
+
+/// Start accepting connections on socket passed by service manager or, without it, on newly created socket. Existing
+/// socket file, e.g. left by previous instance, is replaced.
+pub fn start(
+    daemon: &daemon::Daemon,
+    config: &config::Control,
+    listener: Option<std::os::unix::net::UnixListener>,
+) -> Result<(), failure::Error> {
+    use self::futures::Stream;
+
+    let listener = match listener {
+        Some(listener) => listener,
+        None => bind(config)?,
+    };
+    let listener = tokio_uds::UnixListener::from_std(listener, daemon.handle.new_tokio_handle())?;
+    info!("Control socket: listening on \"{}\"", config.socket);
+
+    let daemon_clone = daemon.clone();
+    let config = config.clone();
+    daemon.handle.spawn(
+        listener
+            .incoming()
+            .then(|result| {
+                // Failure to accept one connection should not stop accepting others.
+                if let Err(ref error) = result {
+                    warn!("Control socket: unable to accept connection: {}", error);
+                }
+                Ok(result.ok())
+            })
+            .for_each(move |stream| {
+                if let Some(stream) = stream {
+                    accept(&daemon_clone, &config, stream);
+                }
+                Ok(())
+            }),
+    );
+    Ok(())
+}
+
+fn bind(config: &config::Control) -> Result<std::os::unix::net::UnixListener, failure::Error> {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::fs::PermissionsExt;
+
+    let permissions = config.permissions()?;
+    match std::fs::symlink_metadata(&config.socket) {
+        Ok(metadata) => {
+            ensure!(
+                metadata.file_type().is_socket(),
+                "File \"{}\" exists and is not a socket",
+                config.socket
+            );
+            std::fs::remove_file(&config.socket)?;
+        },
+        Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => {},
+        Err(error) => return Err(error.into()),
+    }
+
+    let listener = std::os::unix::net::UnixListener::bind(&config.socket)?;
+    std::fs::set_permissions(&config.socket, std::fs::Permissions::from_mode(permissions))?;
+    Ok(listener)
+}
+
+/// Remove socket file. Not needed for socket passed by service manager, which owns its file.
+pub fn stop(config: &config::Control) {
+    if let Err(error) = std::fs::remove_file(&config.socket) {
+        warn!("Control socket: unable to remove \"{}\": {}", config.socket, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+
+    use sets;
+    use source;
+    use zicsv;
+
+    use super::*;
+
+    fn state() -> daemon::State {
+        let config: config::Config = r#"
+            [sources.local]
+            type = "file"
+            path = "dump.csv"
+
+            [sets.blocked]
+            source = "local"
+            types = ["ipv4", "ipv4_network"]
+
+            [sets.pending]
+            source = "local"
+            types = ["ipv4"]
+
+            [[local.deny]]
+            address = "203.0.113.1"
+            comment = "Court order"
+            expires = "2017-12-31"
+        "#.parse()
+            .unwrap();
+        let mut state = daemon::State::new(&config);
+
+        let text = "Updated: 2017-12-01 12:00:00 +0000\n192.0.2.1;;;Court;1;2017-01-01\n";
+        let mut reader = zicsv::Reader::from_reader(text.as_bytes()).unwrap();
+        let list = source::List {
+            updated: *zicsv::GenericReader::get_timestamp(&reader),
+            records: reader.records().collect::<Result<_, _>>().unwrap(),
+        };
+        let source_state = state.sources.get_mut("local").unwrap();
+        source_state.list = Some(std::rc::Rc::new(list));
+        source_state.refreshed = Some(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_512_130_000));
+
+        state.sets.get_mut("blocked").unwrap().applied = Some(sets::Applied {
+            entries: ["192.0.2.1/32", "198.51.100.0/24", "198.51.100.128/25"]
+                .iter()
+                .map(|entry| entry.parse().unwrap())
+                .collect(),
+            deltas: 2,
+        });
+        state
+    }
+
+    fn run(state: &mut daemon::State, line: &str) -> String {
+        let result = serde_json::from_str(line)
+            .map_err(|error| format_err!("Invalid request: {}", error))
+            .and_then(|request| execute(state, request))
+            .map(|(result, _)| result);
+        reply(result)
+    }
+
+    /// Reply parsed, so it is compared regardless of order of keys, which depends on features of `serde_json`.
+    fn run_value(state: &mut daemon::State, line: &str) -> serde_json::Value {
+        let reply = run(state, line);
+        assert!(reply.ends_with('\n'));
+        serde_json::from_str(&reply).unwrap()
+    }
+
+    fn value(text: &str) -> serde_json::Value {
+        serde_json::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn requests() {
+        assert_eq!(
+            serde_json::from_str::<Request>(r#"{"command": "refresh"}"#).unwrap(),
+            Request::Refresh { source: None }
+        );
+        assert_eq!(
+            serde_json::from_str::<Request>(r#"{"command": "contains", "set": "a", "address": "192.0.2.1"}"#)
+                .unwrap(),
+            Request::Contains {
+                set: "a".into(),
+                address: std::net::Ipv4Addr::new(192, 0, 2, 1).into(),
+            }
+        );
+        assert!(serde_json::from_str::<Request>(r#"{"command": "status", "verbose": true}"#).is_err());
+        assert!(serde_json::from_str::<Request>(r#"{"command": "contains", "set": "a", "address": "x"}"#).is_err());
+    }
+
+    #[test]
+    fn commands() {
+        let mut state = state();
+
+        assert_eq!(
+            run_value(&mut state, r#"{"command": "status"}"#),
+            value(
+                r#"{"result": {
+                    "local": [{"active": false, "address": "203.0.113.1", "comment": "Court order",
+                               "expires": "2017-12-31", "file": null, "list": "deny"}],
+                    "sets": {
+                        "blocked": {"deltas": 2, "entries": 3, "source": "local"},
+                        "pending": {"deltas": null, "entries": null, "source": "local"}
+                    },
+                    "sources": {"local": {"error": null, "failures": 0, "records": 1,
+                                          "refreshed": 1512130000, "updated": "2017-12-01 12:00:00"}}
+                }}"#
+            )
+        );
+
+        assert_eq!(
+            run_value(&mut state, r#"{"command": "contains", "set": "blocked", "address": "198.51.100.200"}"#),
+            value(r#"{"result": {"contains": true, "entries": ["198.51.100.0/24", "198.51.100.128/25"]}}"#)
+        );
+        assert_eq!(
+            run_value(&mut state, r#"{"command": "contains", "set": "blocked", "address": "192.0.2.2"}"#),
+            value(r#"{"result": {"contains": false, "entries": []}}"#)
+        );
+        assert_eq!(
+            run_value(&mut state, r#"{"command": "dump", "set": "blocked"}"#),
+            value(r#"{"result": ["192.0.2.1/32", "198.51.100.0/24", "198.51.100.128/25"]}"#)
+        );
+        assert_eq!(
+            run_value(&mut state, r#"{"command": "dump", "set": "pending"}"#),
+            value(r#"{"error": "Set \"pending\" is not updated yet"}"#)
+        );
+        assert_eq!(
+            run_value(&mut state, r#"{"command": "dump", "set": "missing"}"#),
+            value(r#"{"error": "Unknown set \"missing\""}"#)
+        );
+        assert!(run(&mut state, "status").starts_with("{\"error\":\"Invalid request: "));
+
+        let generation = state.sources["local"].generation;
+        assert_eq!(
+            execute(&mut state, Request::Refresh { source: None }).unwrap(),
+            (serde_json::to_value(vec!["local"]).unwrap(), vec!["local".into()])
+        );
+        assert!(state.sources["local"].generation > generation);
+        assert_eq!(
+            run_value(&mut state, r#"{"command": "refresh", "source": "missing"}"#),
+            value(r#"{"error": "Unknown source \"missing\""}"#)
+        );
+    }
+
+    #[test]
+    fn is_allowed() {
+        let config = config::Control {
+            socket: "control.sock".into(),
+            mode: "0660".into(),
+            users: vec![1000],
+            groups: vec![100],
+        };
+        assert!(super::is_allowed(&config, 0, &[0]));
+        assert!(super::is_allowed(&config, nix::unistd::geteuid().as_raw(), &[65_534]));
+        assert!(super::is_allowed(&config, 1000, &[1000]));
+        assert!(super::is_allowed(&config, 1001, &[100]));
+        assert!(super::is_allowed(&config, 1001, &[1001, 100]));
+        assert!(!super::is_allowed(&config, 1001, &[1001, 1002]));
+    }
+
+    #[test]
+    fn groups_of_user() {
+        let groups = super::groups_of_user(0, 0);
+        assert_eq!(groups[0], 0);
+        assert_eq!(groups.iter().filter(|&&gid| gid == 0).count(), 1);
+
+        // Unknown user has only primary group.
+        assert_eq!(super::groups_of_user(4_000_000_000, 12_345), vec![12_345]);
+    }
+}
@@ -0,0 +1,656 @@
+use std;
+
+use failure;
+use futures_cpupool;
+use ipnet;
+use tokio_core;
+use zicsv;
+
+use backend;
+use cache;
+use config;
+use dns;
+use local;
+use protected;
+use resolver;
+use sets;
+use source;
+use systemd;
+
+pub struct SourceState {
+    pub config: config::Source,
+    /// Changed each time source is (re)created, used to stop refreshing of removed or replaced source.
+    pub generation: u64,
+    pub source: std::sync::Arc<source::Source + Send + Sync>,
+    pub validators: source::Validators,
+    /// Last successfully parsed list.
+    pub list: Option<std::rc::Rc<source::List>>,
+    /// Time of last successful refresh, including ones which found list not modified.
+    pub refreshed: Option<std::time::SystemTime>,
+    /// Number of consecutive failures.
+    pub failures: u32,
+    /// Error of last refresh, if it failed.
+    pub last_error: Option<String>,
+    /// Number of finished refreshes, including failed ones.
+    pub fetches: u64,
+    /// Number of failed refreshes, including ones failed because of errors of parsing.
+    pub fetch_failures: u64,
+    /// Number of refreshes failed because of errors of parsing.
+    pub parse_errors: u64,
+    /// Number of lists rejected by guards, by name of rule.
+    pub rejections: std::collections::BTreeMap<&'static str, u64>,
+}
+
+impl SourceState {
+    fn new(config: &config::Source, generation: u64) -> Self {
+        Self {
+            config: config.clone(),
+            generation,
+            source: source::create(config),
+            validators: source::Validators::default(),
+            list: None,
+            refreshed: None,
+            failures: 0,
+            last_error: None,
+            fetches: 0,
+            fetch_failures: 0,
+            parse_errors: 0,
+            rejections: std::collections::BTreeMap::new(),
+        }
+    }
+}
+
+pub struct SetState {
+    pub config: config::Set,
+    /// Compiled filter of set, see `config::Set::filter`.
+    pub filter: Option<zicsv::Filter>,
+    pub backend: Box<backend::Backend>,
+    /// Contents of set after last successful update.
+    pub applied: Option<sets::Applied>,
+    /// Result of last update, error is kept as message.
+    pub last_update: Option<Result<sets::Changes, String>>,
+    /// Time taken by last update, successful or not.
+    pub last_update_duration: Option<std::time::Duration>,
+    /// Number of failed updates.
+    pub update_failures: u64,
+    /// Names taken from list, for sets of resolved names. `None` until list is fetched.
+    pub names: Option<resolver::Names>,
+}
+
+impl SetState {
+    fn new(config: &config::Set) -> Self {
+        Self {
+            config: config.clone(),
+            filter: config.compile_filter().expect("Filter is checked by config validation"),
+            backend: backend::create(config),
+            applied: None,
+            last_update: None,
+            last_update_duration: None,
+            update_failures: 0,
+            names: None,
+        }
+    }
+}
+
+/// Difference between two maps of named items.
+#[derive(Debug, Default, PartialEq)]
+pub struct Diff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+impl Diff {
+    pub fn new<Item: PartialEq>(
+        old: &std::collections::BTreeMap<String, Item>,
+        new: &std::collections::BTreeMap<String, Item>,
+    ) -> Self {
+        let mut diff = Self::default();
+
+        for (name, new_item) in new {
+            match old.get(name) {
+                Some(old_item) => if old_item != new_item {
+                    diff.changed.push(name.clone());
+                },
+                None => diff.added.push(name.clone()),
+            }
+        }
+
+        diff.removed = old.keys().filter(|name| !new.contains_key(*name)).cloned().collect();
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    fn log(&self, kind: &str) {
+        for name in &self.added {
+            info!("Reload: {} \"{}\" added", kind, name);
+        }
+        for name in &self.removed {
+            info!("Reload: {} \"{}\" removed", kind, name);
+        }
+        for name in &self.changed {
+            info!("Reload: {} \"{}\" changed", kind, name);
+        }
+    }
+}
+
+pub struct State {
+    pub config: config::Config,
+    pub sources: std::collections::BTreeMap<String, SourceState>,
+    pub sets: std::collections::BTreeMap<String, SetState>,
+    /// Addresses of names of sets of resolved names.
+    pub resolutions: std::collections::BTreeMap<resolver::Key, resolver::Resolution>,
+    /// Addresses from answers of forwarder to queries of names listed for sets of resolved names.
+    pub forwarded: std::collections::BTreeMap<resolver::Key, resolver::Addresses>,
+    /// Local entries in effect, together with protected addresses.
+    pub local: local::Active,
+    /// Protected addresses of local entries, parsed for forwarder. `None` if they are invalid.
+    protected: Option<protected::Protected>,
+
+    next_generation: u64,
+}
+
+impl State {
+    pub fn new(config: &config::Config) -> Self {
+        let mut state = Self {
+            config: config::Config::default(),
+            sources: std::collections::BTreeMap::new(),
+            sets: std::collections::BTreeMap::new(),
+            resolutions: std::collections::BTreeMap::new(),
+            forwarded: std::collections::BTreeMap::new(),
+            local: local::Active::default(),
+            protected: None,
+
+            next_generation: 0,
+        };
+        let _ = state.apply_config(config);
+        state
+    }
+
+    /// Log changes between current and new configuration and apply new one.
+    pub fn reload(&mut self, config: &config::Config) -> Vec<String> {
+        let sources_diff = Diff::new(&self.config.sources, &config.sources);
+        let sets_diff = Diff::new(&self.config.sets, &config.sets);
+        if sources_diff.is_empty() && sets_diff.is_empty() {
+            info!("Reload: no changes in sources and sets");
+        }
+        sources_diff.log("source");
+        sets_diff.log("set");
+        if self.config.control != config.control {
+            warn!("Reload: control socket settings changed, restart is needed to apply them");
+        }
+        if self.config.api != config.api {
+            warn!("Reload: HTTP API settings changed, restart is needed to apply them");
+        }
+        if self.config.metrics != config.metrics {
+            warn!("Reload: metrics settings changed, restart is needed to apply them");
+        }
+        let concurrency = |config: &config::Config| config.resolver.as_ref().map(|resolver| resolver.concurrency);
+        if concurrency(&self.config) != concurrency(config) {
+            warn!("Reload: resolver concurrency changed, restart is needed to apply it");
+        }
+        if self.config.forwarder != config.forwarder {
+            warn!("Reload: forwarder settings changed, restart is needed to apply them");
+        }
+        if self.config.protected != config.protected {
+            info!("Reload: protected addresses changed, all sets will be updated");
+        }
+        if self.config.local != config.local {
+            info!("Reload: local entries changed, all sets will be updated");
+        }
+
+        self.apply_config(config)
+    }
+
+    /// Bring state in accordance with new configuration. Unchanged sources and sets are kept as is, changed
+    /// sources and sets are recreated, sets are updated from already fetched lists. Returns names of sources which
+    /// need to be refreshed.
+    fn apply_config(&mut self, config: &config::Config) -> Vec<String> {
+        let sources_diff = Diff::new(&self.config.sources, &config.sources);
+        let sets_diff = Diff::new(&self.config.sets, &config.sets);
+        // Sets are updated with new protected addresses and local entries.
+        self.config = config.clone();
+        let local = local::active(config, local::today());
+        let local_changed = self.local != local;
+        self.set_local(local);
+
+        for name in &sources_diff.removed {
+            let _ = self.sources.remove(name);
+        }
+        for name in sources_diff.added.iter().chain(&sources_diff.changed) {
+            let generation = self.next_generation;
+            self.next_generation += 1;
+
+            let mut source_state = SourceState::new(&config.sources[name], generation);
+            // Keep previous list until new one is fetched, so sets do not lose their contents.
+            source_state.list = self.sources.get(name).and_then(|old| old.list.clone());
+            let _ = self.sources.insert(name.clone(), source_state);
+        }
+
+        for name in &sets_diff.removed {
+            let _ = self.sets.remove(name);
+        }
+        for name in sets_diff.added.iter().chain(&sets_diff.changed) {
+            let _ = self.sets.insert(name.clone(), SetState::new(&config.sets[name]));
+            self.update_set(name);
+        }
+
+        if local_changed {
+            let unchanged: Vec<String> = self.sets
+                .keys()
+                .filter(|name| !sets_diff.added.contains(name) && !sets_diff.changed.contains(name))
+                .cloned()
+                .collect();
+            for name in unchanged {
+                self.update_set(&name);
+            }
+        }
+
+        sources_diff.added.into_iter().chain(sources_diff.changed).collect()
+    }
+
+    /// Give source new generation, so refreshes scheduled before are dropped and results of running ones are
+    /// ignored. Returns `false` if there is no such source.
+    pub fn renew_source(&mut self, name: &str) -> bool {
+        let generation = self.next_generation;
+        match self.sources.get_mut(name) {
+            Some(source_state) => source_state.generation = generation,
+            None => return false,
+        }
+        self.next_generation += 1;
+        true
+    }
+
+    /// Update set from list of its source. Does nothing until list is fetched. Sets of resolved names take names from
+    /// list and are updated from resolutions, once all names are resolved.
+    fn update_set(&mut self, name: &str) {
+        let set_state = match self.sets.get_mut(name) {
+            Some(set_state) => set_state,
+            None => return,
+        };
+        let list = match self.sources.get(&set_state.config.source).and_then(|source_state| source_state.list.clone()) {
+            Some(list) => list,
+            None => return,
+        };
+
+        let started = std::time::Instant::now();
+        let result = if set_state.config.resolves_names() {
+            match resolver::names(name, &set_state.config, set_state.filter.as_ref(), &self.local, &list) {
+                Ok(names) => {
+                    let settled = resolver::all_settled(
+                        self.config.resolver.as_ref(),
+                        &names,
+                        set_state.config.address_family,
+                        &self.resolutions,
+                    );
+                    set_state.names = Some(names);
+                    if !settled {
+                        return;
+                    }
+                    resolver::update(
+                        &mut *set_state.backend,
+                        name,
+                        &set_state.config,
+                        &self.local.protected,
+                        set_state.names.as_ref().expect("Names are set above"),
+                        resolver::Known {
+                            resolutions: &self.resolutions,
+                            forwarded: &self.forwarded,
+                        },
+                        &mut set_state.applied,
+                    )
+                },
+                Err(error) => Err(error),
+            }
+        } else {
+            sets::update(
+                &mut *set_state.backend,
+                name,
+                &set_state.config,
+                set_state.filter.as_ref(),
+                &self.local,
+                &list,
+                &mut set_state.applied,
+            )
+        };
+        finish_update(name, set_state, started, result);
+    }
+
+    /// Update sets of resolved names from current resolutions and forwarded addresses. Sets are skipped until all
+    /// their names are resolved.
+    pub fn update_resolved_sets(&mut self) {
+        let resolver = self.config.resolver.as_ref();
+        let known = resolver::Known {
+            resolutions: &self.resolutions,
+            forwarded: &self.forwarded,
+        };
+        for (name, set_state) in &mut self.sets {
+            let family = set_state.config.address_family;
+            let names = match set_state.names {
+                Some(ref names) if resolver::all_settled(resolver, names, family, &self.resolutions) => names,
+                _ => continue,
+            };
+
+            let started = std::time::Instant::now();
+            let result = resolver::update(
+                &mut *set_state.backend,
+                name,
+                &set_state.config,
+                &self.local.protected,
+                names,
+                known,
+                &mut set_state.applied,
+            );
+            finish_update(name, set_state, started, result);
+        }
+    }
+
+    /// Select local entries in effect at given date. If they are changed, e.g. some entries expired, all sets are
+    /// updated. Returns whether entries are changed.
+    pub fn update_local(&mut self, today: zicsv::Date) -> bool {
+        let local = local::active(&self.config, today);
+        if local == self.local {
+            return false;
+        }
+        info!("Local entries: entries in effect changed, updating all sets");
+        self.set_local(local);
+
+        let names: Vec<String> = self.sets.keys().cloned().collect();
+        for name in names {
+            self.update_set(&name);
+        }
+        true
+    }
+
+    fn set_local(&mut self, local: local::Active) {
+        self.protected = match protected::Protected::new(&local.protected) {
+            Ok(protected) => Some(protected),
+            Err(error) => {
+                error!("Protected addresses: {}", ::error_chain(&error));
+                None
+            },
+        };
+        self.local = local;
+    }
+
+    /// Whether name, lowercase and without trailing dot, is listed for any set of resolved names.
+    pub fn lists_name(&self, name: &str) -> bool {
+        self.sets
+            .values()
+            .any(|set_state| set_state.names.as_ref().is_some_and(|names| names.matches(name)))
+    }
+
+    /// Add addresses from answer of forwarder to sets listing name right away, without waiting for update. Addresses
+    /// are remembered, so updates keep them in sets until they expire. Sets which are not applied yet get them with
+    /// their first update.
+    pub fn add_forwarded(
+        &mut self,
+        name: &str,
+        family: config::AddressFamily,
+        answer: &[dns::Address],
+        ttl: resolver::Ttl,
+    ) {
+        let protected = match self.protected {
+            Some(ref protected) => protected,
+            // Error is logged once local entries are selected, sets are not updated either.
+            None => return,
+        };
+
+        let mut matched = false;
+        for (set, set_state) in &mut self.sets {
+            if set_state.config.address_family != family
+                || !set_state.names.as_ref().is_some_and(|names| names.matches(name))
+            {
+                continue;
+            }
+            matched = true;
+
+            let applied = match set_state.applied {
+                Some(ref mut applied) => applied,
+                None => continue,
+            };
+            let mut entries = std::collections::BTreeSet::new();
+            for address in answer {
+                entries.extend(protected.exclude(set, ipnet::IpNet::from(address.address), resolver::Name(name)));
+            }
+            match sets::add(&mut *set_state.backend, set, &set_state.config, entries, applied) {
+                Ok(0) => (),
+                Ok(count) => info!("Set \"{}\": {} entries of {} added by forwarder", set, count, resolver::Name(name)),
+                Err(error) => error!(
+                    "Set \"{}\": unable to add entries of {}: {}",
+                    set,
+                    resolver::Name(name),
+                    ::error_chain(&error)
+                ),
+            }
+        }
+
+        if matched {
+            let addresses = self.forwarded.entry((name.to_string(), family)).or_default();
+            let _ = resolver::add_addresses(addresses, answer, ttl, std::time::Instant::now());
+        }
+    }
+
+    /// Take contents of existing sets as applied, so first updates apply only changes. Contents of sets which can not
+    /// be listed are replaced as usual.
+    pub fn load_sets(&mut self) {
+        for (name, set_state) in &mut self.sets {
+            match sets::load(&mut *set_state.backend, name, &set_state.config) {
+                Ok(applied) => set_state.applied = applied,
+                Err(error) => warn!(
+                    "Set \"{}\": unable to load contents, they will be replaced: {}",
+                    name,
+                    ::error_chain(&error)
+                ),
+            }
+        }
+    }
+
+    /// Take lists of sources which are not fetched yet from cache and update sets from them. Validators are taken too,
+    /// so unchanged lists are not fetched again.
+    pub fn load_caches(&mut self) {
+        let directory = match self.config.cache {
+            Some(ref cache) => cache.directory.clone(),
+            None => return,
+        };
+
+        let mut loaded = Vec::new();
+        for (name, source_state) in &mut self.sources {
+            if source_state.list.is_some() {
+                continue;
+            }
+
+            match cache::load(&cache::path(&directory, name), &source_state.config) {
+                Ok(Some((list, validators))) => {
+                    info!(
+                        "Source \"{}\": list updated {} loaded from cache, {} records, {} addresses",
+                        name,
+                        list.updated,
+                        list.records.len(),
+                        list.addresses()
+                    );
+                    source_state.list = Some(std::rc::Rc::new(list));
+                    source_state.validators = validators;
+                    loaded.push(name.clone());
+                },
+                Ok(None) => debug!("Source \"{}\": no cache", name),
+                Err(error) => warn!("Source \"{}\": cache ignored: {}", name, ::error_chain(&error)),
+            }
+        }
+
+        for name in loaded {
+            self.update_sets(&name);
+        }
+    }
+
+    /// Update all sets built from list of given source.
+    pub fn update_sets(&mut self, source: &str) {
+        let names: Vec<String> = self.sets
+            .iter()
+            .filter(|&(_, set_state)| set_state.config.source == source)
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in names {
+            self.update_set(&name);
+        }
+    }
+}
+
+/// Remember result of update of set.
+fn finish_update(
+    name: &str,
+    set_state: &mut SetState,
+    started: std::time::Instant,
+    result: Result<sets::Changes, failure::Error>,
+) {
+    set_state.last_update_duration = Some(started.elapsed());
+    if result.is_err() {
+        set_state.update_failures += 1;
+    }
+    set_state.last_update = Some(result.map_err(|error| {
+        let error = ::error_chain(&error);
+        error!("Set \"{}\": update failed: {}", name, error);
+        error
+    }));
+}
+
+/// Everything needed to run tasks on reactor and in thread pool. Cheap to clone.
+#[derive(Clone)]
+pub struct Daemon {
+    pub handle: tokio_core::reactor::Handle,
+    pub pool: futures_cpupool::CpuPool,
+    /// Pool of resolver, its size limits number of names resolved at once.
+    pub resolver_pool: futures_cpupool::CpuPool,
+    /// Whether names are being resolved, other resolutions are not started meanwhile.
+    pub resolving: std::rc::Rc<std::cell::Cell<bool>>,
+    pub state: std::rc::Rc<std::cell::RefCell<State>>,
+    pub notifier: std::rc::Rc<systemd::Notifier>,
+}
+
+impl Daemon {
+    pub fn new(handle: &tokio_core::reactor::Handle, config: &config::Config) -> Self {
+        Self {
+            handle: handle.clone(),
+            pool: futures_cpupool::Builder::new()
+                .name_prefix("addrsetd-fetch-")
+                .create(),
+            resolver_pool: futures_cpupool::Builder::new()
+                .pool_size(config.resolver.as_ref().map_or(1, |resolver| resolver.concurrency))
+                .name_prefix("addrsetd-resolve-")
+                .create(),
+            resolving: std::rc::Rc::new(std::cell::Cell::new(false)),
+            state: std::rc::Rc::new(std::cell::RefCell::new(State::new(config))),
+            notifier: std::rc::Rc::new(systemd::Notifier::from_env()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use config;
+
+    use super::*;
+
+    fn config(text: &str) -> config::Config {
+        text.parse().unwrap()
+    }
+
+    const CONFIG: &str = r#"
+        [sources.first]
+        type = "file"
+        path = "first.csv"
+
+        [sources.second]
+        type = "file"
+        path = "second.csv"
+
+        [sets.a]
+        source = "first"
+        types = ["ipv4"]
+
+        [sets.b]
+        source = "second"
+        types = ["ipv4"]
+    "#;
+
+    #[test]
+    fn diff() {
+        let old = config(CONFIG);
+        let new = config(
+            r#"
+                [sources.first]
+                type = "file"
+                path = "first.csv"
+
+                [sources.third]
+                type = "file"
+                path = "third.csv"
+
+                [sets.a]
+                source = "third"
+                types = ["ipv4"]
+            "#,
+        );
+
+        assert_eq!(
+            Diff::new(&old.sources, &new.sources),
+            Diff {
+                added: vec!["third".into()],
+                removed: vec!["second".into()],
+                changed: vec![],
+            }
+        );
+        assert_eq!(
+            Diff::new(&old.sets, &new.sets),
+            Diff {
+                added: vec![],
+                removed: vec!["b".into()],
+                changed: vec!["a".into()],
+            }
+        );
+        assert!(Diff::new(&old.sets, &old.sets).is_empty());
+    }
+
+    #[test]
+    fn apply_config() {
+        let mut state = State::new(&config(CONFIG));
+        assert_eq!(state.sources["first"].generation, 0);
+        assert_eq!(state.sources["second"].generation, 1);
+
+        let to_refresh = state.reload(&config(
+            r#"
+                [sources.first]
+                type = "file"
+                path = "first.csv"
+
+                [sources.second]
+                type = "directory"
+                path = "second"
+
+                [sources.third]
+                type = "file"
+                path = "third.csv"
+
+                [sets.a]
+                source = "third"
+                types = ["ipv4"]
+
+                [sets.c]
+                source = "second"
+                types = ["ipv4_network"]
+            "#,
+        ));
+        assert_eq!(to_refresh, vec!["third", "second"]);
+
+        assert_eq!(state.sources["first"].generation, 0);
+        assert_eq!(state.sources["second"].generation, 3);
+        assert_eq!(state.sources["second"].config.source_type, config::SourceType::Directory);
+        assert_eq!(state.sources["third"].generation, 2);
+
+        assert_eq!(state.sets.keys().collect::<Vec<_>>(), vec!["a", "c"]);
+        assert_eq!(state.sets["a"].config.source, "third");
+    }
+}
@@ -0,0 +1,641 @@
+//! Minimal DNS client for recursive resolvers: single A or AAAA query over UDP, repeated over TCP if answer is
+//! truncated. Aliases (CNAME) in answer are followed from queried name, recursive resolver returns the whole chain.
+//! Non-existent name (NXDOMAIN) is not an error, it has no addresses.
+//!
+//! Forwarder uses the same parsing for queries of its clients and answers of upstream servers, which are relayed as
+//! is. Only identifier is replaced: upstream servers get random one instead of one chosen by client, so client can not
+//! forge answers which are taken as responses to its query.
+
+use std;
+
+use failure;
+use rand;
+
+use config;
+
+pub const TYPE_A: u16 = 1;
+const TYPE_CNAME: u16 = 5;
+pub const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_TRUNCATED: u16 = 0x0200;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const FLAG_RECURSION_AVAILABLE: u16 = 0x0080;
+const RCODE_MASK: u16 = 0x000f;
+const RCODE_NO_ERROR: u16 = 0;
+pub const RCODE_SERVER_FAILURE: u16 = 2;
+pub const RCODE_NAME_ERROR: u16 = 3;
+
+const HEADER_SIZE: usize = 12;
+const MAX_LABEL_SIZE: usize = 63;
+const MAX_NAME_SIZE: usize = 255;
+/// Limit of compression pointers in one name, to stop on loops.
+const MAX_POINTERS: usize = 32;
+const MAX_UDP_SIZE: usize = 4096;
+
+/// Address from answer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Address {
+    pub address: std::net::IpAddr,
+    /// Time to live, in seconds.
+    pub ttl: u32,
+}
+
+fn query_type(family: config::AddressFamily) -> u16 {
+    match family {
+        config::AddressFamily::IPv4 => TYPE_A,
+        config::AddressFamily::IPv6 => TYPE_AAAA,
+    }
+}
+
+fn rcode_name(rcode: u16) -> String {
+    match rcode {
+        1 => "FORMERR".into(),
+        RCODE_SERVER_FAILURE => "SERVFAIL".into(),
+        4 => "NOTIMP".into(),
+        5 => "REFUSED".into(),
+        _ => format!("RCODE {}", rcode),
+    }
+}
+
+/// Name in wire format. Trailing dot is optional.
+fn encode_name(name: &str) -> Result<Vec<u8>, failure::Error> {
+    let trimmed = name.trim_right_matches('.');
+    ensure!(!trimmed.is_empty(), "Invalid name \"{}\"", name);
+
+    let mut encoded = Vec::new();
+    for label in trimmed.split('.') {
+        ensure!(
+            !label.is_empty() && label.len() <= MAX_LABEL_SIZE,
+            "Invalid name \"{}\"",
+            name
+        );
+        encoded.push(label.len() as u8);
+        encoded.extend_from_slice(label.as_bytes());
+    }
+    encoded.push(0);
+    ensure!(encoded.len() <= MAX_NAME_SIZE, "Name \"{}\" is too long", name);
+    Ok(encoded)
+}
+
+/// Query of one question with recursion desired.
+pub fn build_query(id: u16, name: &str, query_type: u16) -> Result<Vec<u8>, failure::Error> {
+    let mut message = Vec::with_capacity(HEADER_SIZE + name.len() + 6);
+    message.extend_from_slice(&id.to_be_bytes());
+    message.extend_from_slice(&FLAG_RECURSION_DESIRED.to_be_bytes());
+    // One question, no other records.
+    message.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    message.extend(encode_name(name)?);
+    message.extend_from_slice(&query_type.to_be_bytes());
+    message.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(message)
+}
+
+/// Reader of response, all reads are bounds-checked.
+struct Reader<'a> {
+    message: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, size: usize) -> Result<&'a [u8], failure::Error> {
+        ensure!(self.offset + size <= self.message.len(), "Truncated message");
+        let bytes = &self.message[self.offset..self.offset + size];
+        self.offset += size;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, failure::Error> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, failure::Error> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Name, possibly compressed, lowercase and without trailing dot.
+    fn name(&mut self) -> Result<String, failure::Error> {
+        let mut labels = Vec::new();
+        let mut offset = self.offset;
+        // Offset after name, known once the first pointer is met.
+        let mut end = None;
+        let mut pointers = 0;
+
+        loop {
+            ensure!(offset < self.message.len(), "Truncated message");
+            let size = self.message[offset] as usize;
+            match size & 0xc0 {
+                0x00 if size == 0 => {
+                    offset += 1;
+                    break;
+                },
+                0x00 => {
+                    ensure!(offset + 1 + size <= self.message.len(), "Truncated message");
+                    labels.push(String::from_utf8_lossy(&self.message[offset + 1..offset + 1 + size]).to_lowercase());
+                    offset += 1 + size;
+                },
+                0xc0 => {
+                    ensure!(offset + 1 < self.message.len(), "Truncated message");
+                    pointers += 1;
+                    ensure!(pointers <= MAX_POINTERS, "Loop of compression pointers");
+                    if end.is_none() {
+                        end = Some(offset + 2);
+                    }
+                    offset = ((size & 0x3f) << 8) | self.message[offset + 1] as usize;
+                },
+                _ => bail!("Unsupported label type {:#04x}", size & 0xc0),
+            }
+        }
+
+        self.offset = end.unwrap_or(offset);
+        Ok(labels.join("."))
+    }
+}
+
+/// Check that message is response to query with given identifier and the only question.
+fn check_response(message: &[u8], id: u16, name: &str, query_type: u16, class: u16) -> Result<(), failure::Error> {
+    let mut reader = Reader { message, offset: 0 };
+    ensure!(reader.u16()? == id, "Identifier of response does not match query");
+    ensure!(reader.u16()? & FLAG_RESPONSE != 0, "Message is not a response");
+    ensure!(reader.u16()? == 1, "Response should have exactly one question");
+    let _ = reader.bytes(6)?;
+    ensure!(
+        reader.name()? == name.trim_right_matches('.').to_lowercase()
+            && reader.u16()? == query_type
+            && reader.u16()? == class,
+        "Question of response does not match query"
+    );
+    Ok(())
+}
+
+/// Addresses of given type for name, following aliases. `None` if answer is truncated.
+fn parse_response(
+    id: u16,
+    name: &str,
+    query_type: u16,
+    message: &[u8],
+) -> Result<Option<Vec<Address>>, failure::Error> {
+    check_response(message, id, name, query_type, CLASS_IN)?;
+    let mut reader = Reader { message, offset: 2 };
+    let flags = reader.u16()?;
+    match flags & RCODE_MASK {
+        RCODE_NO_ERROR => (),
+        RCODE_NAME_ERROR => return Ok(Some(Vec::new())),
+        rcode => bail!("Server returned {}", rcode_name(rcode)),
+    }
+    if flags & FLAG_TRUNCATED != 0 {
+        return Ok(None);
+    }
+
+    // The only question is checked above.
+    let _ = reader.bytes(2)?;
+    let answers = reader.u16()?;
+    let _ = reader.bytes(4)?;
+    let _ = reader.name()?;
+    let _ = reader.bytes(4)?;
+
+    let mut aliases = std::collections::BTreeMap::new();
+    let mut records = Vec::new();
+    for _ in 0..answers {
+        let owner = reader.name()?;
+        let record_type = reader.u16()?;
+        let class = reader.u16()?;
+        let ttl = reader.u32()?;
+        let size = reader.u16()? as usize;
+        let data_offset = reader.offset;
+        let data = reader.bytes(size)?;
+        if class != CLASS_IN {
+            continue;
+        }
+
+        let address = match (record_type, size) {
+            (TYPE_A, 4) => std::net::IpAddr::from([data[0], data[1], data[2], data[3]]),
+            (TYPE_AAAA, 16) => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(data);
+                std::net::IpAddr::from(octets)
+            },
+            (TYPE_CNAME, _) => {
+                // Target may be compressed, so it is read from the whole message.
+                let mut target = Reader { message, offset: data_offset };
+                let _ = aliases.insert(owner, target.name()?);
+                continue;
+            },
+            (TYPE_A, _) | (TYPE_AAAA, _) => bail!("Invalid size of address {}", size),
+            _ => continue,
+        };
+        if record_type == query_type {
+            records.push((owner, Address { address, ttl }));
+        }
+    }
+
+    // Aliases are followed from queried name only, unrelated records are ignored.
+    let mut names = std::collections::BTreeSet::new();
+    let mut current = name.trim_right_matches('.').to_lowercase();
+    while names.insert(current.clone()) {
+        match aliases.get(&current) {
+            Some(target) => current = target.clone(),
+            None => break,
+        }
+    }
+
+    Ok(Some(
+        records
+            .into_iter()
+            .filter(|record| names.contains(&record.0))
+            .map(|(_, address)| address)
+            .collect(),
+    ))
+}
+
+/// Send query over UDP and wait for response to it, other messages are ignored.
+fn exchange_udp(
+    server: &std::net::SocketAddr,
+    query: &[u8],
+    is_response: &Fn(&[u8]) -> bool,
+    timeout: std::time::Duration,
+) -> Result<Vec<u8>, failure::Error> {
+    let local = if server.is_ipv4() {
+        std::net::SocketAddr::from(([0u8; 4], 0))
+    } else {
+        std::net::SocketAddr::from(([0u16; 8], 0))
+    };
+    let socket = std::net::UdpSocket::bind(local)?;
+    socket.connect(server)?;
+    let _ = socket.send(query)?;
+
+    let deadline = std::time::Instant::now() + timeout;
+    let mut buffer = vec![0; MAX_UDP_SIZE];
+    loop {
+        let now = std::time::Instant::now();
+        ensure!(now < deadline, "No response in {} s", timeout.as_secs());
+        socket.set_read_timeout(Some(deadline - now))?;
+        let size = match socket.recv(&mut buffer) {
+            Ok(size) => size,
+            Err(ref error)
+                if error.kind() == std::io::ErrorKind::WouldBlock || error.kind() == std::io::ErrorKind::TimedOut =>
+            {
+                bail!("No response in {} s", timeout.as_secs())
+            },
+            Err(error) => return Err(error.into()),
+        };
+        if is_response(&buffer[..size]) {
+            buffer.truncate(size);
+            return Ok(buffer);
+        }
+    }
+}
+
+/// Send query over TCP, messages are prefixed with their size.
+fn exchange_tcp(
+    server: &std::net::SocketAddr,
+    query: &[u8],
+    timeout: std::time::Duration,
+) -> Result<Vec<u8>, failure::Error> {
+    use std::io::Read;
+    use std::io::Write;
+
+    let mut stream = std::net::TcpStream::connect_timeout(server, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let mut request = (query.len() as u16).to_be_bytes().to_vec();
+    request.extend_from_slice(query);
+    stream.write_all(&request)?;
+
+    let mut size = [0u8; 2];
+    stream.read_exact(&mut size)?;
+    let mut response = vec![0; u16::from_be_bytes(size) as usize];
+    stream.read_exact(&mut response)?;
+    Ok(response)
+}
+
+fn query_no_context(
+    server: &std::net::SocketAddr,
+    name: &str,
+    family: config::AddressFamily,
+    timeout: std::time::Duration,
+) -> Result<Vec<Address>, failure::Error> {
+    let query_type = query_type(family);
+    let id = rand::random();
+    let query = build_query(id, name, query_type)?;
+
+    let is_response = |message: &[u8]| check_response(message, id, name, query_type, CLASS_IN).is_ok();
+    let response = exchange_udp(server, &query, &is_response, timeout)?;
+    if let Some(addresses) = parse_response(id, name, query_type, &response)? {
+        return Ok(addresses);
+    }
+    parse_response(id, name, query_type, &exchange_tcp(server, &query, timeout)?)?
+        .ok_or_else(|| format_err!("Truncated response over TCP"))
+}
+
+/// Addresses of name of given family, resolved by server.
+pub fn query(
+    server: &std::net::SocketAddr,
+    name: &str,
+    family: config::AddressFamily,
+    timeout: std::time::Duration,
+) -> Result<Vec<Address>, failure::Error> {
+    query_no_context(server, name, family, timeout)
+        .map_err(|error| error.context(format!("Server {}", server)).into())
+}
+
+/// Question of query from client of forwarder.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Question {
+    pub id: u16,
+    /// Lowercase, without trailing dot.
+    pub name: String,
+    pub query_type: u16,
+    class: u16,
+    flags: u16,
+    /// Question section as sent by client, repeated in replies.
+    section: Vec<u8>,
+}
+
+impl Question {
+    /// Family of addresses asked for, `None` for queries of other types.
+    pub fn family(&self) -> Option<config::AddressFamily> {
+        match self.query_type {
+            TYPE_A => Some(config::AddressFamily::IPv4),
+            TYPE_AAAA => Some(config::AddressFamily::IPv6),
+            _ => None,
+        }
+    }
+}
+
+/// Question of query with exactly one question, as sent by stub resolvers.
+pub fn parse_query(message: &[u8]) -> Result<Question, failure::Error> {
+    let mut reader = Reader { message, offset: 0 };
+    let id = reader.u16()?;
+    let flags = reader.u16()?;
+    ensure!(flags & FLAG_RESPONSE == 0, "Message is not a query");
+    ensure!(reader.u16()? == 1, "Query should have exactly one question");
+    let _ = reader.bytes(6)?;
+
+    let start = reader.offset;
+    let name = reader.name()?;
+    let query_type = reader.u16()?;
+    let class = reader.u16()?;
+    Ok(Question {
+        id,
+        name,
+        query_type,
+        class,
+        flags,
+        section: message[start..reader.offset].to_vec(),
+    })
+}
+
+/// Addresses from answer of upstream server to question, following aliases. Truncated answer has no addresses, client
+/// repeats query over TCP.
+pub fn addresses(question: &Question, response: &[u8]) -> Result<Vec<Address>, failure::Error> {
+    Ok(parse_response(question.id, &question.name, question.query_type, response)?.unwrap_or_default())
+}
+
+/// Reply to question without records, with given response code.
+pub fn reply(question: &Question, rcode: u16) -> Vec<u8> {
+    let flags = FLAG_RESPONSE | (question.flags & FLAG_RECURSION_DESIRED) | FLAG_RECURSION_AVAILABLE | rcode;
+    let mut message = Vec::with_capacity(HEADER_SIZE + question.section.len());
+    message.extend_from_slice(&question.id.to_be_bytes());
+    message.extend_from_slice(&flags.to_be_bytes());
+    message.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    message.extend_from_slice(&question.section);
+    message
+}
+
+/// Response of the first server which answers query, over TCP if client uses it, otherwise over UDP. Query is sent
+/// with random identifier, response should have it and the same question, identifier of client is restored then.
+/// Truncated response is returned as is.
+pub fn forward(
+    servers: &[std::net::SocketAddr],
+    question: &Question,
+    query: &[u8],
+    timeout: std::time::Duration,
+    tcp: bool,
+) -> Result<Vec<u8>, failure::Error> {
+    let id: u16 = rand::random();
+    let mut upstream_query = query.to_vec();
+    upstream_query[..2].copy_from_slice(&id.to_be_bytes());
+    let check = |message: &[u8]| check_response(message, id, &question.name, question.query_type, question.class);
+
+    let mut last_error = None;
+    for server in servers {
+        let result = if tcp {
+            exchange_tcp(server, &upstream_query, timeout).and_then(|response| {
+                check(&response)?;
+                Ok(response)
+            })
+        } else {
+            exchange_udp(server, &upstream_query, &|message| check(message).is_ok(), timeout)
+        };
+        match result {
+            Ok(mut response) => {
+                response[..2].copy_from_slice(&question.id.to_be_bytes());
+                return Ok(response);
+            },
+            Err(error) => last_error = Some(error.context(format!("Server {}", server)).into()),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| format_err!("No servers")))
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+
+    use super::*;
+
+    /// Answer to query with given flags and records (owner, type, data), owner is written as pointer to question.
+    fn response(query: &[u8], flags: u16, records: &[(&str, u16, Vec<u8>)]) -> Vec<u8> {
+        let mut message = query[..2].to_vec();
+        message.extend_from_slice(&(FLAG_RESPONSE | flags).to_be_bytes());
+        message.extend_from_slice(&[0, 1]);
+        message.extend_from_slice(&(records.len() as u16).to_be_bytes());
+        message.extend_from_slice(&[0, 0, 0, 0]);
+        message.extend_from_slice(&query[HEADER_SIZE..]);
+        for &(owner, record_type, ref data) in records {
+            if owner.is_empty() {
+                message.extend_from_slice(&[0xc0, HEADER_SIZE as u8]);
+            } else {
+                message.extend(super::encode_name(owner).unwrap());
+            }
+            message.extend_from_slice(&record_type.to_be_bytes());
+            message.extend_from_slice(&CLASS_IN.to_be_bytes());
+            message.extend_from_slice(&300u32.to_be_bytes());
+            message.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            message.extend_from_slice(data);
+        }
+        message
+    }
+
+    fn addresses(result: Option<Vec<Address>>) -> Vec<String> {
+        result
+            .unwrap()
+            .iter()
+            .map(|address| format!("{} {}", address.address, address.ttl))
+            .collect()
+    }
+
+    #[test]
+    fn encode_name() {
+        assert_eq!(
+            super::encode_name("www.example.com.").unwrap(),
+            b"\x03www\x07example\x03com\x00".to_vec()
+        );
+        assert!(super::encode_name("").is_err());
+        assert!(super::encode_name("www..example.com").is_err());
+        assert!(super::encode_name(&format!("{}.com", "a".repeat(64))).is_err());
+        assert!(super::encode_name(&vec!["a".repeat(63); 4].join(".")).is_err());
+    }
+
+    #[test]
+    fn parse_query() {
+        let query = build_query(7, "WWW.Example.com", TYPE_AAAA).unwrap();
+        let question = super::parse_query(&query).unwrap();
+        assert_eq!(question.id, 7);
+        assert_eq!(question.name, "www.example.com");
+        assert_eq!(question.family(), Some(config::AddressFamily::IPv6));
+        assert!(super::parse_query(&query[..query.len() - 1]).is_err());
+        assert!(super::parse_query(&response(&query, 0, &[])).is_err());
+
+        // Question is repeated as sent.
+        let reply = reply(&question, RCODE_NAME_ERROR);
+        assert_eq!(&reply[..4], &[0, 7, 0x81, 0x83]);
+        assert_eq!(&reply[HEADER_SIZE..], &query[HEADER_SIZE..]);
+        assert_eq!(super::addresses(&question, &reply).unwrap(), vec![]);
+
+        let other = build_query(8, "example.com", 16).unwrap();
+        assert_eq!(super::parse_query(&other).unwrap().family(), None);
+    }
+
+    #[test]
+    fn parse_response() {
+        let query = build_query(7, "WWW.example.com", TYPE_A).unwrap();
+        let parse = |message: &[u8]| super::parse_response(7, "WWW.example.com", TYPE_A, message);
+
+        // Alias with compressed target, addresses of other names and types are ignored.
+        let mut message = response(
+            &query,
+            0,
+            &[
+                ("", TYPE_CNAME, b"\x03cdn\xc0\x10".to_vec()),
+                ("cdn.example.com", TYPE_A, vec![192, 0, 2, 1]),
+                ("cdn.example.com", TYPE_AAAA, vec![0x20, 1, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]),
+                ("other.example.com", TYPE_A, vec![192, 0, 2, 2]),
+                ("", TYPE_A, vec![192, 0, 2, 3]),
+            ],
+        );
+        assert_eq!(addresses(parse(&message).unwrap()), vec!["192.0.2.1 300", "192.0.2.3 300"]);
+
+        assert!(super::parse_response(8, "www.example.com", TYPE_A, &message).is_err());
+        assert!(parse(&message[..message.len() - 1]).is_err());
+
+        // Pointer to itself.
+        let length = message.len();
+        message[length - 16..length - 14].copy_from_slice(&[0xc0, (length - 16) as u8]);
+        assert!(parse(&message).is_err());
+
+        assert_eq!(addresses(parse(&response(&query, RCODE_NAME_ERROR, &[])).unwrap()), Vec::<String>::new());
+        assert_eq!(parse(&response(&query, FLAG_TRUNCATED, &[])).unwrap(), None);
+        assert_eq!(
+            format!("{}", parse(&response(&query, 2, &[])).unwrap_err()),
+            "Server returned SERVFAIL"
+        );
+    }
+
+    #[test]
+    fn query() {
+        use std::io::Read;
+        use std::io::Write;
+
+        // Stub server answers over UDP with truncated response and over TCP with full one.
+        let udp = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = udp.local_addr().unwrap();
+        let tcp = std::net::TcpListener::bind(server).unwrap();
+        let thread = std::thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            let (size, client) = udp.recv_from(&mut buffer).unwrap();
+            let _ = udp.send_to(&response(&buffer[..size], FLAG_TRUNCATED, &[]), client).unwrap();
+
+            let (mut stream, _) = tcp.accept().unwrap();
+            let mut size = [0u8; 2];
+            stream.read_exact(&mut size).unwrap();
+            let mut query = vec![0; u16::from_be_bytes(size) as usize];
+            stream.read_exact(&mut query).unwrap();
+            let address = vec![0x20, 1, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2];
+            let message = response(&query, 0, &[("", TYPE_AAAA, address)]);
+            let mut framed = (message.len() as u16).to_be_bytes().to_vec();
+            framed.extend(message);
+            stream.write_all(&framed).unwrap();
+        });
+
+        let timeout = std::time::Duration::from_secs(5);
+        let result = super::query(&server, "example.com", config::AddressFamily::IPv6, timeout);
+        thread.join().unwrap();
+        assert_eq!(addresses(Some(result.unwrap())), vec!["2001:db8::2 300"]);
+
+        // Nothing listens anymore.
+        let timeout = std::time::Duration::from_millis(200);
+        let error = super::query(&server, "example.com", config::AddressFamily::IPv4, timeout).unwrap_err();
+        assert!(format!("{}", error).starts_with("Server 127.0.0.1:"));
+    }
+
+    #[test]
+    fn forward() {
+        use std::io::Read;
+
+        // Stub server answers over UDP with forged responses first, and over TCP with response to other question.
+        let udp = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = udp.local_addr().unwrap();
+        let tcp = std::net::TcpListener::bind(server).unwrap();
+        let thread = std::thread::spawn(move || {
+            use std::io::Write;
+
+            let forged = || vec![("", TYPE_A, vec![192, 0, 2, 66])];
+            let other_question = |query: &[u8]| {
+                let id = u16::from_be_bytes([query[0], query[1]]);
+                response(&build_query(id, "other.example.com", TYPE_A).unwrap(), 0, &forged())
+            };
+
+            let mut buffer = [0u8; 512];
+            let (size, client) = udp.recv_from(&mut buffer).unwrap();
+            let query = &buffer[..size];
+            let mut other_id = response(query, 0, &forged());
+            other_id[1] ^= 1;
+            let answer = response(query, 0, &[("", TYPE_A, vec![192, 0, 2, 1])]);
+            for message in &[other_id, other_question(query), answer] {
+                let _ = udp.send_to(message, client).unwrap();
+            }
+
+            let (mut stream, _) = tcp.accept().unwrap();
+            let mut size = [0u8; 2];
+            stream.read_exact(&mut size).unwrap();
+            let mut query = vec![0; u16::from_be_bytes(size) as usize];
+            stream.read_exact(&mut query).unwrap();
+            let message = other_question(&query);
+            let mut framed = (message.len() as u16).to_be_bytes().to_vec();
+            framed.extend(message);
+            stream.write_all(&framed).unwrap();
+        });
+
+        let query = build_query(7, "www.example.com", TYPE_A).unwrap();
+        let question = super::parse_query(&query).unwrap();
+        let timeout = std::time::Duration::from_secs(5);
+        let result = super::forward(&[server], &question, &query, timeout, false);
+        let error = super::forward(&[server], &question, &query, timeout, true).unwrap_err();
+        thread.join().unwrap();
+
+        // Client gets its own identifier back.
+        let response = result.unwrap();
+        assert_eq!(&response[..2], &[0, 7]);
+        assert_eq!(addresses(super::addresses(&question, &response).ok()), vec!["192.0.2.1 300"]);
+        assert_eq!(
+            ::error_chain(&error),
+            format!("Server {}: Question of response does not match query", server)
+        );
+    }
+}
@@ -0,0 +1,375 @@
+//! DNS forwarder, analogue of `ipset=` option of dnsmasq. Queries of clients are passed to upstream servers, and
+//! addresses from answers to queries of names listed for sets of resolved names are added to these sets before answer
+//! is relayed to client, so client connects to address which is in set already:
+//!
+//! * Domain names and hosts of URLs match themselves, wildcard domains match their subdomains, but not themselves.
+//! * Added addresses stay in sets until their TTL and grace period pass, TTL is limited as for resolver.
+//! * With `sinkhole`, queries of listed names are answered with NXDOMAIN instead of being forwarded.
+//! * Queries which no upstream server answers are answered with SERVFAIL.
+//!
+//! Forwarder listens on the same address over UDP and TCP, queries received over TCP are forwarded over TCP. Settings
+//! are not reloaded, restart is needed to apply them.
+
+use std;
+
+use failure;
+use futures;
+use futures_cpupool;
+use tokio_core;
+use tokio_io;
+
+use config;
+use daemon;
+use dns;
+use resolver;
+
+/// Maximum size of query received over UDP.
+const MAX_QUERY_SIZE: usize = 4096;
+
+struct Forwarder {
+    servers: Vec<std::net::SocketAddr>,
+    timeout: std::time::Duration,
+    ttl: resolver::Ttl,
+    sinkhole: bool,
+    /// Queries are forwarded by blocking client in thread pool, its size limits number of queries forwarded at once.
+    pool: futures_cpupool::CpuPool,
+}
+
+/// Reply to query, `None` if query is dropped. Addresses from answer are added to sets before reply is ready.
+fn handle(
+    daemon: &daemon::Daemon,
+    forwarder: &Forwarder,
+    query: Vec<u8>,
+    tcp: bool,
+) -> Box<futures::Future<Item = Option<Vec<u8>>, Error = ()>> {
+    use self::futures::Future;
+
+    let question = match dns::parse_query(&query) {
+        Ok(question) => question,
+        Err(error) => {
+            debug!("Forwarder: invalid query dropped: {}", error);
+            return Box::new(futures::future::ok(None));
+        },
+    };
+    if forwarder.sinkhole && daemon.state.borrow().lists_name(&question.name) {
+        debug!("Forwarder: query of {} answered with NXDOMAIN", resolver::Name(&question.name));
+        return Box::new(futures::future::ok(Some(dns::reply(&question, dns::RCODE_NAME_ERROR))));
+    }
+
+    let servers = forwarder.servers.clone();
+    let timeout = forwarder.timeout;
+    let forwarded = question.clone();
+    let daemon = daemon.clone();
+    let ttl = forwarder.ttl;
+    Box::new(
+        forwarder
+            .pool
+            .spawn_fn(move || Ok(dns::forward(&servers, &forwarded, &query, timeout, tcp)))
+            .map(move |result| match result {
+                Ok(response) => {
+                    if let Some(family) = question.family() {
+                        match dns::addresses(&question, &response) {
+                            Ok(ref answer) if answer.is_empty() => (),
+                            Ok(answer) => {
+                                daemon
+                                    .state
+                                    .borrow_mut()
+                                    .add_forwarded(&question.name, family, &answer, ttl);
+                            },
+                            Err(error) => debug!(
+                                "Forwarder: answer for {} ignored: {}",
+                                resolver::Name(&question.name),
+                                error
+                            ),
+                        }
+                    }
+                    Some(response)
+                },
+                Err(error) => {
+                    warn!(
+                        "Forwarder: unable to forward query of {}: {}",
+                        resolver::Name(&question.name),
+                        ::error_chain(&error)
+                    );
+                    Some(dns::reply(&question, dns::RCODE_SERVER_FAILURE))
+                },
+            }),
+    )
+}
+
+fn serve_udp(daemon: &daemon::Daemon, forwarder: &std::rc::Rc<Forwarder>, socket: tokio_core::net::UdpSocket) {
+    use self::futures::Future;
+    use self::futures::Stream;
+
+    let socket = std::rc::Rc::new(socket);
+    let receiver = socket.clone();
+    let daemon_clone = daemon.clone();
+    let forwarder = forwarder.clone();
+    daemon.handle.spawn(
+        futures::stream::poll_fn(move || {
+            let mut buffer = vec![0; MAX_QUERY_SIZE];
+            match receiver.recv_from(&mut buffer) {
+                Ok((size, client)) => {
+                    buffer.truncate(size);
+                    Ok(futures::Async::Ready(Some((buffer, client))))
+                },
+                Err(ref error) if error.kind() == std::io::ErrorKind::WouldBlock => Ok(futures::Async::NotReady),
+                Err(error) => Err(error),
+            }
+        }).then(|result| {
+                // Failure to receive one query should not stop receiving others.
+                if let Err(ref error) = result {
+                    warn!("Forwarder: unable to receive query: {}", error);
+                }
+                Ok(result.ok())
+            })
+            .for_each(move |received| {
+                if let Some((query, client)) = received {
+                    let socket = socket.clone();
+                    daemon_clone
+                        .handle
+                        .spawn(handle(&daemon_clone, &forwarder, query, false).map(move |reply| {
+                            if let Some(reply) = reply {
+                                if let Err(error) = socket.send_to(&reply, &client) {
+                                    debug!("Forwarder: unable to send reply to {}: {}", client, error);
+                                }
+                            }
+                        }));
+                }
+                Ok(())
+            }),
+    );
+}
+
+/// Serve queries of one TCP connection until client closes it. Messages are prefixed with their size.
+fn serve_connection(
+    daemon: &daemon::Daemon,
+    forwarder: &std::rc::Rc<Forwarder>,
+    stream: tokio_core::net::TcpStream,
+) -> Box<futures::Future<Item = (), Error = ()>> {
+    use self::futures::Future;
+
+    let daemon = daemon.clone();
+    let forwarder = forwarder.clone();
+    Box::new(
+        futures::future::loop_fn(stream, move |stream| {
+            let daemon = daemon.clone();
+            let forwarder = forwarder.clone();
+            tokio_io::io::read_exact(stream, [0u8; 2])
+                .and_then(|(stream, size)| tokio_io::io::read_exact(stream, vec![0; u16::from_be_bytes(size) as usize]))
+                .map_err(|error| debug!("Forwarder: connection closed: {}", error))
+                .and_then(move |(stream, query)| {
+                    handle(&daemon, &forwarder, query, true).map(|reply| (stream, reply))
+                })
+                .and_then(|(stream, reply)| -> Box<futures::Future<Item = _, Error = ()>> {
+                    match reply {
+                        Some(reply) => {
+                            let mut message = (reply.len() as u16).to_be_bytes().to_vec();
+                            message.extend(reply);
+                            Box::new(
+                                tokio_io::io::write_all(stream, message)
+                                    .map(|(stream, _)| futures::future::Loop::Continue(stream))
+                                    .map_err(|error| debug!("Forwarder: unable to send reply: {}", error)),
+                            )
+                        },
+                        None => Box::new(futures::future::ok(futures::future::Loop::Break(()))),
+                    }
+                })
+        }),
+    )
+}
+
+fn serve_tcp(daemon: &daemon::Daemon, forwarder: &std::rc::Rc<Forwarder>, listener: tokio_core::net::TcpListener) {
+    use self::futures::Stream;
+
+    let daemon_clone = daemon.clone();
+    let forwarder = forwarder.clone();
+    daemon.handle.spawn(
+        listener
+            .incoming()
+            .then(|result| {
+                // Failure to accept one connection should not stop accepting others.
+                if let Err(ref error) = result {
+                    warn!("Forwarder: unable to accept connection: {}", error);
+                }
+                Ok(result.ok())
+            })
+            .for_each(move |connection| {
+                if let Some((stream, _)) = connection {
+                    daemon_clone
+                        .handle
+                        .spawn(serve_connection(&daemon_clone, &forwarder, stream));
+                }
+                Ok(())
+            }),
+    );
+}
+
+/// Start serving queries on reactor of daemon, over UDP and TCP. Returns address actually bound.
+pub fn start(daemon: &daemon::Daemon, config: &config::Forwarder) -> Result<std::net::SocketAddr, failure::Error> {
+    let socket = std::net::UdpSocket::bind(config.listen)?;
+    // Port may be chosen by system, TCP listens on the same one.
+    let address = socket.local_addr()?;
+    let listener = std::net::TcpListener::bind(address)?;
+    let socket = tokio_core::net::UdpSocket::from_socket(socket, &daemon.handle)?;
+    let listener = tokio_core::net::TcpListener::from_listener(listener, &address, &daemon.handle)?;
+
+    let forwarder = std::rc::Rc::new(Forwarder {
+        servers: config.parse_servers()?,
+        timeout: std::time::Duration::from_secs(config.timeout),
+        ttl: resolver::Ttl::from(config),
+        sinkhole: config.sinkhole,
+        pool: futures_cpupool::Builder::new()
+            .pool_size(config.concurrency)
+            .name_prefix("addrsetd-forward-")
+            .create(),
+    });
+    serve_udp(daemon, &forwarder, socket);
+    serve_tcp(daemon, &forwarder, listener);
+    info!("Forwarder: listening on {}", address);
+    Ok(address)
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+
+    use backend::fake;
+    use sets;
+
+    use super::*;
+
+    /// Answer of upstream server with single address with given last octet.
+    fn answer(query: &[u8], octet: u8) -> Vec<u8> {
+        let mut message = query[..2].to_vec();
+        message.extend_from_slice(&[0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0]);
+        message.extend_from_slice(&query[12..]);
+        message.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 1, 0x2c, 0, 4, 198, 51, 100, octet]);
+        message
+    }
+
+    fn read_framed(stream: &mut std::net::TcpStream) -> Vec<u8> {
+        use std::io::Read;
+
+        let mut size = [0u8; 2];
+        stream.read_exact(&mut size).unwrap();
+        let mut message = vec![0; u16::from_be_bytes(size) as usize];
+        stream.read_exact(&mut message).unwrap();
+        message
+    }
+
+    fn write_framed(stream: &mut std::net::TcpStream, message: &[u8]) {
+        use std::io::Write;
+
+        let mut framed = (message.len() as u16).to_be_bytes().to_vec();
+        framed.extend_from_slice(message);
+        stream.write_all(&framed).unwrap();
+    }
+
+    fn exchange_udp(address: std::net::SocketAddr, query: &[u8]) -> Vec<u8> {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let _ = socket.send_to(query, address).unwrap();
+        let mut buffer = [0u8; 512];
+        let size = socket.recv(&mut buffer).unwrap();
+        buffer[..size].to_vec()
+    }
+
+    #[test]
+    fn loopback() {
+        // Upstream server answers two queries over UDP and one over TCP.
+        let udp = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let upstream = udp.local_addr().unwrap();
+        let tcp = std::net::TcpListener::bind(upstream).unwrap();
+        let server = std::thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            for octet in 2..4 {
+                let (size, client) = udp.recv_from(&mut buffer).unwrap();
+                let _ = udp.send_to(&answer(&buffer[..size], octet), client).unwrap();
+            }
+            let (mut stream, _) = tcp.accept().unwrap();
+            let query = read_framed(&mut stream);
+            write_framed(&mut stream, &answer(&query, 4));
+        });
+
+        let config: config::Config = format!(
+            r#"
+                [sources.local]
+                type = "file"
+                path = "dump.csv"
+
+                [sets.blocked]
+                source = "local"
+                types = ["domain", "wildcard_domain"]
+
+                [forwarder]
+                listen = "127.0.0.1:0"
+                servers = ["{}"]
+            "#,
+            upstream
+        ).parse()
+            .unwrap();
+        let mut core = tokio_core::reactor::Core::new().unwrap();
+        let daemon = daemon::Daemon::new(&core.handle(), &config);
+        let fake = fake::Fake::new();
+        {
+            let mut state = daemon.state.borrow_mut();
+            let set_state = state.sets.get_mut("blocked").unwrap();
+            set_state.backend = Box::new(fake.clone());
+            let _ = sets::apply(
+                &mut *set_state.backend,
+                "blocked",
+                &set_state.config,
+                std::collections::BTreeSet::new(),
+                &mut set_state.applied,
+            ).unwrap();
+            set_state.names = Some(resolver::Names {
+                names: vec!["example.com".into()].into_iter().collect(),
+                wildcards: vec!["example.net".into()].into_iter().collect(),
+            });
+        }
+
+        let mut forwarder_config = config.forwarder.clone().unwrap();
+        let address = start(&daemon, &forwarder_config).unwrap();
+        forwarder_config.sinkhole = true;
+        let sinkhole = start(&daemon, &forwarder_config).unwrap();
+
+        // Client blocks, so it runs in separate thread while reactor serves queries.
+        let (sender, receiver) = futures::sync::oneshot::channel();
+        let client = std::thread::spawn(move || {
+            let mut replies = vec![
+                exchange_udp(sinkhole, &dns::build_query(1, "www.example.net", dns::TYPE_A).unwrap()),
+                exchange_udp(address, &dns::build_query(2, "www.example.net", dns::TYPE_A).unwrap()),
+                exchange_udp(address, &dns::build_query(3, "example.org", dns::TYPE_A).unwrap()),
+            ];
+            let mut stream = std::net::TcpStream::connect(address).unwrap();
+            write_framed(&mut stream, &dns::build_query(4, "Example.COM", dns::TYPE_A).unwrap());
+            replies.push(read_framed(&mut stream));
+            sender.send(()).unwrap();
+            replies
+        });
+        core.run(receiver).unwrap();
+        let replies = client.join().unwrap();
+        server.join().unwrap();
+
+        let addresses = |name, reply: &[u8]| -> Vec<String> {
+            let question = dns::parse_query(&dns::build_query(reply[1].into(), name, dns::TYPE_A).unwrap()).unwrap();
+            dns::addresses(&question, reply)
+                .unwrap()
+                .iter()
+                .map(|address| format!("{}", address.address))
+                .collect()
+        };
+        assert_eq!(&replies[0][2..4], &[0x81, 0x83]);
+        assert_eq!(addresses("www.example.net", &replies[1]), vec!["198.51.100.2"]);
+        assert_eq!(addresses("example.org", &replies[2]), vec!["198.51.100.3"]);
+        assert_eq!(addresses("example.com", &replies[3]), vec!["198.51.100.4"]);
+
+        assert_eq!(fake.entries("blocked"), vec!["198.51.100.2/32", "198.51.100.4/32"]);
+        let state = daemon.state.borrow();
+        assert_eq!(
+            state.forwarded.keys().map(|key| key.0.as_str()).collect::<Vec<_>>(),
+            vec!["example.com", "www.example.net"]
+        );
+        assert_eq!(state.sets["blocked"].applied.as_ref().unwrap().entries.len(), 2);
+    }
+}
@@ -0,0 +1,202 @@
+//! Checks of fetched lists against rules from configuration of source, to avoid applying of truncated or broken
+//! lists. Rejected list is not used and not cached, previous list and contents of sets are kept, and refresh is
+//! retried as after any other failure. Rules:
+//!
+//! * `min_records`: minimum number of records. Record may have several addresses, so this is not number of entries.
+//! * `min_entries`: minimum number of entries, i.e. addresses in all records. Sets take only addresses of their
+//!   types, so this should not be greater than number of entries in the smallest set of source.
+//! * `max_shrink`, `max_growth`: maximum change of number of records since previous list, in percent.
+//! * `min_prefix_len`: minimum prefix length of IPv4 networks, e.g. 8 rejects "0.0.0.0/1".
+//! * `reject_older`: "Updated" timestamp should not be older than one of previous list.
+//!
+//! Rules which compare with previous list do nothing until it is fetched or loaded from cache.
+
+use std;
+
+use failure;
+use zicsv;
+
+use config;
+use source;
+
+/// Properties of previous list used by rules.
+#[derive(Clone, Copy, Debug)]
+pub struct Previous {
+    pub updated: zicsv::DateTime,
+    pub records: usize,
+}
+
+impl From<&source::List> for Previous {
+    fn from(list: &source::List) -> Self {
+        Self {
+            updated: list.updated,
+            records: list.records.len(),
+        }
+    }
+}
+
+/// Error of list rejected by rule.
+#[derive(Debug)]
+pub struct Rejected {
+    /// Name of rule, as in configuration.
+    pub rule: &'static str,
+    message: String,
+}
+
+impl std::fmt::Display for Rejected {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter, "Rejected by guard \"{}\": {}", self.rule, self.message)
+    }
+}
+
+impl std::error::Error for Rejected {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+fn reject(rule: &'static str, message: String) -> Result<(), failure::Error> {
+    Err(Rejected { rule, message }.into())
+}
+
+/// Check list against all rules. Error is `Rejected` naming the first failed rule.
+pub fn check(config: &config::Guards, list: &source::List, previous: Option<&Previous>) -> Result<(), failure::Error> {
+    let records = list.records.len();
+    if records < config.min_records {
+        return reject(
+            "min_records",
+            format!("{} records (minimum is {})", records, config.min_records),
+        );
+    }
+
+    let entries: usize = list.records.iter().map(|record| record.addresses.len()).sum();
+    if entries < config.min_entries {
+        return reject(
+            "min_entries",
+            format!("{} entries (minimum is {})", entries, config.min_entries),
+        );
+    }
+
+    if let Some(previous) = previous {
+        // Percents are compared multiplied by number of records, to stay in integers.
+        if let Some(max_shrink) = config.max_shrink {
+            if (records as u64) * 100 < (previous.records as u64) * u64::from(100 - max_shrink) {
+                return reject(
+                    "max_shrink",
+                    format!(
+                        "{} records instead of {} (maximum shrink is {}%)",
+                        records, previous.records, max_shrink
+                    ),
+                );
+            }
+        }
+
+        if let Some(max_growth) = config.max_growth {
+            if (records as u64) * 100 > (previous.records as u64) * (100 + u64::from(max_growth)) {
+                return reject(
+                    "max_growth",
+                    format!(
+                        "{} records instead of {} (maximum growth is {}%)",
+                        records, previous.records, max_growth
+                    ),
+                );
+            }
+        }
+
+        if config.reject_older && list.updated < previous.updated {
+            return reject(
+                "reject_older",
+                format!("list updated {} is older than previous one ({})", list.updated, previous.updated),
+            );
+        }
+    }
+
+    for record in &list.records {
+        for address in &record.addresses {
+            if let zicsv::Address::IPv4Network(network) = *address {
+                if network.prefix_len() < config.min_prefix_len {
+                    return reject(
+                        "min_prefix_len",
+                        format!(
+                            "network {} in record {} (minimum prefix length is {})",
+                            network, record.document_id, config.min_prefix_len
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Name of rule which rejected list, if error is rejection.
+pub fn rejected_by(error: &failure::Error) -> Option<&'static str> {
+    error.downcast_ref::<Rejected>().map(|rejected| rejected.rule)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(text: &'static str) -> source::List {
+        source::List::parse(Box::new(zicsv::Reader::from_reader(text.as_bytes()).unwrap())).unwrap()
+    }
+
+    fn rule(result: Result<(), failure::Error>) -> Option<&'static str> {
+        result.err().map(|error| rejected_by(&error).unwrap())
+    }
+
+    #[test]
+    fn check() {
+        let list = list(
+            "Updated: 2017-12-01 12:00:00 +0000\n\
+             192.0.2.1 | 192.0.2.5;;;Court;1;2017-01-01\n\
+             192.0.2.2;;;Court;2;2017-01-01\n\
+             198.51.100.0/24;;;Court;3;2017-01-01\n\
+             203.0.113.1;;;Court;4;2017-01-01\n",
+        );
+        let previous = |records, updated: &str| Previous {
+            updated: updated.parse().unwrap(),
+            records,
+        };
+        let check = |config: &config::Guards, previous: Option<Previous>| {
+            rule(super::check(config, &list, previous.as_ref()))
+        };
+
+        let mut config = config::Guards::default();
+        assert_eq!(check(&config, Some(previous(1000, "2018-01-01T00:00:00"))), None);
+
+        config.min_records = 5;
+        assert_eq!(check(&config, None), Some("min_records"));
+        config.min_records = 4;
+        assert_eq!(check(&config, None), None);
+
+        config.min_entries = 6;
+        assert_eq!(check(&config, None), Some("min_entries"));
+        config.min_entries = 5;
+        assert_eq!(check(&config, None), None);
+
+        config.max_shrink = Some(20);
+        assert_eq!(check(&config, Some(previous(5, "2017-01-01T00:00:00"))), None);
+        assert_eq!(check(&config, Some(previous(6, "2017-01-01T00:00:00"))), Some("max_shrink"));
+        assert_eq!(check(&config, None), None);
+
+        config.max_growth = Some(100);
+        assert_eq!(check(&config, Some(previous(2, "2017-01-01T00:00:00"))), None);
+        assert_eq!(check(&config, Some(previous(1, "2017-01-01T00:00:00"))), Some("max_growth"));
+
+        config.reject_older = true;
+        assert_eq!(check(&config, Some(previous(4, "2017-12-01T12:00:00"))), None);
+        assert_eq!(check(&config, Some(previous(4, "2017-12-01T12:00:01"))), Some("reject_older"));
+
+        config.min_prefix_len = 24;
+        assert_eq!(check(&config, None), None);
+        config.min_prefix_len = 25;
+        let error = super::check(&config, &list, None).unwrap_err();
+        assert_eq!(
+            format!("{}", error),
+            "Rejected by guard \"min_prefix_len\": network 198.51.100.0/24 in record 3 (minimum prefix length is 25)"
+        );
+    }
+}
@@ -0,0 +1,153 @@
+//! Local overrides of lists: static entries from configuration or separate files, for resources which should be
+//! blocked or exempted regardless of lists of sources. Each entry has comment and optional expiry date:
+//!
+//! * Denylist: IPv4 addresses and networks, domain names and wildcard domains. Entries are added to every set which
+//!   stores addresses of their type, as if they were in list of its source, but regardless of filter of set.
+//! * Allowlist: networks, single addresses and domain names. Entries are merged into protected addresses, see
+//!   `protected` module, so they win over both lists and denylist.
+//!
+//! Entry is in effect until the end of its expiry day, in UTC. Entries are checked periodically, sets are updated once
+//! any entry expires. Status reports all entries, marked as local, together with files they are loaded from.
+
+use std;
+
+use failure;
+use futures;
+use tokio_core;
+use zicsv;
+
+use config;
+use daemon;
+
+/// Interval of checks for expired entries, in seconds.
+const CHECK_INTERVAL: u64 = 60;
+/// Number of days from 0001-01-01 to 1970-01-01.
+const UNIX_EPOCH_DAYS_FROM_CE: i64 = 719_163;
+
+/// Local entries in effect at some date.
+#[derive(Debug, Default, PartialEq)]
+pub struct Active {
+    /// Protected addresses from configuration, together with allowlist.
+    pub protected: config::Protected,
+    /// Addresses of denylist.
+    pub denied: Vec<zicsv::Address>,
+}
+
+/// Current date in UTC.
+pub fn today() -> zicsv::Date {
+    let days = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs() / (24 * 60 * 60))
+        .unwrap_or(0);
+    zicsv::Date::from_num_days_from_ce_opt((UNIX_EPOCH_DAYS_FROM_CE + days as i64) as i32)
+        .expect("Current date is out of range")
+}
+
+/// Entries in effect at given date. Configuration is validated, so entries which can not be parsed are not expected.
+pub fn active(config: &config::Config, today: zicsv::Date) -> Active {
+    let mut protected = config.protected.clone();
+    for entry in config.local.allow.iter().filter(|entry| entry.is_active(today)) {
+        if config::parse_network(&entry.address).is_ok() {
+            protected.networks.push(entry.address.clone());
+        } else {
+            protected.domains.push(entry.address.clone());
+        }
+    }
+
+    Active {
+        protected,
+        denied: config
+            .local
+            .deny
+            .iter()
+            .filter(|entry| entry.is_active(today))
+            .filter_map(|entry| entry.parse_address().ok())
+            .collect(),
+    }
+}
+
+/// Entry of denylist in log messages.
+pub struct Denied<'a>(pub &'a zicsv::Address);
+
+impl<'a> std::fmt::Display for Denied<'a> {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter, "local entry \"{}\"", String::from(self.0))
+    }
+}
+
+/// Start checking for expired entries periodically.
+pub fn start(daemon: &daemon::Daemon) -> Result<(), failure::Error> {
+    use self::futures::Future;
+    use self::futures::Stream;
+
+    let daemon_clone = daemon.clone();
+    daemon.handle.spawn(
+        tokio_core::reactor::Interval::new(std::time::Duration::from_secs(CHECK_INTERVAL), &daemon.handle)?
+            .for_each(move |_| {
+                let mut state = daemon_clone.state.borrow_mut();
+                if state.update_local(today()) {
+                    daemon_clone.notifier.update(&state);
+                }
+                Ok(())
+            })
+            .map_err(|error| error!("Local entries: checks stopped: {}", error)),
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn active() {
+        let config: config::Config = r#"
+            [sources.local]
+            type = "file"
+            path = "dump.csv"
+
+            [sets.blocked]
+            source = "local"
+            types = ["ipv4"]
+
+            [protected]
+            networks = ["198.51.100.0/24"]
+
+            [[local.allow]]
+            address = "192.0.2.1"
+            comment = "Exempted"
+
+            [[local.allow]]
+            address = "Example.com"
+            comment = "Exempted until 2018"
+            expires = "2018-12-31"
+
+            [[local.deny]]
+            address = "203.0.113.0/24"
+            comment = "Court 1"
+
+            [[local.deny]]
+            address = "*.example.net"
+            comment = "Court 2"
+            expires = "2018-06-30"
+        "#.parse()
+            .unwrap();
+        let date = |date: &str| date.parse().unwrap();
+
+        let active = super::active(&config, date("2018-06-30"));
+        assert_eq!(active.protected.networks, vec!["198.51.100.0/24", "192.0.2.1"]);
+        assert_eq!(active.protected.domains, vec!["Example.com"]);
+        assert_eq!(
+            active.denied,
+            vec![
+                zicsv::Address::IPv4Network("203.0.113.0/24".parse().unwrap()),
+                zicsv::Address::WildcardDomainName("*.example.net".into()),
+            ]
+        );
+
+        let active = super::active(&config, date("2019-01-01"));
+        assert!(active.protected.domains.is_empty());
+        assert_eq!(active.denied.len(), 1);
+        assert_eq!(format!("{}", Denied(&active.denied[0])), "local entry \"203.0.113.0/24\"");
+    }
+}
@@ -12,24 +12,71 @@
 #![cfg_attr(feature = "cargo-clippy", warn(used_underscore_binding))]
 #![cfg_attr(feature = "cargo-clippy", warn(print_stdout))]
 
+#[macro_use]
 extern crate failure;
+extern crate flate2;
+extern crate form_urlencoded;
 extern crate futures;
+extern crate futures_cpupool;
+extern crate hyper;
+extern crate idna;
+extern crate ipnet;
 extern crate isatty;
+extern crate listenfd;
 
 extern crate log4rs;
 #[macro_use]
 extern crate log;
 extern crate log_panics;
+extern crate nix;
+
+extern crate rand;
+
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
 
 extern crate structopt;
 #[macro_use]
 extern crate structopt_derive;
 
 extern crate tokio_core;
+extern crate tokio_io;
 extern crate tokio_signal;
+extern crate tokio_uds;
+extern crate toml;
+extern crate ureq;
+
+extern crate zicsv;
+
+mod api;
+mod backend;
+mod cache;
+mod config;
+mod control;
+mod daemon;
+mod dns;
+mod forwarder;
+mod guards;
+mod local;
+mod metrics;
+mod oneshot;
+mod protected;
+mod resolver;
+mod scheduler;
+mod sets;
+mod source;
+mod systemd;
 
 #[derive(StructOpt, Debug)]
-struct Options {}
+struct Options {
+    #[structopt(short = "c", long = "config", help = "Path to configuration file",
+                default_value = "/etc/addrsetd/addrsetd.toml")]
+    config_path: String,
+    #[structopt(long = "oneshot", help = "Refresh all sources and update sets once, print summary and exit")]
+    oneshot: bool,
+}
 
 fn init_basic_logger() -> Result<log4rs::Handle, failure::Error> {
     let pattern = if isatty::stderr_isatty() {
@@ -57,40 +104,79 @@ fn init_basic_logger() -> Result<log4rs::Handle, failure::Error> {
     log4rs::init_config(config).map_err(|error| error.into())
 }
 
-fn watch_signals(handle: &tokio_core::reactor::Handle) -> Box<futures::Future<Item = (), Error = failure::Error>> {
+fn reload(daemon: &daemon::Daemon, config_path: &str) {
+    info!("Reloading configuration from \"{}\"...", config_path);
+
+    match config::Config::load(config_path) {
+        Ok(config) => {
+            let to_refresh = daemon.state.borrow_mut().reload(&config);
+            daemon.notifier.update(&daemon.state.borrow());
+            scheduler::start(daemon, &to_refresh);
+            resolver::check(daemon);
+            info!("Configuration reloaded");
+        },
+
+        Err(error) => error!(
+            "Unable to reload configuration, continuing with old one: {}",
+            error_chain(&error)
+        ),
+    }
+}
+
+fn watch_signals(
+    daemon: &daemon::Daemon,
+    config_path: &str,
+) -> Box<futures::Future<Item = (), Error = failure::Error>> {
     use self::futures::Future;
     use self::futures::Stream;
 
+    let handle = &daemon.handle;
     let sigint = tokio_signal::unix::Signal::new(tokio_signal::unix::SIGINT, handle).flatten_stream();
     let sigterm = tokio_signal::unix::Signal::new(tokio_signal::unix::SIGTERM, handle).flatten_stream();
+    let sighup = tokio_signal::unix::Signal::new(tokio_signal::unix::SIGHUP, handle).flatten_stream();
 
-    let signals = sigint.select(sigterm);
+    let signals = sigint.select(sigterm).select(sighup);
 
+    let daemon = daemon.clone();
+    let config_path = String::from(config_path);
     Box::new(
         signals
             .from_err()
-            .take_while(|signal| {
+            .take_while(move |signal| {
                 let sig_name = match *signal {
                     tokio_signal::unix::SIGINT => "SIGINT",
                     tokio_signal::unix::SIGTERM => "SIGTERM",
+                    tokio_signal::unix::SIGHUP => {
+                        info!("SIGHUP received");
+                        reload(&daemon, &config_path);
+                        return Ok(true);
+                    },
                     _ => unreachable!("Unexpected signal: {}", signal),
                 };
                 info!("{} received, terminating...", sig_name);
 
-                // Later we may decide to handle other signals, like SIGHUP for re-reading configuration.
                 Ok(false)
             })
             .for_each(|_| Ok(())),
     )
 }
 
-fn real_main() -> Result<(), failure::Error> {
+fn error_chain(error: &failure::Error) -> String {
+    let causes: Vec<String> = error.causes().map(|cause| format!("{}", cause)).collect();
+    causes.join(": ")
+}
+
+/// Returns exit code.
+fn real_main() -> Result<i32, failure::Error> {
     use structopt::StructOpt;
 
+    // Before anything starts threads, errors are reported once logging is initialized.
+    let passed = systemd::listen_fds();
+
     let _ = init_basic_logger().expect("Unable to initialize basic logger (stderr)");
     log_panics::init();
 
-    let _ = Options::from_args();
+    let options = Options::from_args();
 
     info!(
         "{} version {} started",
@@ -98,16 +184,78 @@ fn real_main() -> Result<(), failure::Error> {
         env!("CARGO_PKG_VERSION")
     );
 
+    let config = config::Config::load(&options.config_path)?;
+    info!(
+        "Configuration loaded from \"{}\": {} source(s), {} set(s)",
+        options.config_path,
+        config.sources.len(),
+        config.sets.len()
+    );
+
     let mut core = tokio_core::reactor::Core::new()?;
     let handle = core.handle();
-    let signals = watch_signals(&handle);
-    core.run(signals)?;
 
-    Ok(())
+    let daemon = daemon::Daemon::new(&handle, &config);
+    if options.oneshot {
+        return Ok(oneshot::run(&mut core, &daemon));
+    }
+    daemon.state.borrow_mut().load_caches();
+
+    let sources: Vec<String> = config.sources.keys().cloned().collect();
+    scheduler::start(&daemon, &sources);
+    resolver::start(&daemon).map_err(|error| error.context("Unable to start resolver"))?;
+    local::start(&daemon).map_err(|error| error.context("Unable to start checks of local entries"))?;
+
+    // Settings of control socket, HTTP API, metrics and forwarder are not reloaded, they are kept until exit.
+    let mut passed = passed.map_err(|error| error.context("Unable to take sockets passed by systemd"))?;
+    if !passed.is_empty() {
+        info!("systemd: {} socket(s) passed", passed.len());
+    }
+    let mut control_activated = false;
+    if let Some(ref control) = config.control {
+        let listener = passed.take_unix(&control.socket);
+        control_activated = listener.is_some();
+        control::start(&daemon, control, listener).map_err(|error| error.context("Unable to start control socket"))?;
+    }
+    if let Some(ref api) = config.api {
+        let listener = passed.take_tcp(&api.listen);
+        let _ = api::start(&daemon, api, listener).map_err(|error| error.context("Unable to start HTTP API"))?;
+    }
+    if let Some(ref metrics) = config.metrics {
+        let listener = passed.take_tcp(&metrics.listen);
+        let _ = metrics::start(&daemon, metrics, listener).map_err(|error| error.context("Unable to start metrics"))?;
+    }
+    if let Some(ref forwarder) = config.forwarder {
+        let _ = forwarder::start(&daemon, forwarder).map_err(|error| error.context("Unable to start forwarder"))?;
+    }
+    if !passed.is_empty() {
+        warn!("systemd: {} passed socket(s) do not match configuration, closing them", passed.len());
+        drop(passed);
+    }
+
+    // Sets may be updated from caches already.
+    daemon.notifier.update(&daemon.state.borrow());
+    systemd::start_watchdog(&daemon).map_err(|error| error.context("Unable to start systemd watchdog"))?;
+    if config.systemd.ready_on_start {
+        daemon.notifier.ready();
+    }
+
+    let signals = watch_signals(&daemon, &options.config_path);
+    let result = core.run(signals);
+    daemon.notifier.stopping();
+
+    if let Some(ref control) = config.control {
+        if !control_activated {
+            control::stop(control);
+        }
+    }
+    result?;
+
+    Ok(0)
 }
 
 fn main() {
-    let rc = real_main().map(|_| 0).unwrap_or_else(|error| {
+    let rc = real_main().unwrap_or_else(|error| {
         let error_backtrace = format!("{}", error.backtrace());
         let mut duplicate_error_backtrace = false;
         for cause in error.causes() {
@@ -128,7 +276,7 @@ fn main() {
             error!("Error {}", error_backtrace);
         };
 
-        1
+        oneshot::EXIT_FAILED
     });
     std::process::exit(rc)
 }
@@ -0,0 +1,349 @@
+//! Metrics in Prometheus text exposition format, served at `GET /metrics`. Values reflect state at the moment of
+//! request. Counters start from zero when daemon is started and when source or set is recreated by reload.
+//!
+//! Sources and sets are labeled by name, names are validated with configuration and need no escaping.
+
+use std;
+
+use failure;
+use hyper;
+use zicsv;
+
+use api;
+use config;
+use daemon;
+use scheduler;
+
+/// Metric with all its samples.
+struct Family {
+    name: &'static str,
+    kind: &'static str,
+    help: &'static str,
+    samples: Vec<(String, f64)>,
+}
+
+impl Family {
+    fn new(name: &'static str, kind: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            kind,
+            help,
+            samples: Vec::new(),
+        }
+    }
+
+    fn counter(name: &'static str, help: &'static str) -> Self {
+        Self::new(name, "counter", help)
+    }
+
+    fn gauge(name: &'static str, help: &'static str) -> Self {
+        Self::new(name, "gauge", help)
+    }
+
+    fn add(&mut self, labels: &str, value: f64) {
+        self.samples.push((labels.into(), value));
+    }
+
+    fn write(&self, output: &mut String) {
+        output.push_str(&format!("# HELP {} {}\n", self.name, self.help));
+        output.push_str(&format!("# TYPE {} {}\n", self.name, self.kind));
+        for &(ref labels, value) in &self.samples {
+            output.push_str(&format!("{}{{{}}} {}\n", self.name, labels, value));
+        }
+    }
+}
+
+/// Seconds since Unix epoch, negative for times before it.
+fn timestamp(time: std::time::SystemTime) -> f64 {
+    match time.duration_since(std::time::UNIX_EPOCH) {
+        Ok(duration) => scheduler::as_seconds(duration),
+        Err(error) => -scheduler::as_seconds(error.duration()),
+    }
+}
+
+/// Numbers of records containing addresses of each type and numbers of addresses of each type.
+fn count_by_type(records: &[zicsv::Record]) -> std::collections::BTreeMap<zicsv::AddressType, (usize, usize)> {
+    let mut counts: std::collections::BTreeMap<_, _> = zicsv::AddressType::all()
+        .iter()
+        .map(|address_type| (*address_type, (0, 0)))
+        .collect();
+
+    for record in records {
+        let mut types = std::collections::BTreeSet::new();
+        for address in &record.addresses {
+            let address_type = address.address_type();
+            counts.entry(address_type).or_insert((0, 0)).1 += 1;
+            let _ = types.insert(address_type);
+        }
+        for address_type in types {
+            counts.entry(address_type).or_insert((0, 0)).0 += 1;
+        }
+    }
+
+    counts
+}
+
+/// Render all metrics. Current time is used for ages of lists.
+fn render(state: &daemon::State, now: std::time::SystemTime) -> String {
+    let mut fetches = Family::counter(
+        "addrsetd_source_fetches_total",
+        "Number of finished refreshes of source, including failed ones.",
+    );
+    let mut fetch_failures = Family::counter(
+        "addrsetd_source_fetch_failures_total",
+        "Number of failed refreshes of source, including errors of parsing.",
+    );
+    let mut parse_errors = Family::counter(
+        "addrsetd_source_parse_errors_total",
+        "Number of refreshes of source failed because list could not be parsed.",
+    );
+    let mut rejections = Family::counter(
+        "addrsetd_source_rejections_total",
+        "Number of fetched lists of source rejected by guard rule.",
+    );
+    let mut consecutive_failures = Family::gauge(
+        "addrsetd_source_consecutive_failures",
+        "Number of consecutive failed refreshes of source.",
+    );
+    let mut last_success = Family::gauge(
+        "addrsetd_source_last_success_timestamp_seconds",
+        "Time of last successful refresh of source, including ones which found list not modified.",
+    );
+    let mut list_updated = Family::gauge(
+        "addrsetd_source_list_updated_timestamp_seconds",
+        "Time from \"Updated\" header of last fetched list.",
+    );
+    let mut list_age = Family::gauge(
+        "addrsetd_source_list_age_seconds",
+        "Time passed since time from \"Updated\" header of last fetched list.",
+    );
+    let mut records = Family::gauge(
+        "addrsetd_source_records",
+        "Number of records in last fetched list which contain addresses of type.",
+    );
+    let mut addresses = Family::gauge(
+        "addrsetd_source_addresses",
+        "Number of addresses of type in last fetched list.",
+    );
+
+    let now = timestamp(now);
+    for (name, source_state) in &state.sources {
+        let labels = format!("source=\"{}\"", name);
+        fetches.add(&labels, source_state.fetches as f64);
+        fetch_failures.add(&labels, source_state.fetch_failures as f64);
+        parse_errors.add(&labels, source_state.parse_errors as f64);
+        for (rule, &count) in &source_state.rejections {
+            rejections.add(&format!("{},rule=\"{}\"", labels, rule), count as f64);
+        }
+        consecutive_failures.add(&labels, f64::from(source_state.failures));
+        if let Some(refreshed) = source_state.refreshed {
+            last_success.add(&labels, timestamp(refreshed));
+        }
+
+        if let Some(ref list) = source_state.list {
+            let updated = list.updated.timestamp() as f64;
+            list_updated.add(&labels, updated);
+            list_age.add(&labels, now - updated);
+            for (address_type, &(records_count, addresses_count)) in &count_by_type(&list.records) {
+                let labels = format!("{},type=\"{}\"", labels, address_type.name());
+                records.add(&labels, records_count as f64);
+                addresses.add(&labels, addresses_count as f64);
+            }
+        }
+    }
+
+    let mut entries = Family::gauge("addrsetd_set_entries", "Number of entries in set after last successful update.");
+    let mut deltas = Family::gauge(
+        "addrsetd_set_deltas",
+        "Number of updates which applied only changes since set was replaced whole.",
+    );
+    let mut replaced = Family::gauge(
+        "addrsetd_set_last_update_replaced",
+        "1 if last successful update replaced contents of set whole, 0 if it applied only changes.",
+    );
+    let mut added = Family::gauge(
+        "addrsetd_set_last_update_added_entries",
+        "Number of entries added by last successful update which applied only changes.",
+    );
+    let mut deleted = Family::gauge(
+        "addrsetd_set_last_update_deleted_entries",
+        "Number of entries deleted by last successful update which applied only changes.",
+    );
+    let mut duration = Family::gauge(
+        "addrsetd_set_last_update_duration_seconds",
+        "Time taken by last update of set, successful or not.",
+    );
+    let mut update_failures = Family::counter("addrsetd_set_update_failures_total", "Number of failed updates of set.");
+
+    for (name, set_state) in &state.sets {
+        let labels = format!("set=\"{}\"", name);
+        if let Some(ref applied) = set_state.applied {
+            entries.add(&labels, applied.entries.len() as f64);
+            deltas.add(&labels, f64::from(applied.deltas));
+        }
+        if let Some(Ok(ref changes)) = set_state.last_update {
+            replaced.add(&labels, if changes.replaced { 1.0 } else { 0.0 });
+            // Numbers of added and deleted entries are not known when set is replaced.
+            if !changes.replaced {
+                added.add(&labels, changes.added as f64);
+                deleted.add(&labels, changes.deleted as f64);
+            }
+        }
+        if let Some(last_update_duration) = set_state.last_update_duration {
+            duration.add(&labels, scheduler::as_seconds(last_update_duration));
+        }
+        update_failures.add(&labels, set_state.update_failures as f64);
+    }
+
+    let mut output = String::new();
+    for family in &[
+        fetches,
+        fetch_failures,
+        parse_errors,
+        rejections,
+        consecutive_failures,
+        last_success,
+        list_updated,
+        list_age,
+        records,
+        addresses,
+        entries,
+        deltas,
+        replaced,
+        added,
+        deleted,
+        duration,
+        update_failures,
+    ] {
+        family.write(&mut output);
+    }
+    output
+}
+
+fn respond(daemon: &daemon::Daemon, request: &hyper::Request) -> hyper::Response {
+    let (status, body) = match (request.path(), request.method()) {
+        ("/metrics", &hyper::Method::Get) => (
+            hyper::StatusCode::Ok,
+            render(&daemon.state.borrow(), std::time::SystemTime::now()),
+        ),
+        ("/metrics", _) => (hyper::StatusCode::MethodNotAllowed, "Method is not allowed\n".into()),
+        _ => (hyper::StatusCode::NotFound, "Not found\n".into()),
+    };
+    debug!("Metrics: {} {} - {}", request.method(), request.uri(), status);
+
+    let content_type = "text/plain; version=0.0.4; charset=utf-8"
+        .parse()
+        .expect("Invalid content type of metrics");
+    hyper::Response::new()
+        .with_status(status)
+        .with_header(hyper::header::ContentType(content_type))
+        .with_header(hyper::header::ContentLength(body.len() as u64))
+        .with_body(body)
+}
+
+/// Start serving metrics on reactor of daemon. Returns address actually bound.
+pub fn start(
+    daemon: &daemon::Daemon,
+    config: &config::Metrics,
+    listener: Option<std::net::TcpListener>,
+) -> Result<std::net::SocketAddr, failure::Error> {
+    api::serve(daemon, &config.listen, listener, "Metrics", respond)
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+
+    use sets;
+    use source;
+
+    use super::*;
+
+    #[test]
+    fn render() {
+        let config: config::Config = r#"
+            [sources.local]
+            type = "file"
+            path = "dump.csv"
+
+            [sources.remote]
+            type = "http"
+            url = "https://example.com/dump.csv"
+
+            [sets.blocked]
+            source = "local"
+            types = ["ipv4", "ipv4_network"]
+        "#.parse()
+            .unwrap();
+        let mut state = daemon::State::new(&config);
+
+        let text = "Updated: 2017-12-01 12:00:00 +0000\n\
+                    192.0.2.1 | 192.0.2.2 | 198.51.100.0/24;example.com;;Court;1;2017-01-01\n\
+                    192.0.2.3;;;Court;2;2017-01-01\n";
+        let mut reader = zicsv::Reader::from_reader(text.as_bytes()).unwrap();
+        let list = source::List {
+            updated: *zicsv::GenericReader::get_timestamp(&reader),
+            records: reader.records().collect::<Result<_, _>>().unwrap(),
+        };
+        {
+            let source_state = state.sources.get_mut("local").unwrap();
+            source_state.list = Some(std::rc::Rc::new(list));
+            source_state.refreshed = Some(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_512_130_000));
+            source_state.fetches = 3;
+            source_state.fetch_failures = 2;
+            source_state.parse_errors = 1;
+            let _ = source_state.rejections.insert("max_shrink", 2);
+        }
+        state.sources.get_mut("remote").unwrap().failures = 4;
+        {
+            let set_state = state.sets.get_mut("blocked").unwrap();
+            set_state.applied = Some(sets::Applied {
+                entries: ["192.0.2.1/32", "198.51.100.0/24"]
+                    .iter()
+                    .map(|entry| entry.parse().unwrap())
+                    .collect(),
+                deltas: 1,
+            });
+            set_state.last_update = Some(Ok(sets::Changes {
+                entries: 2,
+                added: 1,
+                deleted: 3,
+                replaced: false,
+            }));
+            set_state.last_update_duration = Some(std::time::Duration::from_millis(250));
+        }
+
+        let now = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_512_133_600);
+        let output = super::render(&state, now);
+        for line in &[
+            "# HELP addrsetd_source_fetches_total Number of finished refreshes of source, including failed ones.",
+            "# TYPE addrsetd_source_fetches_total counter",
+            "addrsetd_source_fetches_total{source=\"local\"} 3",
+            "addrsetd_source_fetches_total{source=\"remote\"} 0",
+            "addrsetd_source_fetch_failures_total{source=\"local\"} 2",
+            "addrsetd_source_parse_errors_total{source=\"local\"} 1",
+            "# TYPE addrsetd_source_rejections_total counter",
+            "addrsetd_source_rejections_total{source=\"local\",rule=\"max_shrink\"} 2",
+            "addrsetd_source_consecutive_failures{source=\"remote\"} 4",
+            "addrsetd_source_last_success_timestamp_seconds{source=\"local\"} 1512130000",
+            "addrsetd_source_list_updated_timestamp_seconds{source=\"local\"} 1512129600",
+            "# TYPE addrsetd_source_list_age_seconds gauge",
+            "addrsetd_source_list_age_seconds{source=\"local\"} 4000",
+            "addrsetd_source_records{source=\"local\",type=\"ipv4\"} 2",
+            "addrsetd_source_records{source=\"local\",type=\"ipv4_network\"} 1",
+            "addrsetd_source_records{source=\"local\",type=\"url\"} 0",
+            "addrsetd_source_addresses{source=\"local\",type=\"ipv4\"} 3",
+            "addrsetd_source_addresses{source=\"local\",type=\"domain\"} 1",
+            "addrsetd_set_entries{set=\"blocked\"} 2",
+            "addrsetd_set_deltas{set=\"blocked\"} 1",
+            "addrsetd_set_last_update_replaced{set=\"blocked\"} 0",
+            "addrsetd_set_last_update_added_entries{set=\"blocked\"} 1",
+            "addrsetd_set_last_update_deleted_entries{set=\"blocked\"} 3",
+            "addrsetd_set_last_update_duration_seconds{set=\"blocked\"} 0.25",
+            "addrsetd_set_update_failures_total{set=\"blocked\"} 0",
+        ] {
+            assert!(output.lines().any(|output_line| output_line == *line), "{}\n{}", line, output);
+        }
+        assert!(!output.contains("addrsetd_source_list_age_seconds{source=\"remote\"}"));
+    }
+}
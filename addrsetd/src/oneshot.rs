@@ -0,0 +1,165 @@
+//! One-shot mode, for runs from cron or timers: every source is refreshed once, sets are updated from fetched lists,
+//! summary is printed and exit code tells the outcome. Work is done by the same code as in daemon mode, except that
+//! next refreshes are not scheduled. Contents of sets left by previous run are loaded first, so only changes are
+//! applied. Cached lists are applied before refresh, so sets are filled even if sources are unreachable. Names of
+//! sets of resolved names are resolved once, after all sources are refreshed.
+
+use tokio_core;
+
+use daemon;
+use resolver;
+use scheduler;
+
+/// Nothing is changed in sets.
+pub const EXIT_UNCHANGED: i32 = 0;
+/// Any source or set failed. The same code is used for errors which prevent run at all.
+pub const EXIT_FAILED: i32 = 1;
+/// Some sets are changed, all sources and sets succeeded.
+pub const EXIT_APPLIED: i32 = 2;
+
+/// Lines of summary and exit code.
+fn summary(state: &daemon::State) -> (Vec<String>, i32) {
+    let mut lines = Vec::new();
+    let mut failed = false;
+    let mut applied = false;
+
+    for (name, source_state) in &state.sources {
+        if source_state.failures != 0 {
+            failed = true;
+            lines.push(format!("Source \"{}\": refresh failed", name));
+        }
+    }
+
+    for (name, set_state) in &state.sets {
+        let line = match set_state.last_update {
+            None => {
+                failed = true;
+                "not updated, list is not fetched".into()
+            },
+            Some(Err(ref error)) => {
+                failed = true;
+                format!("update failed: {}", error)
+            },
+            Some(Ok(ref changes)) if changes.replaced => {
+                applied = true;
+                format!("replaced, {} entries", changes.entries)
+            },
+            Some(Ok(ref changes)) if !changes.is_empty() => {
+                applied = true;
+                format!(
+                    "{} entries added, {} deleted, {} entries",
+                    changes.added, changes.deleted, changes.entries
+                )
+            },
+            Some(Ok(ref changes)) => format!("unchanged, {} entries", changes.entries),
+        };
+        lines.push(format!("Set \"{}\": {}", name, line));
+    }
+
+    let code = if failed {
+        EXIT_FAILED
+    } else if applied {
+        EXIT_APPLIED
+    } else {
+        EXIT_UNCHANGED
+    };
+    (lines, code)
+}
+
+/// Refresh all sources, update sets and print summary to stdout. Returns exit code.
+#[cfg_attr(feature = "cargo-clippy", allow(print_stdout))]
+pub fn run(core: &mut tokio_core::reactor::Core, daemon: &daemon::Daemon) -> i32 {
+    daemon.state.borrow_mut().load_sets();
+    daemon.state.borrow_mut().load_caches();
+
+    let sources: Vec<String> = daemon.state.borrow().sources.keys().cloned().collect();
+    // Refreshes never fail, errors are logged and remembered in state.
+    // Names are resolved once, after all refreshes.
+    daemon.resolving.set(true);
+    let _ = core.run(scheduler::run_once(daemon, &sources));
+    let _ = core.run(resolver::run_once(daemon));
+
+    let (lines, code) = summary(&daemon.state.borrow());
+    for line in lines {
+        println!("{}", line);
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use config;
+    use daemon;
+    use sets;
+
+    use super::*;
+
+    #[test]
+    fn summary() {
+        let config: config::Config = r#"
+            [sources.local]
+            type = "file"
+            path = "dump.csv"
+
+            [sets.a]
+            source = "local"
+            types = ["ipv4"]
+
+            [sets.b]
+            source = "local"
+            types = ["ipv4"]
+        "#.parse()
+            .unwrap();
+        let mut state = daemon::State::new(&config);
+        let changes = |added, deleted, replaced| {
+            Some(Ok(sets::Changes {
+                entries: 10,
+                added,
+                deleted,
+                replaced,
+            }))
+        };
+
+        state.sets.get_mut("a").unwrap().last_update = changes(0, 0, false);
+        assert_eq!(super::summary(&state).1, EXIT_FAILED);
+
+        state.sets.get_mut("b").unwrap().last_update = changes(0, 0, false);
+        assert_eq!(
+            super::summary(&state),
+            (
+                vec![
+                    "Set \"a\": unchanged, 10 entries".into(),
+                    "Set \"b\": unchanged, 10 entries".into(),
+                ],
+                EXIT_UNCHANGED
+            )
+        );
+
+        state.sets.get_mut("a").unwrap().last_update = changes(2, 1, false);
+        state.sets.get_mut("b").unwrap().last_update = changes(0, 0, true);
+        assert_eq!(
+            super::summary(&state),
+            (
+                vec![
+                    "Set \"a\": 2 entries added, 1 deleted, 10 entries".into(),
+                    "Set \"b\": replaced, 10 entries".into(),
+                ],
+                EXIT_APPLIED
+            )
+        );
+
+        state.sets.get_mut("b").unwrap().last_update = Some(Err("Too many entries".into()));
+        state.sources.get_mut("local").unwrap().failures = 1;
+        assert_eq!(
+            super::summary(&state),
+            (
+                vec![
+                    "Source \"local\": refresh failed".into(),
+                    "Set \"a\": 2 entries added, 1 deleted, 10 entries".into(),
+                    "Set \"b\": update failed: Too many entries".into(),
+                ],
+                EXIT_FAILED
+            )
+        );
+    }
+}
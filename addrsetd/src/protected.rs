@@ -0,0 +1,223 @@
+//! Addresses which are never added to sets, whatever list says, e.g. own infrastructure or public resolvers:
+//!
+//! * Entries inside protected networks are dropped, entries containing them are split into smaller networks around
+//!   them.
+//! * Records naming protected domain or its subdomain give no entries at all. Wildcard domains are protected if they
+//!   cover protected domain or any of its subdomains.
+//!
+//! Each exclusion is logged together with record which requested it.
+
+use std;
+
+use failure;
+use ipnet;
+use zicsv;
+
+use config;
+
+/// Protected networks and domains, parsed.
+#[derive(Clone, Debug, Default)]
+pub struct Protected {
+    networks: Vec<ipnet::IpNet>,
+    /// Lowercase, without trailing dot.
+    domains: Vec<String>,
+}
+
+fn normalize_domain(domain: &str) -> String {
+    domain.trim_right_matches('.').to_lowercase()
+}
+
+/// Whether `domain` is equal to `parent` or is its subdomain.
+fn is_subdomain(domain: &str, parent: &str) -> bool {
+    domain == parent || (domain.ends_with(parent) && domain[..domain.len() - parent.len()].ends_with('.'))
+}
+
+impl Protected {
+    pub fn new(config: &config::Protected) -> Result<Self, failure::Error> {
+        Ok(Self {
+            networks: config.parse_networks()?,
+            domains: config.domains.iter().map(|domain| normalize_domain(domain)).collect(),
+        })
+    }
+
+    /// Protected domain covered by domain or wildcard domain, if any.
+    pub fn domain(&self, address: &zicsv::Address) -> Option<&str> {
+        let (domain, wildcard) = match *address {
+            zicsv::Address::DomainName(ref domain) => (normalize_domain(domain), false),
+            zicsv::Address::WildcardDomainName(ref domain) => (normalize_domain(domain.trim_left_matches("*.")), true),
+            _ => return None,
+        };
+
+        self.domains
+            .iter()
+            .find(|protected| is_subdomain(&domain, protected) || (wildcard && is_subdomain(protected, &domain)))
+            .map(String::as_str)
+    }
+
+    /// Protected domain covering name, e.g. host of URL, if any.
+    pub fn name(&self, name: &str) -> Option<&str> {
+        let name = normalize_domain(name);
+        self.domains
+            .iter()
+            .find(|protected| is_subdomain(&name, protected))
+            .map(String::as_str)
+    }
+
+    /// Protected domain named in record, if any.
+    pub fn record_domain(&self, record: &zicsv::Record) -> Option<&str> {
+        record.addresses.iter().filter_map(|address| self.domain(address)).next()
+    }
+
+    /// Parts of network which remain after removal of protected networks, and protected networks which overlap it.
+    pub fn carve(&self, network: ipnet::IpNet) -> (Vec<ipnet::IpNet>, Vec<ipnet::IpNet>) {
+        let overlapping: Vec<_> = self.networks
+            .iter()
+            .filter(|protected| protected.contains(&network) || network.contains(*protected))
+            .cloned()
+            .collect();
+
+        let mut remaining = Vec::new();
+        if !overlapping.is_empty() {
+            split(network, &overlapping, &mut remaining);
+        } else {
+            remaining.push(network);
+        }
+        (remaining, overlapping)
+    }
+
+    /// Parts of entry of set which are not protected. Exclusion is logged along with origin of entry, e.g. record.
+    pub fn exclude<Origin: std::fmt::Display>(
+        &self,
+        set: &str,
+        entry: ipnet::IpNet,
+        origin: Origin,
+    ) -> Vec<ipnet::IpNet> {
+        let (remaining, overlapping) = self.carve(entry);
+        if overlapping.is_empty() {
+            return remaining;
+        }
+
+        let overlapping: Vec<_> = overlapping.iter().map(|network| format!("{}", network)).collect();
+        if remaining.is_empty() {
+            info!(
+                "Set \"{}\": {} from {} excluded, protected by {}",
+                set,
+                entry,
+                origin,
+                overlapping.join(", ")
+            );
+        } else {
+            info!(
+                "Set \"{}\": {} from {} split into {} entries around protected {}",
+                set,
+                entry,
+                origin,
+                remaining.len(),
+                overlapping.join(", ")
+            );
+        }
+        remaining
+    }
+}
+
+/// Split network into halves until they do not overlap protected networks.
+fn split(network: ipnet::IpNet, protected: &[ipnet::IpNet], remaining: &mut Vec<ipnet::IpNet>) {
+    if protected.iter().any(|protected| protected.contains(&network)) {
+        return;
+    }
+    if !protected.iter().any(|protected| network.contains(protected)) {
+        remaining.push(network);
+        return;
+    }
+
+    // Network contains protected one, so its prefix is not the longest one and it can be split.
+    for half in network
+        .subnets(network.prefix_len() + 1)
+        .expect("Prefix length is checked above")
+    {
+        split(half, protected, remaining);
+    }
+}
+
+/// Record in log messages.
+pub struct Record<'a>(pub &'a zicsv::Record);
+
+impl<'a> std::fmt::Display for Record<'a> {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            formatter,
+            "record {} ({}, {})",
+            self.0.document_id, self.0.organization, self.0.document_date
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn protected() -> Protected {
+        Protected::new(&config::Protected {
+            networks: vec!["192.0.2.0/25".into(), "198.51.100.1".into(), "2001:db8::/33".into()],
+            domains: vec!["Example.com.".into(), "cdn.example.net".into()],
+        }).unwrap()
+    }
+
+    fn formatted(networks: &[ipnet::IpNet]) -> Vec<String> {
+        networks.iter().map(|network| format!("{}", network)).collect()
+    }
+
+    #[test]
+    fn carve() {
+        let protected = protected();
+        let carve = |network: &str| {
+            let (remaining, overlapping) = protected.carve(network.parse().unwrap());
+            (formatted(&remaining), formatted(&overlapping))
+        };
+
+        assert_eq!(carve("203.0.113.0/24"), (vec!["203.0.113.0/24".into()], vec![]));
+        assert_eq!(carve("2001:db8::/32"), (vec!["2001:db8:8000::/33".into()], vec!["2001:db8::/33".into()]));
+        assert_eq!(carve("2001:db9::1/128"), (vec!["2001:db9::1/128".into()], vec![]));
+        assert_eq!(carve("192.0.2.1/32"), (vec![], vec!["192.0.2.0/25".into()]));
+        assert_eq!(carve("192.0.2.0/24"), (vec!["192.0.2.128/25".into()], vec!["192.0.2.0/25".into()]));
+        assert_eq!(
+            carve("198.51.100.0/30"),
+            (
+                vec!["198.51.100.0/32".into(), "198.51.100.2/31".into()],
+                vec!["198.51.100.1/32".into()],
+            )
+        );
+
+        let (remaining, overlapping) = protected.carve("0.0.0.0/0".parse().unwrap());
+        assert_eq!(overlapping.len(), 2);
+        let size: u64 = remaining.iter().map(|network| 1 << (32 - network.prefix_len())).sum();
+        assert_eq!(size, (1 << 32) - 128 - 1);
+        assert!(!remaining.iter().any(|network| overlapping.iter().any(|protected| network.contains(protected))));
+    }
+
+    #[test]
+    fn domain() {
+        let protected = protected();
+        let domain = |address| protected.domain(&address).map(String::from);
+
+        assert_eq!(
+            domain(zicsv::Address::DomainName("example.com".into())),
+            Some("example.com".into())
+        );
+        assert_eq!(
+            domain(zicsv::Address::DomainName("WWW.Example.COM.".into())),
+            Some("example.com".into())
+        );
+        assert_eq!(domain(zicsv::Address::DomainName("notexample.com".into())), None);
+        assert_eq!(domain(zicsv::Address::DomainName("example.net".into())), None);
+        assert_eq!(
+            domain(zicsv::Address::WildcardDomainName("*.example.net".into())),
+            Some("cdn.example.net".into())
+        );
+        assert_eq!(domain(zicsv::Address::WildcardDomainName("*.other.example.net".into())), None);
+        assert_eq!(domain(zicsv::Address::IPv4("192.0.2.1".parse().unwrap())), None);
+
+        assert_eq!(protected.name("Www.Example.com"), Some("example.com"));
+        assert_eq!(protected.name("example.net"), None);
+    }
+}
@@ -0,0 +1,736 @@
+//! Resolution of domain names for sets of resolved names, i.e. sets of "domain", "wildcard_domain" and "url" types.
+//! Names are taken from records of list which match filter of set: domain names, wildcard domains, and hosts of URLs
+//! unless they are IP addresses. Internationalized names are converted to ASCII. Names of protected domains are not
+//! resolved.
+//!
+//! Names are resolved with A or AAAA queries, depending on family of set, through configured servers, limited number
+//! at once. Each address stays in set until its TTL, limited by `min_ttl` and `max_ttl`, and grace period pass. Name is
+//! resolved again once the shortest TTL of its addresses passes, so addresses which are still returned stay in sets,
+//! while addresses which are not returned anymore are removed after grace period. Names without addresses are
+//! resolved again after `min_ttl`, failed resolutions are retried after `retry_interval`, addresses are kept
+//! meanwhile until they expire.
+//!
+//! Resolutions are kept in memory only. Set is updated once all its names are resolved, or failed to resolve several
+//! times in a row, so contents left by previous run are not lost at start if servers are unreachable for a while.
+//!
+//! Sets also get addresses from answers of forwarder to queries of their names, see `forwarder` module. Such addresses
+//! expire the same way. Wildcard domains are matched by forwarder only, they are never resolved. Without resolver,
+//! sets get forwarded addresses only.
+
+use std;
+
+use failure;
+use futures;
+use idna;
+use ipnet;
+use tokio_core;
+use zicsv;
+
+use config;
+use daemon;
+use dns;
+use local;
+use protected;
+use scheduler;
+use sets;
+use source;
+
+/// Interval of checks for names due for resolution and for expired addresses, in seconds.
+const CHECK_INTERVAL: u64 = 10;
+/// Number of consecutive failures after which name which was never resolved does not hold updates of sets.
+const MAX_UNSETTLED_FAILURES: u32 = 3;
+
+/// Name and family of its addresses.
+pub type Key = (String, config::AddressFamily);
+
+/// Addresses and times when they expire.
+pub type Addresses = std::collections::BTreeMap<std::net::IpAddr, std::time::Instant>;
+
+/// Limits of TTL of addresses and grace period, in seconds.
+#[derive(Clone, Copy, Debug)]
+pub struct Ttl {
+    pub min: u64,
+    pub max: u64,
+    pub grace: u64,
+}
+
+impl Ttl {
+    fn limit(&self, ttl: u32) -> u64 {
+        std::cmp::max(self.min, std::cmp::min(u64::from(ttl), self.max))
+    }
+}
+
+impl From<&config::Resolver> for Ttl {
+    fn from(config: &config::Resolver) -> Self {
+        Self {
+            min: config.min_ttl,
+            max: config.max_ttl,
+            grace: config.grace,
+        }
+    }
+}
+
+impl From<&config::Forwarder> for Ttl {
+    fn from(config: &config::Forwarder) -> Self {
+        Self {
+            min: config.min_ttl,
+            max: config.max_ttl,
+            grace: config.grace,
+        }
+    }
+}
+
+/// Add addresses from answer, expiration of known ones is extended. Returns the shortest TTL of answer, limited.
+pub fn add_addresses(
+    addresses: &mut Addresses,
+    answer: &[dns::Address],
+    ttl: Ttl,
+    now: std::time::Instant,
+) -> Option<u64> {
+    for address in answer {
+        let expires = now + std::time::Duration::from_secs(ttl.limit(address.ttl) + ttl.grace);
+        let current = addresses.entry(address.address).or_insert(expires);
+        *current = std::cmp::max(*current, expires);
+    }
+    answer.iter().map(|address| ttl.limit(address.ttl)).min()
+}
+
+/// Remove expired addresses. Returns whether any address is removed.
+fn expire_addresses(addresses: &mut Addresses, now: std::time::Instant) -> bool {
+    let count = addresses.len();
+    addresses.retain(|_, expires| *expires > now);
+    addresses.len() != count
+}
+
+/// Addresses of name of one family.
+#[derive(Clone, Debug)]
+pub struct Resolution {
+    pub addresses: Addresses,
+    /// Time of next resolution.
+    pub next: std::time::Instant,
+    /// Whether name was resolved successfully at least once.
+    pub resolved: bool,
+    /// Number of consecutive failures.
+    pub failures: u32,
+    /// Error of last resolution, if it failed.
+    pub last_error: Option<String>,
+}
+
+impl Resolution {
+    fn new(now: std::time::Instant) -> Self {
+        Self {
+            addresses: Addresses::new(),
+            next: now,
+            resolved: false,
+            failures: 0,
+            last_error: None,
+        }
+    }
+
+    /// Remember result of resolution. Expiration of known addresses is extended, other ones are kept until they expire.
+    fn record(
+        &mut self,
+        config: &config::Resolver,
+        result: Result<Vec<dns::Address>, String>,
+        now: std::time::Instant,
+    ) {
+        match result {
+            Ok(addresses) => {
+                let next = add_addresses(&mut self.addresses, &addresses, Ttl::from(config), now);
+                self.next = now + std::time::Duration::from_secs(next.unwrap_or(config.min_ttl));
+                self.resolved = true;
+                self.failures = 0;
+                self.last_error = None;
+            },
+            Err(error) => {
+                self.next = now + std::time::Duration::from_secs(config.retry_interval);
+                self.failures = self.failures.saturating_add(1);
+                self.last_error = Some(error);
+            },
+        }
+    }
+
+    /// Whether name is resolved, or is given up for now.
+    fn settled(&self) -> bool {
+        self.resolved || self.failures >= MAX_UNSETTLED_FAILURES
+    }
+}
+
+/// Names listed for set of resolved names.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Names {
+    /// Domain names and hosts of URLs. Resolved by resolver and matched by forwarder.
+    pub names: std::collections::BTreeSet<String>,
+    /// Wildcard domains, without "*.". Only matched by forwarder.
+    pub wildcards: std::collections::BTreeSet<String>,
+}
+
+impl Names {
+    /// Whether name, lowercase and without trailing dot, is listed: it is equal to listed name, or it is subdomain of
+    /// wildcard domain.
+    pub fn matches(&self, name: &str) -> bool {
+        if self.names.contains(name) {
+            return true;
+        }
+
+        let mut suffix = name;
+        while let Some(position) = suffix.find('.') {
+            suffix = &suffix[position + 1..];
+            if self.wildcards.contains(suffix) {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// Addresses known for names: resolved ones and ones from answers of forwarder.
+#[derive(Clone, Copy)]
+pub struct Known<'a> {
+    pub resolutions: &'a std::collections::BTreeMap<Key, Resolution>,
+    pub forwarded: &'a std::collections::BTreeMap<Key, Addresses>,
+}
+
+/// Name in log messages.
+pub struct Name<'a>(pub &'a str);
+
+impl<'a> std::fmt::Display for Name<'a> {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter, "name \"{}\"", self.0)
+    }
+}
+
+/// Name from address of record, in ASCII, lowercase and without trailing dot. Wildcard domains are taken without "*.".
+fn name(address: &zicsv::Address) -> Option<Result<String, String>> {
+    let name = match *address {
+        zicsv::Address::DomainName(ref name) => name.trim_right_matches('.'),
+        zicsv::Address::WildcardDomainName(ref name) => name.trim_left_matches("*.").trim_right_matches('.'),
+        // Hosts of URLs are converted to ASCII already, IP addresses are not domains.
+        zicsv::Address::URL(ref url) => url.domain()?.trim_right_matches('.'),
+        _ => return None,
+    };
+    Some(idna::domain_to_ascii(name).map_err(|_| name.to_string()))
+}
+
+/// Add name from address to names, unless it is protected. Origin of address, e.g. record, is used in log messages.
+fn add_name<Origin: std::fmt::Display>(
+    set: &str,
+    protected: &protected::Protected,
+    protected_domain: Option<&str>,
+    address: &zicsv::Address,
+    origin: Origin,
+    names: &mut Names,
+) {
+    let name = match name(address) {
+        Some(Ok(name)) => name,
+        Some(Err(name)) => {
+            info!("Set \"{}\": invalid name \"{}\" from {} skipped", set, name, origin);
+            return;
+        },
+        None => return,
+    };
+
+    if let Some(domain) = protected_domain.or_else(|| protected.name(&name)) {
+        info!(
+            "Set \"{}\": {} from {} excluded, domain \"{}\" is protected",
+            set,
+            Name(&name),
+            origin,
+            domain
+        );
+        return;
+    }
+
+    let _ = match *address {
+        zicsv::Address::WildcardDomainName(_) => names.wildcards.insert(name),
+        _ => names.names.insert(name),
+    };
+}
+
+fn names_no_context(
+    set: &str,
+    config: &config::Set,
+    filter: Option<&zicsv::Filter>,
+    local: &local::Active,
+    list: &source::List,
+) -> Result<Names, failure::Error> {
+    let protected = protected::Protected::new(&local.protected)?;
+    let mut names = Names::default();
+
+    for record in &list.records {
+        let protected_domain = protected.record_domain(record);
+        for address in &record.addresses {
+            if config.types.contains(&address.address_type())
+                && filter.is_none_or(|filter| filter.matches_address(record, address))
+            {
+                let origin = protected::Record(record);
+                add_name(set, &protected, protected_domain, address, origin, &mut names);
+            }
+        }
+    }
+
+    for address in &local.denied {
+        if config.types.contains(&address.address_type()) {
+            let protected_domain = protected.domain(address);
+            add_name(set, &protected, protected_domain, address, local::Denied(address), &mut names);
+        }
+    }
+
+    Ok(names)
+}
+
+/// Names listed for set of resolved names, taken from list and local denylist.
+pub fn names(
+    set: &str,
+    config: &config::Set,
+    filter: Option<&zicsv::Filter>,
+    local: &local::Active,
+    list: &source::List,
+) -> Result<Names, failure::Error> {
+    names_no_context(set, config, filter, local, list).map_err(|error| error.context("Names to resolve").into())
+}
+
+/// Whether all names are settled, see `Resolution::settled()`. Without resolver names are not resolved at all.
+pub fn all_settled(
+    config: Option<&config::Resolver>,
+    names: &Names,
+    family: config::AddressFamily,
+    resolutions: &std::collections::BTreeMap<Key, Resolution>,
+) -> bool {
+    config.is_none() || names
+        .names
+        .iter()
+        .all(|name| {
+            resolutions
+                .get(&(name.clone(), family))
+                .is_some_and(Resolution::settled)
+        })
+}
+
+/// Entries of set: unexpired addresses of its names, resolved or forwarded, without protected ones.
+fn entries(
+    set: &str,
+    config: &config::Set,
+    protected: &protected::Protected,
+    names: &Names,
+    known: Known,
+    now: std::time::Instant,
+) -> std::collections::BTreeSet<ipnet::IpNet> {
+    let mut entries = std::collections::BTreeSet::new();
+    let mut add = |name: &str, addresses: &Addresses| {
+        for (address, expires) in addresses {
+            if *expires > now {
+                entries.extend(protected.exclude(set, ipnet::IpNet::from(*address), Name(name)));
+            }
+        }
+    };
+
+    for name in &names.names {
+        if let Some(resolution) = known.resolutions.get(&(name.clone(), config.address_family)) {
+            add(name, &resolution.addresses);
+        }
+    }
+    for (&(ref name, family), addresses) in known.forwarded {
+        if family == config.address_family && names.matches(name) {
+            add(name, addresses);
+        }
+    }
+
+    entries
+}
+
+/// Bring contents of set of resolved names in accordance with addresses currently known for its names, see
+/// `sets::apply()`.
+pub fn update(
+    backend: &mut ::backend::Backend,
+    name: &str,
+    config: &config::Set,
+    protected: &config::Protected,
+    names: &Names,
+    known: Known,
+    applied: &mut Option<sets::Applied>,
+) -> Result<sets::Changes, failure::Error> {
+    let protected = protected::Protected::new(protected)?;
+    let entries = entries(name, config, &protected, names, known, std::time::Instant::now());
+    sets::apply(backend, name, config, entries, applied)
+}
+
+/// Names of all sets of resolved names.
+fn used(state: &daemon::State) -> std::collections::BTreeSet<Key> {
+    let mut used = std::collections::BTreeSet::new();
+    for set_state in state.sets.values() {
+        if let Some(ref names) = set_state.names {
+            used.extend(names.names.iter().map(|name| (name.clone(), set_state.config.address_family)));
+        }
+    }
+    used
+}
+
+/// Resolve name through servers in order, each one is queried given number of times until answer is received.
+fn resolve(
+    servers: &[std::net::SocketAddr],
+    attempts: u32,
+    name: &str,
+    family: config::AddressFamily,
+    timeout: std::time::Duration,
+) -> Result<Vec<dns::Address>, failure::Error> {
+    let mut last_error = format_err!("No servers");
+    for server in servers {
+        for _ in 0..attempts {
+            match dns::query(server, name, family, timeout) {
+                Ok(addresses) => return Ok(addresses),
+                Err(error) => last_error = error,
+            }
+        }
+    }
+    Err(last_error)
+}
+
+/// Forget expired addresses and names not used by sets anymore, resolve names which are due in thread pool, and
+/// update sets of resolved names. Completes when all names are resolved.
+pub fn run_once(daemon: &daemon::Daemon) -> Box<futures::Future<Item = (), Error = ()>> {
+    use self::futures::Future;
+
+    let (config, due) = {
+        let mut state = daemon.state.borrow_mut();
+        let now = std::time::Instant::now();
+        let mut expired = false;
+        for addresses in state.forwarded.values_mut() {
+            expired |= expire_addresses(addresses, now);
+        }
+        state.forwarded.retain(|_, addresses| !addresses.is_empty());
+
+        let used = used(&state);
+        state.resolutions.retain(|key, _| used.contains(key));
+        for resolution in state.resolutions.values_mut() {
+            expired |= expire_addresses(&mut resolution.addresses, now);
+        }
+
+        let config = state.config.resolver.clone();
+        let due: Vec<Key> = match config {
+            Some(_) => used.into_iter()
+                .filter(|key| state.resolutions.get(key).is_none_or(|resolution| resolution.next <= now))
+                .collect(),
+            None => Vec::new(),
+        };
+        match config {
+            Some(config) if !due.is_empty() => (config, due),
+            _ => {
+                if expired {
+                    state.update_resolved_sets();
+                    daemon.notifier.update(&state);
+                }
+                return Box::new(futures::future::ok(()));
+            },
+        }
+    };
+
+    debug!("Resolver: resolving {} name(s)...", due.len());
+    let started = std::time::Instant::now();
+    // Servers are checked by validation of configuration.
+    let servers = config.parse_servers().unwrap_or_default();
+    let timeout = std::time::Duration::from_secs(config.timeout);
+
+    let resolutions: Vec<_> = due.into_iter()
+        .map(|key| {
+            let servers = servers.clone();
+            let attempts = config.attempts;
+            daemon.resolver_pool.spawn_fn(move || {
+                let result = resolve(&servers, attempts, &key.0, key.1, timeout).map_err(|error| ::error_chain(&error));
+                Ok::<_, ()>((key, result))
+            })
+        })
+        .collect();
+
+    let daemon = daemon.clone();
+    Box::new(futures::future::join_all(resolutions).map(move |results| finish(&daemon, results, started.elapsed())))
+}
+
+/// Remember results of resolutions and update sets of resolved names.
+fn finish(
+    daemon: &daemon::Daemon,
+    results: Vec<(Key, Result<Vec<dns::Address>, String>)>,
+    duration: std::time::Duration,
+) {
+    let mut state = daemon.state.borrow_mut();
+    // Resolver may be removed by reload meanwhile.
+    let config = match state.config.resolver {
+        Some(ref config) => config.clone(),
+        None => return,
+    };
+
+    let now = std::time::Instant::now();
+    let count = results.len();
+    let mut failed = 0;
+    for ((name, family), result) in results {
+        match result {
+            Ok(ref addresses) => debug!(
+                "Resolver: {} resolved to {} {:?} address(es)",
+                Name(&name),
+                addresses.len(),
+                family
+            ),
+            Err(ref error) => {
+                failed += 1;
+                debug!("Resolver: {}: {:?} resolution failed: {}", Name(&name), family, error);
+            },
+        }
+        state
+            .resolutions
+            .entry((name, family))
+            .or_insert_with(|| Resolution::new(now))
+            .record(&config, result, now);
+    }
+    info!(
+        "Resolver: {} resolution(s) done in {:.3} s, {} failed",
+        count,
+        scheduler::as_seconds(duration),
+        failed
+    );
+
+    state.update_resolved_sets();
+    daemon.notifier.update(&state);
+}
+
+/// Start resolution unless previous one is still running, e.g. once names of sets are changed.
+pub fn check(daemon: &daemon::Daemon) {
+    use self::futures::Future;
+
+    if daemon.resolving.replace(true) {
+        return;
+    }
+    let resolving = daemon.resolving.clone();
+    daemon.handle.spawn(run_once(daemon).then(move |_| {
+        resolving.set(false);
+        Ok(())
+    }));
+}
+
+/// Start resolving names of sets, now and periodically.
+pub fn start(daemon: &daemon::Daemon) -> Result<(), failure::Error> {
+    use self::futures::Stream;
+    use self::futures::Future;
+
+    check(daemon);
+
+    let daemon_clone = daemon.clone();
+    daemon.handle.spawn(
+        tokio_core::reactor::Interval::new(std::time::Duration::from_secs(CHECK_INTERVAL), &daemon.handle)?
+            .for_each(move |_| {
+                check(&daemon_clone);
+                Ok(())
+            })
+            .map_err(|error| error!("Resolver: stopped: {}", error)),
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+
+    use super::*;
+
+    fn resolver_config() -> config::Resolver {
+        config::Resolver {
+            servers: vec!["192.0.2.53".into()],
+            timeout: 5,
+            attempts: 2,
+            concurrency: 16,
+            grace: 300,
+            min_ttl: 60,
+            max_ttl: 3600,
+            retry_interval: 30,
+        }
+    }
+
+    fn address(address: &str, ttl: u32) -> dns::Address {
+        dns::Address {
+            address: address.parse().unwrap(),
+            ttl,
+        }
+    }
+
+    #[test]
+    fn names() {
+        let config: config::Config = r#"
+            [sources.local]
+            type = "file"
+            path = "dump.csv"
+
+            [sets.blocked]
+            source = "local"
+            types = ["domain", "url"]
+            filter = "organization != \"Skipped\""
+
+            [resolver]
+            servers = ["192.0.2.53"]
+
+            [protected]
+            domains = ["example.org"]
+
+            [[local.allow]]
+            address = "www.example.net"
+            comment = "Exempted"
+
+            [[local.deny]]
+            address = "Local.example.com"
+            comment = "Denied"
+
+            [[local.deny]]
+            address = "192.0.2.1"
+            comment = "Not a name"
+        "#.parse()
+            .unwrap();
+        // Lists are in CP1251, the third record names "пример.рф".
+        let text: &[u8] = b"Updated: 2017-12-01 12:00:00 +0000\n\
+                            192.0.2.1;Example.COM.;http://www.example.com/path;Court;1;2017-01-01\n\
+                            ;;https://192.0.2.2/path | https://[2001:db8::1]/;Court;2;2017-01-01\n\
+                            ;\xef\xf0\xe8\xec\xe5\xf0.\xf0\xf4;https://www.example.net;Court;3;2017-01-01\n\
+                            ;example.org;http://example.net/;Court;4;2017-01-01\n\
+                            ;;https://cdn.example.org/;Court;5;2017-01-01\n\
+                            ;skipped.example.com;;Skipped;6;2017-01-01\n";
+        let list = source::List::parse(Box::new(zicsv::Reader::from_reader(text).unwrap())).unwrap();
+
+        let local = local::active(&config, local::today());
+        let config = &config.sets["blocked"];
+        let filter = config.compile_filter().unwrap();
+        let names = super::names("blocked", config, filter.as_ref(), &local, &list).unwrap();
+        assert_eq!(
+            names.names.into_iter().collect::<Vec<_>>(),
+            vec!["example.com", "local.example.com", "www.example.com", "xn--e1afmkfd.xn--p1ai"]
+        );
+        assert!(names.wildcards.is_empty());
+    }
+
+    #[test]
+    fn matches() {
+        let names = Names {
+            names: vec!["example.com".into()].into_iter().collect(),
+            wildcards: vec!["example.net".into()].into_iter().collect(),
+        };
+
+        assert!(names.matches("example.com"));
+        assert!(!names.matches("www.example.com"));
+        assert!(!names.matches("example.net"));
+        assert!(names.matches("www.example.net"));
+        assert!(names.matches("cdn.www.example.net"));
+        assert!(!names.matches("notexample.net"));
+    }
+
+    #[test]
+    fn record() {
+        let config = resolver_config();
+        let now = std::time::Instant::now();
+        let seconds = std::time::Duration::from_secs;
+
+        let mut resolution = Resolution::new(now);
+        for _ in 0..MAX_UNSETTLED_FAILURES {
+            assert!(!resolution.settled());
+            resolution.record(&config, Err("timeout".into()), now);
+        }
+        assert!(resolution.settled());
+
+        let mut resolution = Resolution::new(now);
+
+        resolution.record(
+            &config,
+            Ok(vec![address("192.0.2.1", 10), address("192.0.2.2", 600), address("192.0.2.3", 86400)]),
+            now,
+        );
+        assert_eq!(resolution.next, now + seconds(60));
+        assert_eq!(
+            resolution.addresses.values().cloned().collect::<Vec<_>>(),
+            vec![now + seconds(360), now + seconds(900), now + seconds(3900)]
+        );
+
+        // Failure keeps addresses.
+        let later = now + seconds(60);
+        resolution.record(&config, Err("timeout".into()), later);
+        assert_eq!(resolution.next, later + seconds(30));
+        assert_eq!(resolution.failures, 1);
+        assert_eq!(resolution.addresses.len(), 3);
+        assert!(resolution.settled());
+
+        // Only returned addresses are extended.
+        resolution.record(&config, Ok(vec![address("192.0.2.2", 600)]), later);
+        assert_eq!(resolution.next, later + seconds(600));
+        assert_eq!(resolution.failures, 0);
+        assert_eq!(resolution.last_error, None);
+        assert_eq!(resolution.addresses[&"192.0.2.1".parse().unwrap()], now + seconds(360));
+        assert_eq!(resolution.addresses[&"192.0.2.2".parse().unwrap()], later + seconds(900));
+
+        assert!(!expire_addresses(&mut resolution.addresses, now + seconds(359)));
+        assert!(expire_addresses(&mut resolution.addresses, now + seconds(360)));
+        assert_eq!(resolution.addresses.len(), 2);
+
+        resolution.record(&config, Ok(vec![]), later);
+        assert_eq!(resolution.next, later + seconds(60));
+    }
+
+    #[test]
+    fn entries() {
+        let config: config::Config = r#"
+            [sources.local]
+            type = "file"
+            path = "dump.csv"
+
+            [sets.blocked]
+            source = "local"
+            types = ["domain"]
+
+            [resolver]
+            servers = ["192.0.2.53"]
+
+            [protected]
+            networks = ["192.0.2.128/25"]
+        "#.parse()
+            .unwrap();
+        let protected = protected::Protected::new(&config.protected).unwrap();
+        let now = std::time::Instant::now();
+        let seconds = std::time::Duration::from_secs;
+
+        let mut resolutions = std::collections::BTreeMap::new();
+        let mut resolution = Resolution::new(now);
+        resolution.record(
+            &resolver_config(),
+            Ok(vec![address("192.0.2.1", 600), address("192.0.2.129", 600)]),
+            now,
+        );
+        let _ = resolution.addresses.insert("192.0.2.2".parse().unwrap(), now);
+        let _ = resolutions.insert(("example.com".to_string(), config::AddressFamily::IPv4), resolution.clone());
+        let _ = resolutions.insert(("example.net".to_string(), config::AddressFamily::IPv6), resolution);
+
+        let mut forwarded = std::collections::BTreeMap::new();
+        // Wildcard domain matches subdomains only.
+        for &(name, forwarded_address) in &[("www.example.org", "198.51.100.1"), ("example.org", "198.51.100.2")] {
+            let mut addresses = Addresses::new();
+            let ttl = Ttl::from(&resolver_config());
+            let _ = add_addresses(&mut addresses, &[address(forwarded_address, 60)], ttl, now);
+            let _ = forwarded.insert((name.to_string(), config::AddressFamily::IPv4), addresses);
+        }
+
+        let names = Names {
+            names: vec!["example.com".into(), "example.net".into()].into_iter().collect(),
+            wildcards: vec!["example.org".into()].into_iter().collect(),
+        };
+        assert!(!all_settled(Some(&resolver_config()), &names, config::AddressFamily::IPv4, &resolutions));
+        assert!(all_settled(None, &names, config::AddressFamily::IPv4, &resolutions));
+
+        let entries = super::entries(
+            "blocked",
+            &config.sets["blocked"],
+            &protected,
+            &names,
+            Known {
+                resolutions: &resolutions,
+                forwarded: &forwarded,
+            },
+            now + seconds(1),
+        );
+        assert_eq!(
+            entries.into_iter().collect::<Vec<_>>(),
+            vec!["192.0.2.1/32".parse().unwrap(), "198.51.100.1/32".parse().unwrap()]
+        );
+    }
+}
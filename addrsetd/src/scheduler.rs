@@ -0,0 +1,323 @@
+use std;
+
+use failure;
+use futures;
+use rand;
+use tokio_core;
+
+use cache;
+use config;
+use daemon;
+use guards;
+use resolver;
+use source;
+
+/// Delay before next refresh, without jitter. Grows exponentially with number of consecutive failures.
+fn base_delay(config: &config::Source, failures: u32) -> std::time::Duration {
+    let seconds = if failures == 0 {
+        config.interval
+    } else {
+        let multiplier = 1u64.checked_shl(failures - 1).unwrap_or(u64::MAX);
+        std::cmp::min(
+            config.retry_interval.saturating_mul(multiplier),
+            config.max_retry_interval,
+        )
+    };
+
+    std::time::Duration::from_secs(seconds)
+}
+
+/// Random delay added to base delay. Limited by half of base delay to keep short retry intervals short.
+fn jitter(config: &config::Source, base_delay: std::time::Duration) -> std::time::Duration {
+    use self::rand::Rng;
+
+    let max_ms = std::cmp::min(
+        config.jitter.saturating_mul(1000),
+        base_delay.as_secs().saturating_mul(1000) / 2,
+    );
+    if max_ms == 0 {
+        return std::time::Duration::from_secs(0);
+    }
+
+    std::time::Duration::from_millis(rand::thread_rng().gen_range(0..=max_ms))
+}
+
+pub fn as_seconds(duration: std::time::Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1_000_000_000.0
+}
+
+/// Start refreshing of given sources immediately.
+pub fn start(daemon: &daemon::Daemon, names: &[String]) {
+    for name in names {
+        let generation = match daemon.state.borrow().sources.get(name) {
+            Some(source_state) => source_state.generation,
+            None => continue,
+        };
+
+        schedule(daemon, name.clone(), generation, std::time::Duration::from_secs(0));
+    }
+}
+
+/// Refresh given sources once, without scheduling next refreshes. Completes when all refreshes are finished.
+pub fn run_once(daemon: &daemon::Daemon, names: &[String]) -> Box<futures::Future<Item = (), Error = ()>> {
+    use self::futures::Future;
+
+    let refreshes: Vec<_> = names
+        .iter()
+        .filter_map(|name| {
+            let generation = daemon.state.borrow().sources.get(name)?.generation;
+            Some(refresh(daemon, name.clone(), generation))
+        })
+        .collect();
+    Box::new(futures::future::join_all(refreshes).map(|_| ()))
+}
+
+/// Schedule refresh of source after given delay, following refreshes are scheduled after it.
+fn schedule(daemon: &daemon::Daemon, name: String, generation: u64, delay: std::time::Duration) {
+    use self::futures::Future;
+
+    let timeout = match tokio_core::reactor::Timeout::new(delay, &daemon.handle) {
+        Ok(timeout) => timeout,
+        Err(error) => {
+            error!("Source \"{}\": unable to schedule refresh: {}", name, error);
+            return;
+        },
+    };
+
+    let daemon_clone = daemon.clone();
+    daemon.handle.spawn(timeout.then(move |_| {
+        refresh(&daemon_clone, name.clone(), generation).map(move |delay| if let Some(delay) = delay {
+            schedule(&daemon_clone, name, generation, delay);
+        })
+    }));
+}
+
+/// Find state of source, if it was not removed or replaced since refresh was scheduled.
+fn get_source_state<'a>(
+    state: &'a mut daemon::State,
+    name: &str,
+    generation: u64,
+) -> Option<&'a mut daemon::SourceState> {
+    state
+        .sources
+        .get_mut(name)
+        .and_then(|source_state| if source_state.generation == generation {
+            Some(source_state)
+        } else {
+            None
+        })
+}
+
+/// Fetch and parse list in thread pool. Results in delay before next refresh, `None` if source was removed or
+/// replaced.
+fn refresh(
+    daemon: &daemon::Daemon,
+    name: String,
+    generation: u64,
+) -> Box<futures::Future<Item = Option<std::time::Duration>, Error = ()>> {
+    use self::futures::Future;
+
+    let (source, validators, config, previous, cache_path) = {
+        let mut state = daemon.state.borrow_mut();
+        let cache_path = state.config.cache.as_ref().map(|cache| cache::path(&cache.directory, &name));
+        match get_source_state(&mut state, &name, generation) {
+            Some(source_state) => (
+                source_state.source.clone(),
+                source_state.validators.clone(),
+                source_state.config.clone(),
+                source_state.list.as_ref().map(|list| guards::Previous::from(&**list)),
+                cache_path,
+            ),
+            None => {
+                debug!("Source \"{}\": removed or replaced, not refreshing", name);
+                return Box::new(futures::future::ok(None));
+            },
+        }
+    };
+
+    debug!("Source \"{}\": refreshing...", name);
+    let started = std::time::Instant::now();
+
+    let daemon = daemon.clone();
+    let cache_name = name.clone();
+    Box::new(
+        daemon
+            .pool
+            .spawn_fn(move || {
+                let result = source::fetch_list(&*source, &validators).and_then(|result| {
+                    if let Some((ref list, _)) = result {
+                        guards::check(&config.guards, list, previous.as_ref())?;
+                    }
+                    Ok(result)
+                });
+                if let Ok(Some((ref list, ref validators))) = result {
+                    if let Some(ref cache_path) = cache_path {
+                        save_cache(&cache_name, cache_path, &config, list, validators);
+                    }
+                }
+                result
+            })
+            .then(move |result| Ok(finish(&daemon, &name, generation, started.elapsed(), result))),
+    )
+}
+
+/// Store fetched list in cache. Runs in thread pool. Errors are only logged, list is used anyway.
+fn save_cache(
+    name: &str,
+    path: &std::path::Path,
+    config: &config::Source,
+    list: &source::List,
+    validators: &source::Validators,
+) {
+    match cache::save(path, config, list, validators) {
+        Ok(()) => debug!("Source \"{}\": list saved to cache", name),
+        Err(error) => warn!("Source \"{}\": unable to save list to cache: {}", name, ::error_chain(&error)),
+    }
+}
+
+/// Remember result of refresh and update sets. Returns delay before next refresh, `None` if source was removed or
+/// replaced.
+fn finish(
+    daemon: &daemon::Daemon,
+    name: &str,
+    generation: u64,
+    duration: std::time::Duration,
+    result: Result<Option<(source::List, source::Validators)>, failure::Error>,
+) -> Option<std::time::Duration> {
+    let (delay, updated) = {
+        let mut state = daemon.state.borrow_mut();
+        let source_state = match get_source_state(&mut state, name, generation) {
+            Some(source_state) => source_state,
+            None => {
+                debug!("Source \"{}\": removed or replaced during refresh, result ignored", name);
+                return None;
+            },
+        };
+
+        let mut updated = false;
+        source_state.fetches += 1;
+        match result {
+            Ok(Some((list, validators))) => {
+                info!(
+                    "Source \"{}\": list updated {} fetched in {:.3} s, {} records, {} addresses",
+                    name,
+                    list.updated,
+                    as_seconds(duration),
+                    list.records.len(),
+                    list.addresses()
+                );
+
+                source_state.validators = validators;
+                source_state.list = Some(std::rc::Rc::new(list));
+                source_state.refreshed = Some(std::time::SystemTime::now());
+                source_state.failures = 0;
+                source_state.last_error = None;
+                updated = true;
+            },
+
+            Ok(None) => {
+                info!(
+                    "Source \"{}\": list updated {} not modified, checked in {:.3} s",
+                    name,
+                    source_state
+                        .list
+                        .as_ref()
+                        .map_or_else(|| "(unknown)".into(), |list| format!("{}", list.updated)),
+                    as_seconds(duration)
+                );
+
+                source_state.refreshed = Some(std::time::SystemTime::now());
+                source_state.failures = 0;
+                source_state.last_error = None;
+            },
+
+            Err(error) => {
+                source_state.fetch_failures += 1;
+                if source::ParseError::is(&error) {
+                    source_state.parse_errors += 1;
+                }
+                if let Some(rule) = guards::rejected_by(&error) {
+                    *source_state.rejections.entry(rule).or_insert(0) += 1;
+                }
+
+                let error = ::error_chain(&error);
+                source_state.failures = source_state.failures.saturating_add(1);
+                warn!(
+                    "Source \"{}\": refresh failed after {:.3} s ({} consecutive failure(s)): {}",
+                    name,
+                    as_seconds(duration),
+                    source_state.failures,
+                    error
+                );
+                source_state.last_error = Some(error);
+            },
+        }
+
+        let delay = base_delay(&source_state.config, source_state.failures);
+        let delay = delay + jitter(&source_state.config, delay);
+        debug!(
+            "Source \"{}\": next refresh in {:.3} s",
+            name,
+            as_seconds(delay)
+        );
+
+        if updated {
+            state.update_sets(name);
+        }
+        daemon.notifier.update(&state);
+        (delay, updated)
+    };
+    if updated {
+        // Names of sets may be changed.
+        resolver::check(daemon);
+    }
+
+    Some(delay)
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+
+    use config;
+
+    fn source_config() -> config::Source {
+        config::Source {
+            source_type: config::SourceType::File,
+            path: Some("dump.csv".into()),
+            url: None,
+            timeout: 300,
+            max_size: None,
+            interval: 3600,
+            jitter: 10,
+            retry_interval: 60,
+            max_retry_interval: 1000,
+            guards: config::Guards::default(),
+        }
+    }
+
+    #[test]
+    fn base_delay() {
+        let config = source_config();
+        let delays: Vec<u64> = [0, 1, 2, 3, 4, 5, 100]
+            .iter()
+            .map(|failures| super::base_delay(&config, *failures).as_secs())
+            .collect();
+        assert_eq!(delays, vec![3600, 60, 120, 240, 480, 960, 1000]);
+    }
+
+    #[test]
+    fn jitter() {
+        let mut config = source_config();
+        for _ in 0..100 {
+            assert!(super::jitter(&config, std::time::Duration::from_secs(3600)) <= std::time::Duration::from_secs(10));
+            assert!(super::jitter(&config, std::time::Duration::from_secs(4)) <= std::time::Duration::from_secs(2));
+        }
+
+        config.jitter = 0;
+        assert_eq!(
+            super::jitter(&config, std::time::Duration::from_secs(3600)),
+            std::time::Duration::from_secs(0)
+        );
+    }
+}
@@ -0,0 +1,633 @@
+use std;
+
+use failure;
+use ipnet;
+use zicsv;
+
+use backend;
+use config;
+use local;
+use protected;
+use scheduler;
+use source;
+
+/// Maximum number of rejected entries reported in log for single update.
+const MAX_LOGGED_ENTRY_ERRORS: usize = 10;
+
+/// Kind of set able to store addresses of configured types.
+pub fn kind(config: &config::Set) -> backend::SetKind {
+    if config.types.contains(&zicsv::AddressType::IPv4Network) {
+        backend::SetKind::Networks
+    } else {
+        backend::SetKind::Addresses
+    }
+}
+
+/// Entry of set for address of configured type.
+fn entry(config: &config::Set, address: &zicsv::Address) -> Option<ipnet::IpNet> {
+    if !config.types.contains(&address.address_type()) {
+        return None;
+    }
+    match *address {
+        zicsv::Address::IPv4(address) => Some(ipnet::IpNet::from(std::net::IpAddr::from(address))),
+        zicsv::Address::IPv4Network(network) => Some(ipnet::IpNet::V4(network.trunc())),
+        _ => None,
+    }
+}
+
+/// Entries of set built from list and local denylist: addresses of configured types, matching filter if they are
+/// from list, without protected ones. Single addresses are stored as networks with longest prefix, host bits of
+/// networks are cleared.
+pub fn entries(
+    name: &str,
+    config: &config::Set,
+    filter: Option<&zicsv::Filter>,
+    protected: &protected::Protected,
+    list: &source::List,
+    denied: &[zicsv::Address],
+) -> std::collections::BTreeSet<ipnet::IpNet> {
+    let mut entries = std::collections::BTreeSet::new();
+
+    for record in &list.records {
+        let protected_domain = protected.record_domain(record);
+        for address in &record.addresses {
+            if !filter.is_none_or(|filter| filter.matches_address(record, address)) {
+                continue;
+            }
+            let entry = match entry(config, address) {
+                Some(entry) => entry,
+                None => continue,
+            };
+
+            if let Some(domain) = protected_domain {
+                info!(
+                    "Set \"{}\": {} from {} excluded, domain \"{}\" is protected",
+                    name,
+                    entry,
+                    protected::Record(record),
+                    domain
+                );
+                continue;
+            }
+
+            entries.extend(protected.exclude(name, entry, protected::Record(record)));
+        }
+    }
+
+    for address in denied {
+        if let Some(entry) = entry(config, address) {
+            entries.extend(protected.exclude(name, entry, local::Denied(address)));
+        }
+    }
+
+    entries
+}
+
+/// Contents of set after successful update.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Applied {
+    /// Entries present in set, without ones rejected by backend.
+    pub entries: std::collections::BTreeSet<ipnet::IpNet>,
+    /// Number of updates applied as changes since contents of set were replaced as a whole.
+    pub deltas: u32,
+}
+
+/// Effect of successful update.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Changes {
+    /// Number of entries in set.
+    pub entries: usize,
+    pub added: usize,
+    pub deleted: usize,
+    /// Contents were replaced as a whole, so added and deleted entries are not known.
+    pub replaced: bool,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        !self.replaced && self.added == 0 && self.deleted == 0
+    }
+}
+
+/// Contents of existing set, taken as applied by previous run, `None` if set does not exist. Set is checked to be
+/// of the right kind.
+pub fn load(
+    backend: &mut backend::Backend,
+    name: &str,
+    config: &config::Set,
+) -> Result<Option<Applied>, failure::Error> {
+    if backend.count(name)?.is_none() {
+        return Ok(None);
+    }
+
+    // Set exists already, so it is not changed, only its kind is checked and becomes known to backend.
+    backend.create(name, kind(config), config.address_family, config.max_entries)?;
+    Ok(backend.list(name)?.map(|entries| Applied { entries, deltas: 0 }))
+}
+
+fn log_errors(name: &str, errors: &[backend::EntryError]) {
+    for error in errors.iter().take(MAX_LOGGED_ENTRY_ERRORS) {
+        warn!("Set \"{}\": entry rejected: {}", name, error);
+    }
+    if errors.len() > MAX_LOGGED_ENTRY_ERRORS {
+        warn!(
+            "Set \"{}\": {} more entries rejected",
+            name,
+            errors.len() - MAX_LOGGED_ENTRY_ERRORS
+        );
+    }
+}
+
+/// Whether only changes can be applied to set: limit of such updates is not reached, and set still has contents left
+/// by previous update. Contents are compared by number of entries only, as listing of whole set is expensive.
+fn can_apply_delta(
+    backend: &mut backend::Backend,
+    name: &str,
+    config: &config::Set,
+    applied: &Applied,
+) -> Result<bool, failure::Error> {
+    if applied.deltas >= config.max_deltas {
+        return Ok(false);
+    }
+
+    match backend.count(name)? {
+        Some(count) if count == applied.entries.len() => Ok(true),
+        Some(count) => {
+            warn!(
+                "Set \"{}\": changed outside of daemon ({} entries instead of {}), replacing contents",
+                name,
+                count,
+                applied.entries.len()
+            );
+            Ok(false)
+        },
+        None => {
+            warn!("Set \"{}\": removed outside of daemon, recreating", name);
+            Ok(false)
+        },
+    }
+}
+
+/// Bring contents of set in accordance with entries built from list and local denylist, see `apply()`. Filter is
+/// compiled from `config` in advance, see `config::Set::compile_filter()`.
+pub fn update(
+    backend: &mut backend::Backend,
+    name: &str,
+    config: &config::Set,
+    filter: Option<&zicsv::Filter>,
+    local: &local::Active,
+    list: &source::List,
+    applied: &mut Option<Applied>,
+) -> Result<Changes, failure::Error> {
+    let protected = protected::Protected::new(&local.protected)?;
+    let entries = entries(name, config, filter, &protected, list, &local.denied);
+    apply(backend, name, config, entries, applied)
+}
+
+/// Bring contents of set in accordance with given entries. Only changes since previous update are applied, if it is
+/// known, otherwise contents are replaced atomically. Entries rejected by backend are logged, but do not fail update.
+pub fn apply(
+    backend: &mut backend::Backend,
+    name: &str,
+    config: &config::Set,
+    entries: std::collections::BTreeSet<ipnet::IpNet>,
+    applied: &mut Option<Applied>,
+) -> Result<Changes, failure::Error> {
+    let started = std::time::Instant::now();
+
+    let mut entries: Vec<_> = entries.into_iter().collect();
+    if kind(config) == backend::SetKind::Networks && backend.requires_disjoint_entries() {
+        // Networks covered by other ones are dropped, adjacent networks are merged.
+        entries = ipnet::IpNet::aggregate(&entries);
+    }
+    ensure!(
+        entries.len() <= config.max_entries as usize,
+        "Too many entries ({}, maximum is {})",
+        entries.len(),
+        config.max_entries
+    );
+
+    // Contents of set are unknown until update succeeds.
+    let previous = match applied.take() {
+        Some(previous) => if can_apply_delta(backend, name, config, &previous)? {
+            Some(previous)
+        } else {
+            None
+        },
+        None => None,
+    };
+
+    let mut current: std::collections::BTreeSet<_> = entries.iter().cloned().collect();
+    let (deltas, mut changes) = match previous {
+        Some(previous) => {
+            let deleted: Vec<_> = previous.entries.difference(&current).cloned().collect();
+            let added: Vec<_> = current.difference(&previous.entries).cloned().collect();
+
+            let mut errors = backend.delete(name, &deleted)?;
+            // Entries which are not deleted remain in set.
+            for error in &errors {
+                let _ = current.insert(error.entry);
+            }
+            let deleted_count = deleted.len() - errors.len();
+
+            let add_errors = backend.add(name, &added)?;
+            for error in &add_errors {
+                let _ = current.remove(&error.entry);
+            }
+            let added_count = added.len() - add_errors.len();
+            errors.extend(add_errors);
+
+            log_errors(name, &errors);
+            info!(
+                "Set \"{}\": {} entries added, {} deleted, {} entries in set, applied in {:.3} s",
+                name,
+                added_count,
+                deleted_count,
+                current.len(),
+                scheduler::as_seconds(started.elapsed())
+            );
+            let changes = Changes {
+                added: added_count,
+                deleted: deleted_count,
+                ..Changes::default()
+            };
+            (previous.deltas + 1, changes)
+        },
+        None => {
+            // Kernel refuses to create existing set again if its maximum number of entries is changed, and replacement
+            // sets new maximum anyway.
+            if backend.count(name)?.is_none() {
+                backend.create(name, kind(config), config.address_family, config.max_entries)?;
+            }
+            let errors = backend.replace(
+                name,
+                kind(config),
+                config.address_family,
+                config.max_entries,
+                &entries,
+            )?;
+            for error in &errors {
+                let _ = current.remove(&error.entry);
+            }
+
+            log_errors(name, &errors);
+            info!(
+                "Set \"{}\": {} entries applied in {:.3} s",
+                name,
+                current.len(),
+                scheduler::as_seconds(started.elapsed())
+            );
+            let changes = Changes {
+                replaced: true,
+                ..Changes::default()
+            };
+            (0, changes)
+        },
+    };
+
+    changes.entries = current.len();
+    *applied = Some(Applied {
+        entries: current,
+        deltas,
+    });
+    Ok(changes)
+}
+
+/// Add entries to set between updates, e.g. addresses from answers of forwarder. Entries present in set already are
+/// skipped. Returns number of added entries, rejected ones are logged.
+pub fn add(
+    backend: &mut backend::Backend,
+    name: &str,
+    config: &config::Set,
+    entries: std::collections::BTreeSet<ipnet::IpNet>,
+    applied: &mut Applied,
+) -> Result<usize, failure::Error> {
+    let added: Vec<_> = entries.difference(&applied.entries).cloned().collect();
+    if added.is_empty() {
+        return Ok(0);
+    }
+    ensure!(
+        applied.entries.len() + added.len() <= config.max_entries as usize,
+        "Too many entries ({}, maximum is {})",
+        applied.entries.len() + added.len(),
+        config.max_entries
+    );
+
+    let errors = backend.add(name, &added)?;
+    log_errors(name, &errors);
+    let rejected: std::collections::BTreeSet<_> = errors.iter().map(|error| error.entry).collect();
+    let count = added.len() - rejected.len();
+    applied
+        .entries
+        .extend(added.into_iter().filter(|entry| !rejected.contains(entry)));
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use backend::fake;
+    use zicsv;
+
+    use super::*;
+
+    fn list() -> source::List {
+        let text = "Updated: 2017-12-01 12:00:00 +0000\n\
+                    192.0.2.1;example.com;;Court;1;2017-01-01\n\
+                    198.51.100.7/24 | 198.51.100.9;;;Ministry;2;2017-01-02\n\
+                    192.0.2.2;;;Ministry;3;2017-01-03\n\
+                    0.0.0.0/0;;;Court;4;2017-01-04\n";
+        let mut reader = zicsv::Reader::from_reader(text.as_bytes()).unwrap();
+        source::List {
+            updated: *zicsv::GenericReader::get_timestamp(&reader),
+            records: reader.records().collect::<Result<_, _>>().unwrap(),
+        }
+    }
+
+    fn set_config(text: &str) -> config::Set {
+        let config: config::Config = format!(
+            "[sources.local]\ntype = \"file\"\npath = \"dump.csv\"\n[sets.test]\nsource = \"local\"\n{}",
+            text
+        ).parse()
+            .unwrap();
+        config.sets["test"].clone()
+    }
+
+    /// Update from `list()` without protected addresses.
+    fn update_list(
+        backend: &mut backend::Backend,
+        name: &str,
+        config: &config::Set,
+        applied: &mut Option<Applied>,
+    ) -> Result<Changes, failure::Error> {
+        let filter = config.compile_filter().unwrap();
+        super::update(backend, name, config, filter.as_ref(), &local::Active::default(), &list(), applied)
+    }
+
+    fn formatted_entries(config: &config::Set) -> Vec<String> {
+        let filter = config.compile_filter().unwrap();
+        super::entries("test", config, filter.as_ref(), &protected::Protected::default(), &list(), &[])
+            .iter()
+            .map(|entry| format!("{}", entry))
+            .collect()
+    }
+
+    #[test]
+    fn entries() {
+        let config = set_config("types = [\"ipv4\"]");
+        assert_eq!(kind(&config), backend::SetKind::Addresses);
+        assert_eq!(
+            formatted_entries(&config),
+            vec!["192.0.2.1/32", "192.0.2.2/32", "198.51.100.9/32"]
+        );
+
+        let config = set_config("types = [\"ipv4\", \"ipv4_network\"]");
+        assert_eq!(kind(&config), backend::SetKind::Networks);
+        assert_eq!(
+            formatted_entries(&config),
+            vec![
+                "0.0.0.0/0",
+                "192.0.2.1/32",
+                "192.0.2.2/32",
+                "198.51.100.0/24",
+                "198.51.100.9/32",
+            ]
+        );
+
+        let config = set_config("types = [\"ipv4\", \"ipv4_network\"]\nfilter = 'org == \"Court\"'");
+        assert_eq!(formatted_entries(&config), vec!["0.0.0.0/0", "192.0.2.1/32"]);
+    }
+
+    #[test]
+    fn entries_protected() {
+        let protected = protected::Protected::new(&config::Protected {
+            networks: vec!["198.51.100.0/25".into()],
+            domains: vec!["example.com".into()],
+        }).unwrap();
+        let formatted_entries = |config: &config::Set| -> Vec<String> {
+            let filter = config.compile_filter().unwrap();
+            super::entries("test", config, filter.as_ref(), &protected, &list(), &[])
+                .iter()
+                .map(|entry| format!("{}", entry))
+                .collect()
+        };
+
+        let config = set_config("types = [\"ipv4\", \"ipv4_network\"]\nfilter = 'org == \"Ministry\"'");
+        assert_eq!(formatted_entries(&config), vec!["192.0.2.2/32", "198.51.100.128/25"]);
+
+        // Network covering protected one is split around it.
+        let config = set_config("types = [\"ipv4_network\"]\nfilter = 'org == \"Court\"'");
+        let entries = formatted_entries(&config);
+        assert_eq!(entries.len(), 25);
+        assert_eq!(entries[0], "0.0.0.0/1");
+        assert!(entries.contains(&"198.51.100.128/25".into()));
+        assert!(!entries.iter().any(|entry| entry.starts_with("198.51.100.0/")));
+    }
+
+    #[test]
+    fn entries_local() {
+        let protected = protected::Protected::new(&config::Protected {
+            networks: vec!["203.0.113.0/25".into()],
+            domains: vec![],
+        }).unwrap();
+        let denied = vec![
+            zicsv::Address::IPv4("203.0.113.1".parse().unwrap()),
+            zicsv::Address::IPv4("203.0.113.129".parse().unwrap()),
+            zicsv::Address::IPv4Network("192.0.2.0/24".parse().unwrap()),
+            zicsv::Address::DomainName("example.com".into()),
+        ];
+
+        // Filter does not apply to local entries, protected addresses do.
+        let config = set_config("types = [\"ipv4\"]\nfilter = 'org == \"Court\"'");
+        let filter = config.compile_filter().unwrap();
+        let entries: Vec<String> = super::entries("test", &config, filter.as_ref(), &protected, &list(), &denied)
+            .iter()
+            .map(|entry| format!("{}", entry))
+            .collect();
+        assert_eq!(entries, vec!["192.0.2.1/32", "203.0.113.129/32"]);
+    }
+
+    #[test]
+    fn update() {
+        let fake = fake::Fake::new();
+        let mut backend = fake.clone();
+
+        let config = set_config("types = [\"ipv4\"]");
+        assert_eq!(update_list(&mut backend, "test", &config, &mut None).unwrap().entries, 3);
+        assert_eq!(fake.sets.borrow()["test"].kind, backend::SetKind::Addresses);
+        assert_eq!(fake.sets.borrow()["test"].max_entries, 1_048_576);
+        assert_eq!(
+            fake.entries("test"),
+            vec!["192.0.2.1/32", "192.0.2.2/32", "198.51.100.9/32"]
+        );
+
+        // Old entries are replaced.
+        let config = set_config("types = [\"ipv4\"]\nfilter = 'org == \"Ministry\"'");
+        assert_eq!(update_list(&mut backend, "test", &config, &mut None).unwrap().entries, 2);
+        assert_eq!(fake.entries("test"), vec!["192.0.2.2/32", "198.51.100.9/32"]);
+
+        // Rejected entries do not fail update.
+        let config = set_config("types = [\"ipv4\", \"ipv4_network\"]");
+        assert_eq!(update_list(&mut backend, "networks", &config, &mut None).unwrap().entries, 4);
+        assert_eq!(
+            fake.entries("networks"),
+            vec!["192.0.2.1/32", "192.0.2.2/32", "198.51.100.0/24", "198.51.100.9/32"]
+        );
+
+        // Set of different kind can not be reused.
+        assert!(update_list(&mut backend, "test", &config, &mut None).is_err());
+        assert_eq!(fake.entries("test"), vec!["192.0.2.2/32", "198.51.100.9/32"]);
+
+        // Nothing is applied if entries do not fit.
+        let config = set_config("types = [\"ipv4\"]\nmax_entries = 1");
+        let error = update_list(&mut backend, "test", &config, &mut None).unwrap_err();
+        assert_eq!(format!("{}", error), "Too many entries (3, maximum is 1)");
+        assert_eq!(fake.entries("test"), vec!["192.0.2.2/32", "198.51.100.9/32"]);
+
+        // Existing set gets new maximum number of entries with its contents.
+        let config = set_config("types = [\"ipv4\"]\nmax_entries = 10");
+        assert_eq!(update_list(&mut backend, "test", &config, &mut None).unwrap().entries, 3);
+        assert_eq!(fake.sets.borrow()["test"].max_entries, 10);
+    }
+
+    #[test]
+    fn update_disjoint() {
+        let fake = fake::Fake::disjoint();
+        let mut backend = fake.clone();
+
+        let config = set_config("types = [\"ipv4\", \"ipv4_network\"]\nfilter = 'org == \"Ministry\"'");
+        assert_eq!(update_list(&mut backend, "test", &config, &mut None).unwrap().entries, 2);
+        assert_eq!(fake.entries("test"), vec!["192.0.2.2/32", "198.51.100.0/24"]);
+
+        // Sets of single addresses are not aggregated.
+        let config = set_config("types = [\"ipv4\"]");
+        assert_eq!(update_list(&mut backend, "addresses", &config, &mut None).unwrap().entries, 3);
+        assert_eq!(
+            fake.entries("addresses"),
+            vec!["192.0.2.1/32", "192.0.2.2/32", "198.51.100.9/32"]
+        );
+    }
+
+    #[test]
+    fn update_delta() {
+        let fake = fake::Fake::new();
+        let mut backend = fake.clone();
+        let mut applied = None;
+        let court = set_config("types = [\"ipv4\"]\nfilter = 'org == \"Court\"'");
+        let all = set_config("types = [\"ipv4\"]");
+
+        assert_eq!(update_list(&mut backend, "test", &court, &mut applied).unwrap().entries, 1);
+        assert_eq!(applied.as_ref().unwrap().deltas, 0);
+
+        assert_eq!(update_list(&mut backend, "test", &all, &mut applied).unwrap().entries, 3);
+        assert_eq!(applied.as_ref().unwrap().deltas, 1);
+        assert_eq!(
+            fake.entries("test"),
+            vec!["192.0.2.1/32", "192.0.2.2/32", "198.51.100.9/32"]
+        );
+
+        assert_eq!(update_list(&mut backend, "test", &court, &mut applied).unwrap().entries, 1);
+        assert_eq!(
+            applied,
+            Some(Applied {
+                entries: vec!["192.0.2.1/32".parse().unwrap()].into_iter().collect(),
+                deltas: 2,
+            })
+        );
+        assert_eq!(fake.entries("test"), vec!["192.0.2.1/32"]);
+
+        // Changes made by somebody else cause replacement of contents.
+        let _ = fake.sets.borrow_mut().get_mut("test").unwrap().entries.insert("203.0.113.1/32".parse().unwrap());
+        assert_eq!(update_list(&mut backend, "test", &court, &mut applied).unwrap().entries, 1);
+        assert_eq!(applied.as_ref().unwrap().deltas, 0);
+        assert_eq!(fake.entries("test"), vec!["192.0.2.1/32"]);
+
+        let _ = fake.sets.borrow_mut().remove("test");
+        assert_eq!(update_list(&mut backend, "test", &court, &mut applied).unwrap().entries, 1);
+        assert_eq!(applied.as_ref().unwrap().deltas, 0);
+        assert_eq!(fake.entries("test"), vec!["192.0.2.1/32"]);
+
+        // Failed update does not change set.
+        let limited = set_config("types = [\"ipv4\"]\nmax_entries = 2\nmax_deltas = 1");
+        assert!(update_list(&mut backend, "test", &limited, &mut applied).is_err());
+        assert_eq!(applied.as_ref().unwrap().deltas, 0);
+
+        // Contents are replaced after configured number of updates.
+        let limited = set_config("types = [\"ipv4\"]\nfilter = 'org == \"Ministry\"'\nmax_deltas = 1");
+        assert_eq!(update_list(&mut backend, "test", &limited, &mut applied).unwrap().entries, 2);
+        assert_eq!(applied.as_ref().unwrap().deltas, 1);
+        assert_eq!(update_list(&mut backend, "test", &limited, &mut applied).unwrap().entries, 2);
+        assert_eq!(applied.as_ref().unwrap().deltas, 0);
+        assert_eq!(fake.entries("test"), vec!["192.0.2.2/32", "198.51.100.9/32"]);
+
+        // Rejected entries are not counted as present in set.
+        let networks = set_config("types = [\"ipv4\", \"ipv4_network\"]");
+        let mut applied = None;
+        assert_eq!(update_list(&mut backend, "networks", &networks, &mut applied).unwrap().entries, 4);
+        assert_eq!(update_list(&mut backend, "networks", &networks, &mut applied).unwrap().entries, 4);
+        assert_eq!(applied.as_ref().unwrap().deltas, 1);
+    }
+
+    #[test]
+    fn add() {
+        let fake = fake::Fake::new();
+        let mut backend = fake.clone();
+        let court = set_config("types = [\"ipv4\"]\nfilter = 'org == \"Court\"'\nmax_entries = 3");
+        let mut applied = None;
+        assert_eq!(update_list(&mut backend, "test", &court, &mut applied).unwrap().entries, 1);
+        let mut applied = applied.unwrap();
+
+        let entries = |entries: &[&str]| entries.iter().map(|entry| entry.parse().unwrap()).collect();
+        let added = entries(&["192.0.2.1/32", "203.0.113.1/32"]);
+        assert_eq!(super::add(&mut backend, "test", &court, added, &mut applied).unwrap(), 1);
+        assert_eq!(fake.entries("test"), vec!["192.0.2.1/32", "203.0.113.1/32"]);
+        assert_eq!(applied.entries.len(), 2);
+        assert_eq!(applied.deltas, 0);
+
+        let added = entries(&["203.0.113.2/32", "203.0.113.3/32"]);
+        let error = super::add(&mut backend, "test", &court, added, &mut applied).unwrap_err();
+        assert_eq!(format!("{}", error), "Too many entries (4, maximum is 3)");
+        assert_eq!(applied.entries.len(), 2);
+
+        // Set is still in sync for next update.
+        assert_eq!(update_list(&mut backend, "test", &court, &mut Some(applied)).unwrap().deleted, 1);
+        assert_eq!(fake.entries("test"), vec!["192.0.2.1/32"]);
+    }
+
+    #[test]
+    fn load() {
+        let fake = fake::Fake::new();
+        let mut backend = fake.clone();
+        let all = set_config("types = [\"ipv4\"]");
+        let court = set_config("types = [\"ipv4\"]\nfilter = 'org == \"Court\"'");
+
+        assert_eq!(super::load(&mut backend, "test", &all).unwrap(), None);
+        assert!(fake.sets.borrow().is_empty());
+        assert_eq!(
+            update_list(&mut backend, "test", &all, &mut None).unwrap(),
+            Changes {
+                entries: 3,
+                added: 0,
+                deleted: 0,
+                replaced: true,
+            }
+        );
+
+        // Contents left by previous run are only changed.
+        let mut applied = super::load(&mut backend, "test", &all).unwrap();
+        assert_eq!(applied.as_ref().unwrap().entries.len(), 3);
+        assert!(update_list(&mut backend, "test", &all, &mut applied).unwrap().is_empty());
+        assert_eq!(
+            update_list(&mut backend, "test", &court, &mut applied).unwrap(),
+            Changes {
+                entries: 1,
+                added: 0,
+                deleted: 2,
+                replaced: false,
+            }
+        );
+        assert_eq!(fake.entries("test"), vec!["192.0.2.1/32"]);
+
+        let networks = set_config("types = [\"ipv4\", \"ipv4_network\"]");
+        assert!(super::load(&mut backend, "test", &networks).is_err());
+    }
+}
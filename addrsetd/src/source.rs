@@ -0,0 +1,542 @@
+use std;
+
+use failure;
+use flate2;
+use ureq;
+use zicsv;
+
+use config;
+
+/// Information about previously fetched list used to avoid fetching and parsing of unchanged list.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Validators {
+    /// HTTP "ETag".
+    pub etag: Option<String>,
+    /// HTTP "Last-Modified", or modification times and sizes of local files.
+    pub last_modified: Option<String>,
+}
+
+/// Result of fetching.
+pub enum Fetched {
+    /// List was not modified since previous fetch.
+    NotModified,
+    /// New list and its validators. Validators should be remembered only if list was parsed successfully.
+    Modified(Box<zicsv::GenericReader>, Validators),
+}
+
+/// Source of list.
+pub trait Source {
+    /// Fetch list if it was modified since fetch described by given validators.
+    fn fetch(&self, validators: &Validators) -> Result<Fetched, failure::Error>;
+}
+
+/// Create source from configuration.
+pub fn create(config: &config::Source) -> std::sync::Arc<Source + Send + Sync> {
+    // Presence of path or URL is checked during validation of configuration.
+    let path = std::path::PathBuf::from(config.path.clone().unwrap_or_default());
+    let url = config.url.clone().unwrap_or_default();
+
+    match config.source_type {
+        config::SourceType::File => std::sync::Arc::new(File { path }),
+        config::SourceType::Directory => std::sync::Arc::new(Directory { path }),
+        config::SourceType::Http => std::sync::Arc::new(Http::new(
+            &url,
+            std::time::Duration::from_secs(config.timeout),
+            config.max_size,
+        )),
+    }
+}
+
+/// Parsed list.
+#[derive(Debug)]
+pub struct List {
+    /// Date of last update of this list.
+    pub updated: zicsv::DateTime,
+    pub records: Vec<zicsv::Record>,
+}
+
+impl List {
+    /// Read and parse all records.
+    pub fn parse(mut reader: Box<zicsv::GenericReader>) -> Result<Self, failure::Error> {
+        let updated = *reader.get_timestamp();
+        let records = reader.records_boxed().collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self { updated, records })
+    }
+
+    /// Total number of addresses in all records.
+    pub fn addresses(&self) -> usize {
+        self.records.iter().map(|record| record.addresses.len()).sum()
+    }
+}
+
+/// Context of errors of parsing, to tell them from errors of fetching. List is read while it is parsed, so errors of
+/// reading are reported as errors of parsing too.
+#[derive(Debug)]
+pub struct ParseError;
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("Unable to parse list")
+    }
+}
+
+impl ParseError {
+    /// Check if error is error of parsing.
+    pub fn is(error: &failure::Error) -> bool {
+        error.downcast_ref::<failure::Context<Self>>().is_some()
+    }
+}
+
+/// Fetch and parse list. Returns `None` if list was not modified since fetch described by given validators.
+pub fn fetch_list(source: &Source, validators: &Validators) -> Result<Option<(List, Validators)>, failure::Error> {
+    match source.fetch(validators)? {
+        Fetched::Modified(reader, validators) => {
+            let list = List::parse(reader).map_err(|error| error.context(ParseError))?;
+            Ok(Some((list, validators)))
+        },
+        Fetched::NotModified => Ok(None),
+    }
+}
+
+fn file_fingerprint(path: &std::path::Path) -> Result<String, failure::Error> {
+    let metadata = std::fs::metadata(path)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_else(|_| std::time::Duration::from_secs(0));
+
+    Ok(format!(
+        "{}.{:09}/{}",
+        modified.as_secs(),
+        modified.subsec_nanos(),
+        metadata.len()
+    ))
+}
+
+fn local_fetched(
+    validators: &Validators,
+    last_modified: String,
+    open: &Fn() -> Result<Box<zicsv::GenericReader>, failure::Error>,
+) -> Result<Fetched, failure::Error> {
+    if validators.last_modified.as_ref() == Some(&last_modified) {
+        return Ok(Fetched::NotModified);
+    }
+
+    Ok(Fetched::Modified(
+        open()?,
+        Validators {
+            etag: None,
+            last_modified: Some(last_modified),
+        },
+    ))
+}
+
+/// Single local CSV file.
+struct File {
+    path: std::path::PathBuf,
+}
+
+impl File {
+    fn fetch_no_context(&self, validators: &Validators) -> Result<Fetched, failure::Error> {
+        local_fetched(validators, file_fingerprint(&self.path)?, &|| {
+            Ok(Box::new(zicsv::Reader::from_reader(std::fs::File::open(&self.path)?)?))
+        })
+    }
+}
+
+impl Source for File {
+    fn fetch(&self, validators: &Validators) -> Result<Fetched, failure::Error> {
+        self.fetch_no_context(validators)
+            .map_err(|error| error.context(format!("File \"{}\"", self.path.display())).into())
+    }
+}
+
+/// Local directory with list split into several parts, "*.csv" files concatenated in order of their names.
+struct Directory {
+    path: std::path::PathBuf,
+}
+
+impl Directory {
+    fn parts(&self) -> Result<Vec<std::path::PathBuf>, failure::Error> {
+        let mut parts = Vec::new();
+        for entry in std::fs::read_dir(&self.path)? {
+            let path = entry?.path();
+            if path.is_file() && path.extension().is_some_and(|extension| extension == "csv") {
+                parts.push(path);
+            }
+        }
+        ensure!(!parts.is_empty(), "No \"*.csv\" files found");
+
+        parts.sort();
+        Ok(parts)
+    }
+
+    fn fetch_no_context(&self, validators: &Validators) -> Result<Fetched, failure::Error> {
+        use std::io::Read;
+
+        let parts = self.parts()?;
+
+        let mut fingerprints = Vec::with_capacity(parts.len());
+        for path in &parts {
+            fingerprints.push(format!(
+                "{}:{}",
+                path.file_name().unwrap_or_default().to_string_lossy(),
+                file_fingerprint(path)?
+            ));
+        }
+
+        local_fetched(validators, fingerprints.join(" "), &|| {
+            let mut reader: Box<std::io::Read> = Box::new(std::io::empty());
+            for path in &parts {
+                let part = std::fs::File::open(path)
+                    .map_err(|error| failure::Error::from(error).context(format!("File \"{}\"", path.display())))?;
+                reader = Box::new(reader.chain(part));
+            }
+
+            Ok(Box::new(zicsv::Reader::from_reader(reader)?))
+        })
+    }
+}
+
+impl Source for Directory {
+    fn fetch(&self, validators: &Validators) -> Result<Fetched, failure::Error> {
+        self.fetch_no_context(validators)
+            .map_err(|error| error.context(format!("Directory \"{}\"", self.path.display())).into())
+    }
+}
+
+/// HTTP(S) URL. Uses conditional requests and gzip compression.
+///
+/// Lists are fetched with blocking client in thread pool together with parsing, which needs pool anyway. Client of
+/// hyper used by API and metrics can not be used here: the only TLS connector for its `tokio-core` based version,
+/// `hyper-tls` 0.1, depends on `native-tls` 0.1, which requires yanked releases of `security-framework`.
+struct Http {
+    url: String,
+    agent: ureq::Agent,
+    max_size: Option<u64>,
+}
+
+impl Http {
+    fn new(url: &str, timeout: std::time::Duration, max_size: Option<u64>) -> Self {
+        Self {
+            url: url.into(),
+            agent: ureq::AgentBuilder::new().timeout(timeout).build(),
+            max_size,
+        }
+    }
+
+    fn fetch_no_context(&self, validators: &Validators) -> Result<Fetched, failure::Error> {
+        use std::io::Read;
+
+        let mut request = self.agent.get(&self.url).set("Accept-Encoding", "gzip");
+        if let Some(ref etag) = validators.etag {
+            request = request.set("If-None-Match", etag);
+        }
+        if let Some(ref last_modified) = validators.last_modified {
+            request = request.set("If-Modified-Since", last_modified);
+        }
+
+        let response = request.call().map_err(|error| match error {
+            ureq::Error::Status(status, response) => format_err!("HTTP status {} {}", status, response.status_text()),
+            error => error.into(),
+        })?;
+
+        match response.status() {
+            304 => return Ok(Fetched::NotModified),
+            200 => (),
+            status => bail!("Unexpected HTTP status {} {}", status, response.status_text()),
+        }
+
+        let content_length = match response.header("Content-Length") {
+            Some(value) => Some(
+                value
+                    .parse::<u64>()
+                    .map_err(|_| format_err!("Invalid Content-Length: \"{}\"", value))?,
+            ),
+            None => None,
+        };
+        if let (Some(content_length), Some(max_size)) = (content_length, self.max_size) {
+            ensure!(
+                content_length <= max_size,
+                "Content-Length is too big: {} (should be not more than {})",
+                content_length,
+                max_size
+            );
+        }
+
+        let new_validators = Validators {
+            etag: response.header("ETag").map(|value| value.into()),
+            last_modified: response.header("Last-Modified").map(|value| value.into()),
+        };
+        let gzipped = match response.header("Content-Encoding") {
+            Some(encoding) if encoding.eq_ignore_ascii_case("gzip") => true,
+            Some(encoding) if encoding.eq_ignore_ascii_case("identity") => false,
+            Some(encoding) => bail!("Unsupported Content-Encoding: \"{}\"", encoding),
+            None => false,
+        };
+
+        let mut body = Vec::new();
+        let limit = self.max_size.map_or(u64::MAX, |max_size| max_size + 1);
+        let _ = response.into_reader().take(limit).read_to_end(&mut body)?;
+        if let Some(max_size) = self.max_size {
+            ensure!(
+                body.len() as u64 <= max_size,
+                "Response is too big (should be not more than {} bytes)",
+                max_size
+            );
+        }
+        if let Some(content_length) = content_length {
+            ensure!(
+                body.len() as u64 == content_length,
+                "Truncated response: received {} bytes instead of {}",
+                body.len(),
+                content_length
+            );
+        }
+
+        // Limit applies to decompressed body too, otherwise small compressed response could take all memory.
+        let body = if gzipped {
+            let mut decompressed = Vec::new();
+            let _ = flate2::read::GzDecoder::new(&body[..])
+                .take(limit)
+                .read_to_end(&mut decompressed)?;
+            if let Some(max_size) = self.max_size {
+                ensure!(
+                    decompressed.len() as u64 <= max_size,
+                    "Decompressed response is too big (should be not more than {} bytes)",
+                    max_size
+                );
+            }
+            decompressed
+        } else {
+            body
+        };
+
+        let reader: Box<zicsv::GenericReader> = Box::new(zicsv::Reader::from_reader(std::io::Cursor::new(body))?);
+        Ok(Fetched::Modified(reader, new_validators))
+    }
+}
+
+impl Source for Http {
+    fn fetch(&self, validators: &Validators) -> Result<Fetched, failure::Error> {
+        self.fetch_no_context(validators)
+            .map_err(|error| error.context(format!("URL \"{}\"", self.url)).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+
+    use flate2;
+
+    use super::*;
+
+    const LIST: &str = "Updated: 2017-12-01 12:00:00 +0000\n1.2.3.4;;;Org;123;2017-01-01\n";
+
+    fn modified(fetched: Fetched) -> (Vec<String>, Validators) {
+        match fetched {
+            Fetched::Modified(mut reader, validators) => (
+                reader
+                    .records_boxed()
+                    .map(|record| record.unwrap().document_id)
+                    .collect(),
+                validators,
+            ),
+            Fetched::NotModified => panic!("List is not modified"),
+        }
+    }
+
+    fn is_not_modified(fetched: Fetched) -> bool {
+        match fetched {
+            Fetched::NotModified => true,
+            Fetched::Modified(..) => false,
+        }
+    }
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("addrsetd-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    /// Serve given raw HTTP responses, one per connection, and return received requests.
+    fn serve(responses: Vec<Vec<u8>>) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        use std::io::Read;
+        use std::io::Write;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/dump.csv", listener.local_addr().unwrap());
+
+        let thread = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).unwrap();
+                    assert!(n > 0);
+                    request.extend_from_slice(&buf[..n]);
+                }
+                requests.push(String::from_utf8(request).unwrap().to_lowercase());
+
+                stream.write_all(&response).unwrap();
+            }
+            requests
+        });
+
+        (url, thread)
+    }
+
+    fn response(headers: &str, body: &[u8]) -> Vec<u8> {
+        let mut response = format!("HTTP/1.1 200 OK\r\nConnection: close\r\n{}\r\n", headers).into_bytes();
+        response.extend_from_slice(body);
+        response
+    }
+
+    #[test]
+    fn file() {
+        let dir = temp_dir("file");
+        let path = dir.join("dump.csv");
+        std::fs::write(&path, LIST).unwrap();
+
+        let source = File { path: path.clone() };
+        let (ids, validators) = modified(source.fetch(&Validators::default()).unwrap());
+        assert_eq!(ids, vec!["123"]);
+        assert!(is_not_modified(source.fetch(&validators).unwrap()));
+
+        std::fs::write(&path, format!("{}1.2.3.5;;;Org;124;2017-01-01\n", LIST)).unwrap();
+        let (ids, _) = modified(source.fetch(&validators).unwrap());
+        assert_eq!(ids, vec!["123", "124"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parse_error() {
+        let dir = temp_dir("parse-error");
+        let path = dir.join("dump.csv");
+        std::fs::write(&path, format!("{}1.2.3.5;;;Org\n", LIST)).unwrap();
+
+        let source = File { path: path.clone() };
+        let error = fetch_list(&source, &Validators::default()).unwrap_err();
+        assert!(ParseError::is(&error));
+
+        std::fs::remove_file(&path).unwrap();
+        let error = fetch_list(&source, &Validators::default()).unwrap_err();
+        assert!(!ParseError::is(&error));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn directory() {
+        let dir = temp_dir("directory");
+        let (first, second) = LIST.split_at(50);
+        std::fs::write(dir.join("dump-01.csv"), second).unwrap();
+        std::fs::write(dir.join("dump-00.csv"), first).unwrap();
+        std::fs::write(dir.join("README"), "Not a part").unwrap();
+
+        let source = Directory { path: dir.clone() };
+        let (ids, validators) = modified(source.fetch(&Validators::default()).unwrap());
+        assert_eq!(ids, vec!["123"]);
+        assert!(is_not_modified(source.fetch(&validators).unwrap()));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let empty = temp_dir("directory-empty");
+        let source = Directory { path: empty.clone() };
+        assert!(source.fetch(&Validators::default()).is_err());
+        std::fs::remove_dir_all(&empty).unwrap();
+    }
+
+    #[test]
+    fn http() {
+        let not_modified = b"HTTP/1.1 304 Not Modified\r\nConnection: close\r\n\r\n".to_vec();
+        let (url, server) = serve(vec![
+            response(
+                &format!(
+                    "Content-Length: {}\r\nETag: \"v1\"\r\nLast-Modified: Fri, 01 Dec 2017 12:00:00 GMT\r\n",
+                    LIST.len()
+                ),
+                LIST.as_bytes(),
+            ),
+            not_modified,
+        ]);
+
+        let source = Http::new(&url, std::time::Duration::from_secs(10), None);
+        let (ids, validators) = modified(source.fetch(&Validators::default()).unwrap());
+        assert_eq!(ids, vec!["123"]);
+        assert_eq!(
+            validators,
+            Validators {
+                etag: Some("\"v1\"".into()),
+                last_modified: Some("Fri, 01 Dec 2017 12:00:00 GMT".into()),
+            }
+        );
+        assert!(is_not_modified(source.fetch(&validators).unwrap()));
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].contains("accept-encoding: gzip"));
+        assert!(!requests[0].contains("if-none-match"));
+        assert!(requests[1].contains("if-none-match: \"v1\""));
+        assert!(requests[1].contains("if-modified-since: fri, 01 dec 2017 12:00:00 gmt"));
+    }
+
+    #[test]
+    fn http_gzip() {
+        use std::io::Write;
+
+        let gzipped = |data: &[u8]| {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data).unwrap();
+            let body = encoder.finish().unwrap();
+            response(
+                &format!("Content-Length: {}\r\nContent-Encoding: gzip\r\n", body.len()),
+                &body,
+            )
+        };
+        let padded = format!("{}{}", LIST, "\n".repeat(100_000));
+        let (url, server) = serve(vec![gzipped(LIST.as_bytes()), gzipped(padded.as_bytes())]);
+
+        let source = Http::new(&url, std::time::Duration::from_secs(10), None);
+        let (ids, _) = modified(source.fetch(&Validators::default()).unwrap());
+        assert_eq!(ids, vec!["123"]);
+
+        // Compressed body fits into limit, decompressed one does not.
+        let limited_source = Http::new(&url, std::time::Duration::from_secs(10), Some(10_000));
+        let error = limited_source.fetch(&Validators::default()).err().unwrap();
+        assert_eq!(
+            format!("{}", error.causes().last().unwrap()),
+            "Decompressed response is too big (should be not more than 10000 bytes)"
+        );
+        let _ = server.join().unwrap();
+    }
+
+    #[test]
+    fn http_invalid() {
+        let (url, server) = serve(vec![
+            response(&format!("Content-Length: {}\r\n", LIST.len() + 10), LIST.as_bytes()),
+            response(&format!("Content-Length: {}\r\n", LIST.len()), LIST.as_bytes()),
+            b"HTTP/1.1 404 Not Found\r\nConnection: close\r\nContent-Length: 0\r\n\r\n".to_vec(),
+        ]);
+
+        let source = Http::new(&url, std::time::Duration::from_secs(10), None);
+        let limited_source = Http::new(&url, std::time::Duration::from_secs(10), Some(10));
+
+        // Truncated.
+        assert!(source.fetch(&Validators::default()).is_err());
+        // Too big.
+        assert!(limited_source.fetch(&Validators::default()).is_err());
+        // Not found.
+        assert!(source.fetch(&Validators::default()).is_err());
+        let _ = server.join().unwrap();
+
+        // Connection refused.
+        assert!(source.fetch(&Validators::default()).is_err());
+    }
+}
@@ -0,0 +1,332 @@
+//! Integration with systemd: notifications of readiness and status, watchdog and socket activation. Everything is
+//! enabled by environment variables set by service manager, nothing is done without them.
+//!
+//! With `Type=notify` readiness is reported once all sets are updated first time, or once daemon is started if
+//! `ready_on_start` is set. Status shows numbers of entries in sets and failing sources. Watchdog is pinged from
+//! reactor, so it fires if reactor is stuck.
+//!
+//! Sockets passed by socket unit are matched with control socket, HTTP API and metrics by their addresses, which
+//! should be the same as in configuration. Sockets not matched with anything are closed.
+
+use std;
+
+use failure;
+use futures;
+use listenfd;
+use tokio_core;
+
+use daemon;
+use scheduler;
+
+fn env(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+/// Check variable with PID of process for which other variables are meant. Missing variable means any process.
+fn is_for_this_process(pid: Option<String>) -> bool {
+    pid.is_none_or(|pid| pid.parse() == Ok(std::process::id()))
+}
+
+/// Sender of notifications to service manager.
+pub struct Notifier {
+    /// Socket and address of service manager, `None` if daemon is not run by it.
+    socket: Option<(std::os::unix::net::UnixDatagram, std::os::unix::net::SocketAddr)>,
+    /// Readiness is reported already.
+    ready: std::cell::Cell<bool>,
+}
+
+impl Notifier {
+    /// Notifier for address from `NOTIFY_SOCKET` environment variable.
+    pub fn from_env() -> Self {
+        Self::new(env("NOTIFY_SOCKET"))
+    }
+
+    /// Address is path of socket or, if it starts with '@', name in abstract namespace. Without address notifications
+    /// are not sent.
+    pub fn new(address: Option<String>) -> Self {
+        let socket = address.and_then(|address| match Self::connect(&address) {
+            Ok(socket) => Some(socket),
+            Err(error) => {
+                warn!("systemd: unable to use notification socket \"{}\": {}", address, error);
+                None
+            },
+        });
+
+        Self {
+            socket,
+            ready: std::cell::Cell::new(false),
+        }
+    }
+
+    fn connect(
+        address: &str,
+    ) -> Result<(std::os::unix::net::UnixDatagram, std::os::unix::net::SocketAddr), failure::Error> {
+        use std::os::linux::net::SocketAddrExt;
+
+        let address = match address.as_bytes().split_first() {
+            Some((&b'@', name)) => std::os::unix::net::SocketAddr::from_abstract_name(name)?,
+            _ => std::os::unix::net::SocketAddr::from_pathname(address)?,
+        };
+        Ok((std::os::unix::net::UnixDatagram::unbound()?, address))
+    }
+
+    fn send(&self, message: &str) {
+        if let Some((ref socket, ref address)) = self.socket {
+            if let Err(error) = socket.send_to_addr(message.as_bytes(), address) {
+                warn!("systemd: unable to send notification: {}", error);
+            }
+        }
+    }
+
+    /// Report readiness, if it is not reported yet.
+    pub fn ready(&self) {
+        if !self.ready.replace(true) {
+            self.send("READY=1\n");
+        }
+    }
+
+    /// Report status of sets and sources, and readiness once all sets are updated.
+    pub fn update(&self, state: &daemon::State) {
+        let mut message = String::new();
+        if state.sets.values().all(|set_state| set_state.applied.is_some()) && !self.ready.replace(true) {
+            message.push_str("READY=1\n");
+        }
+        message.push_str(&format!("STATUS={}\n", status(state)));
+        self.send(&message);
+    }
+
+    pub fn stopping(&self) {
+        self.send("STOPPING=1\n");
+    }
+
+    fn watchdog(&self) {
+        self.send("WATCHDOG=1\n");
+    }
+}
+
+/// One line describing sets and failing sources.
+fn status(state: &daemon::State) -> String {
+    let sets: Vec<String> = state
+        .sets
+        .iter()
+        .map(|(name, set_state)| match set_state.applied {
+            Some(ref applied) => format!("{}: {} entries", name, applied.entries.len()),
+            None => format!("{}: not updated", name),
+        })
+        .collect();
+    let failing: Vec<&str> = state
+        .sources
+        .iter()
+        .filter(|&(_, source_state)| source_state.failures != 0)
+        .map(|(name, _)| name.as_str())
+        .collect();
+
+    let mut status = format!("Sets {}", sets.join(", "));
+    if !failing.is_empty() {
+        status.push_str(&format!("; failing sources {}", failing.join(", ")));
+    }
+    status
+}
+
+/// Interval of watchdog pings: half of timeout, so single delayed ping does not trigger watchdog.
+fn watchdog_interval(
+    usec: Option<String>,
+    pid: Option<String>,
+) -> Result<Option<std::time::Duration>, failure::Error> {
+    let usec: u64 = match usec {
+        Some(usec) if is_for_this_process(pid) => usec
+            .parse()
+            .map_err(|_| format_err!("Invalid WATCHDOG_USEC \"{}\"", usec))?,
+        _ => return Ok(None),
+    };
+    ensure!(usec != 0, "Invalid WATCHDOG_USEC \"0\"");
+
+    Ok(Some(std::time::Duration::from_micros(usec / 2)))
+}
+
+/// Start pinging watchdog, if it is enabled by service manager.
+pub fn start_watchdog(daemon: &daemon::Daemon) -> Result<(), failure::Error> {
+    use self::futures::Future;
+    use self::futures::Stream;
+
+    let interval = match watchdog_interval(env("WATCHDOG_USEC"), env("WATCHDOG_PID"))? {
+        Some(interval) => interval,
+        None => return Ok(()),
+    };
+    info!("systemd: pinging watchdog every {:.3} s", scheduler::as_seconds(interval));
+
+    daemon.notifier.watchdog();
+    let notifier = daemon.notifier.clone();
+    daemon.handle.spawn(
+        tokio_core::reactor::Interval::new(interval, &daemon.handle)?
+            .for_each(move |_| {
+                notifier.watchdog();
+                Ok(())
+            })
+            .map_err(|error| error!("systemd: watchdog stopped: {}", error)),
+    );
+    Ok(())
+}
+
+/// Sockets passed by service manager, not taken yet.
+#[derive(Debug, Default)]
+pub struct Passed {
+    tcp: Vec<std::net::TcpListener>,
+    unix: Vec<std::os::unix::net::UnixListener>,
+}
+
+/// Take sockets passed by service manager. Variables are removed from environment by `listenfd`, so sockets are taken
+/// only once. Changing environment is sound only while there are no other threads, so this should be called at the
+/// very start of process, even before logging is initialized.
+pub fn listen_fds() -> Result<Passed, failure::Error> {
+    let mut listen_fd = listenfd::ListenFd::from_env();
+
+    let mut passed = Passed::default();
+    for index in 0..listen_fd.len() {
+        match listen_fd.take_tcp_listener(index) {
+            Ok(Some(listener)) => passed.tcp.push(listener),
+            _ => match listen_fd.take_unix_listener(index) {
+                Ok(Some(listener)) => passed.unix.push(listener),
+                _ => bail!("Passed socket #{} is neither TCP nor Unix stream socket", index + 1),
+            },
+        }
+    }
+    Ok(passed)
+}
+
+impl Passed {
+    /// Number of sockets not taken yet.
+    pub fn len(&self) -> usize {
+        self.tcp.len() + self.unix.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Take passed socket bound to TCP address.
+    pub fn take_tcp(&mut self, address: &std::net::SocketAddr) -> Option<std::net::TcpListener> {
+        let position = self
+            .tcp
+            .iter()
+            .position(|listener| listener.local_addr().ok().as_ref() == Some(address))?;
+
+        info!("systemd: using passed socket for {}", address);
+        Some(self.tcp.remove(position))
+    }
+
+    /// Take passed socket bound to path.
+    pub fn take_unix(&mut self, path: &str) -> Option<std::os::unix::net::UnixListener> {
+        let position = self.unix.iter().position(|listener| {
+            listener
+                .local_addr()
+                .ok()
+                .as_ref()
+                .and_then(|address| address.as_pathname())
+                == Some(std::path::Path::new(path))
+        })?;
+
+        info!("systemd: using passed socket for \"{}\"", path);
+        Some(self.unix.remove(position))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+
+    use config;
+    use sets;
+
+    use super::*;
+
+    #[test]
+    fn notifier() {
+        let dir = std::env::temp_dir().join(format!("addrsetd-test-notify-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notify.sock");
+        let socket = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+        socket.set_read_timeout(Some(std::time::Duration::from_secs(10))).unwrap();
+        let receive = || {
+            let mut buffer = [0; 1024];
+            let size = socket.recv(&mut buffer).unwrap();
+            String::from_utf8(buffer[..size].to_vec()).unwrap()
+        };
+
+        let config: config::Config = r#"
+            [sources.local]
+            type = "file"
+            path = "dump.csv"
+
+            [sets.a]
+            source = "local"
+            types = ["ipv4"]
+
+            [sets.b]
+            source = "local"
+            types = ["ipv4"]
+        "#.parse()
+            .unwrap();
+        let mut state = daemon::State::new(&config);
+        let notifier = Notifier::new(path.to_str().map(String::from));
+
+        state.sets.get_mut("a").unwrap().applied = Some(sets::Applied {
+            entries: ["192.0.2.1/32".parse().unwrap()].iter().cloned().collect(),
+            deltas: 0,
+        });
+        state.sources.get_mut("local").unwrap().failures = 1;
+        notifier.update(&state);
+        assert_eq!(receive(), "STATUS=Sets a: 1 entries, b: not updated; failing sources local\n");
+
+        state.sets.get_mut("b").unwrap().applied = Some(sets::Applied::default());
+        state.sources.get_mut("local").unwrap().failures = 0;
+        notifier.update(&state);
+        assert_eq!(receive(), "READY=1\nSTATUS=Sets a: 1 entries, b: 0 entries\n");
+
+        notifier.ready();
+        notifier.stopping();
+        assert_eq!(receive(), "STOPPING=1\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn watchdog_interval() {
+        let interval = |usec: &str, pid: Option<String>| super::watchdog_interval(Some(usec.into()), pid);
+        assert_eq!(
+            interval("30000000", Some(format!("{}", std::process::id()))).unwrap(),
+            Some(std::time::Duration::from_secs(15))
+        );
+        assert_eq!(interval("30000000", Some("1".into())).unwrap(), None);
+        assert_eq!(super::watchdog_interval(None, None).unwrap(), None);
+        assert!(interval("0", None).is_err());
+        assert!(interval("30 s", None).is_err());
+    }
+
+    #[test]
+    fn take() {
+        let dir = std::env::temp_dir().join(format!("addrsetd-test-activation-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("control.sock");
+        let path = path.to_str().unwrap();
+
+        let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = tcp.local_addr().unwrap();
+        let unix = std::os::unix::net::UnixListener::bind(path).unwrap();
+        let mut passed = Passed {
+            tcp: vec![tcp],
+            unix: vec![unix],
+        };
+        assert_eq!(passed.len(), 2);
+
+        assert!(passed.take_tcp(&"127.0.0.1:1".parse().unwrap()).is_none());
+        assert_eq!(passed.take_tcp(&address).unwrap().local_addr().unwrap(), address);
+        assert!(passed.take_unix("/run/addrsetd/control.sock").is_none());
+        assert!(passed.take_unix(path).is_some());
+        assert!(passed.is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,123 @@
+use failure;
+use regex;
+use zicsv;
+
+pub struct GrepOptions {
+    pub pattern: String,
+    pub glob: bool,
+    pub ignore_case: bool,
+    pub organization: bool,
+    pub document_id: bool,
+}
+
+/// Convert shell-like glob into anchored regular expression. Supports `*`, `?` and `[...]`.
+fn glob_into_regex(glob: &str) -> Result<String, failure::Error> {
+    let mut result = String::from("^");
+
+    let mut chars = glob.chars();
+    while let Some(chr) = chars.next() {
+        match chr {
+            '*' => result.push_str(".*"),
+            '?' => result.push('.'),
+            '[' => {
+                result.push('[');
+
+                let mut class = String::new();
+                loop {
+                    match chars.next() {
+                        Some(']') if !class.is_empty() && class != "!" => break,
+                        Some(chr) => class.push(chr),
+                        None => bail!("Unterminated character class in glob \"{}\"", glob),
+                    }
+                }
+
+                if class.starts_with('!') {
+                    result.push('^');
+                    let _ = class.remove(0);
+                }
+                for chr in class.chars() {
+                    if chr == '-' {
+                        result.push(chr);
+                    } else {
+                        result.push_str(&regex::escape(&chr.to_string()));
+                    }
+                }
+
+                result.push(']');
+            },
+            chr => result.push_str(&regex::escape(&chr.to_string())),
+        }
+    }
+
+    result.push('$');
+    Ok(result)
+}
+
+fn compile(options: &GrepOptions) -> Result<regex::Regex, failure::Error> {
+    let pattern = if options.glob {
+        glob_into_regex(&options.pattern)?
+    } else {
+        options.pattern.clone()
+    };
+
+    regex::RegexBuilder::new(&pattern)
+        .case_insensitive(options.ignore_case)
+        .build()
+        .map_err(|error| format_err!("Invalid pattern \"{}\" ({})", options.pattern, error))
+}
+
+fn is_searchable(address: &zicsv::Address) -> bool {
+    matches!(
+        *address,
+        zicsv::Address::DomainName(_) | zicsv::Address::WildcardDomainName(_) | zicsv::Address::URL(_)
+    )
+}
+
+/// Print matching domain names and URLs, one per line, followed by metadata of record (tab-separated).
+pub fn grep(options: &GrepOptions, mut reader: Box<zicsv::GenericReader>) -> Result<(), failure::Error> {
+    let regex = compile(options)?;
+
+    for record in reader.records_boxed() {
+        let record = record?;
+
+        // Match in record metadata selects all its domain names and URLs.
+        let record_matches = (options.organization && regex.is_match(&record.organization))
+            || (options.document_id && regex.is_match(&record.document_id));
+
+        for address in record.addresses.iter().filter(|address| is_searchable(address)) {
+            let address = String::from(address);
+            if record_matches || regex.is_match(&address) {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    address, record.document_id, record.document_date, record.organization
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use regex;
+
+    fn glob_matches(glob: &str, value: &str) -> bool {
+        regex::Regex::new(&super::glob_into_regex(glob).unwrap())
+            .unwrap()
+            .is_match(value)
+    }
+
+    #[test]
+    fn glob() {
+        assert!(glob_matches("*.example.com", "www.example.com"));
+        assert!(!glob_matches("*.example.com", "example.com"));
+        assert!(!glob_matches("*.example.com", "www.example.com.org"));
+        assert!(glob_matches("http://example.com/?", "http://example.com/a"));
+        assert!(glob_matches("ex[a-c]mple.com", "exbmple.com"));
+        assert!(!glob_matches("ex[!a-c]mple.com", "exbmple.com"));
+        assert!(glob_matches("ex[]]mple.com", "ex]mple.com"));
+        assert!(glob_matches("a+b(c)", "a+b(c)"));
+        assert!(super::glob_into_regex("ex[a-c").is_err());
+    }
+}
@@ -0,0 +1,315 @@
+use std;
+
+use failure;
+use git2;
+use zicsv;
+
+pub struct HistoryOptions {
+    pub repository_path: String,
+    pub revision: String,
+    pub file_path: String,
+    pub addresses: Vec<String>,
+    pub document_ids: Vec<String>,
+}
+
+#[derive(Default)]
+struct Snapshot {
+    /// `None` for missing file.
+    updated: Option<zicsv::DateTime>,
+    records: usize,
+    addresses: usize,
+    found_addresses: std::collections::HashSet<String>,
+    found_document_ids: std::collections::HashSet<String>,
+}
+
+fn short_id(commit: &git2::Commit) -> String {
+    format!("{}", commit.id()).chars().take(10).collect()
+}
+
+fn load_snapshot(options: &HistoryOptions, content: &[u8]) -> Result<Snapshot, failure::Error> {
+    let mut reader = zicsv::Reader::from_reader(content)?;
+
+    let mut snapshot = Snapshot {
+        updated: Some(*zicsv::GenericReader::get_timestamp(&reader)),
+        records: 0,
+        addresses: 0,
+        found_addresses: std::collections::HashSet::new(),
+        found_document_ids: std::collections::HashSet::new(),
+    };
+
+    for record in reader.records() {
+        let record = record?;
+
+        snapshot.records += 1;
+        snapshot.addresses += record.addresses.len();
+
+        if options.document_ids.contains(&record.document_id) {
+            let _ = snapshot.found_document_ids.insert(record.document_id.clone());
+        }
+
+        if !options.addresses.is_empty() {
+            for address in &record.addresses {
+                let address = String::from(address);
+                if options.addresses.contains(&address) {
+                    let _ = snapshot.found_addresses.insert(address);
+                }
+            }
+        }
+    }
+
+    Ok(snapshot)
+}
+
+fn print_changes(
+    output: &mut std::io::Write,
+    kind: &str,
+    tracked: &[String],
+    previous: Option<&std::collections::HashSet<String>>,
+    current: &std::collections::HashSet<String>,
+    commit_id: &str,
+    updated: &str,
+) -> Result<(), failure::Error> {
+    for value in tracked {
+        let was_present = previous.is_some_and(|previous| previous.contains(value));
+        let is_present = current.contains(value);
+
+        if was_present != is_present {
+            writeln!(
+                output,
+                "{}\t{}\t{}\t{} \"{}\"",
+                commit_id,
+                updated,
+                if is_present { "appeared" } else { "disappeared" },
+                kind,
+                value
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Walk first-parent history of local clone of the list repository from the oldest commit to the newest one.
+/// Without tracked addresses and document IDs prints timeline of list sizes, otherwise prints commits where
+/// tracked values appeared or disappeared.
+pub fn history(options: &HistoryOptions) -> Result<(), failure::Error> {
+    let stdout = std::io::stdout();
+    let mut output = stdout.lock();
+    write_history(options, &mut output)
+}
+
+/// Same as `history()`, into any output. Commits where file is missing are reported and treated as empty lists.
+/// Commits where file can not be parsed are reported too, changes in the following commits are then compared
+/// against the last parsed version.
+fn write_history(options: &HistoryOptions, output: &mut std::io::Write) -> Result<(), failure::Error> {
+    let repository = git2::Repository::open(&options.repository_path).map_err(|error| {
+        failure::Error::from(error).context(format!("Repository \"{}\"", options.repository_path))
+    })?;
+
+    let head = repository
+        .revparse_single(&options.revision)
+        .and_then(|object| object.peel_to_commit())
+        .map_err(|error| failure::Error::from(error).context(format!("Revision \"{}\"", options.revision)))?;
+
+    let mut revwalk = repository.revwalk()?;
+    revwalk.push(head.id())?;
+    revwalk.simplify_first_parent()?;
+    revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+
+    let tracking = !options.addresses.is_empty() || !options.document_ids.is_empty();
+    let file_path = std::path::Path::new(&options.file_path);
+
+    // Blob of file in previous commit, `None` if file was missing.
+    let mut previous_blob_id: Option<Option<git2::Oid>> = None;
+    let mut previous: Option<Snapshot> = None;
+    for commit_id in revwalk {
+        let commit = repository.find_commit(commit_id?)?;
+        let short_commit_id = short_id(&commit);
+
+        let blob = match commit.tree()?.get_path(file_path) {
+            Ok(entry) => Some(entry.to_object(&repository)?.peel_to_blob()?),
+            Err(ref error) if error.code() == git2::ErrorCode::NotFound => None,
+            Err(error) => return Err(error.into()),
+        };
+
+        // Unchanged file does not need to be parsed again.
+        let blob_id = blob.as_ref().map(|blob| blob.id());
+        if previous_blob_id == Some(blob_id) {
+            continue;
+        }
+        previous_blob_id = Some(blob_id);
+
+        let snapshot = match blob {
+            Some(blob) => match load_snapshot(options, blob.content()) {
+                Ok(snapshot) => snapshot,
+                Err(error) => {
+                    // Messages may quote broken lines, output should stay one line per commit.
+                    let causes: Vec<String> = error
+                        .causes()
+                        .map(|cause| format!("{}", cause).replace('\n', "\\n"))
+                        .collect();
+                    writeln!(
+                        output,
+                        "{}\t-\tunable to parse \"{}\", following changes are against last parsed version: {}",
+                        short_commit_id,
+                        options.file_path,
+                        causes.join(": ")
+                    )?;
+                    continue;
+                },
+            },
+
+            None => {
+                writeln!(output, "{}\t-\tfile \"{}\" absent", short_commit_id, options.file_path)?;
+                Snapshot::default()
+            },
+        };
+
+        let updated = snapshot
+            .updated
+            .map_or_else(|| "-".into(), |updated| format!("{}", updated));
+        if tracking {
+            print_changes(
+                output,
+                "address",
+                &options.addresses,
+                previous.as_ref().map(|snapshot| &snapshot.found_addresses),
+                &snapshot.found_addresses,
+                &short_commit_id,
+                &updated,
+            )?;
+            print_changes(
+                output,
+                "document",
+                &options.document_ids,
+                previous.as_ref().map(|snapshot| &snapshot.found_document_ids),
+                &snapshot.found_document_ids,
+                &short_commit_id,
+                &updated,
+            )?;
+        } else if snapshot.updated.is_some() {
+            writeln!(
+                output,
+                "{}\t{}\t{}\t{}",
+                short_commit_id, updated, snapshot.records, snapshot.addresses
+            )?;
+        }
+
+        previous = Some(snapshot);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+
+    use git2;
+
+    fn commit(repository: &git2::Repository, files: &[(&str, &str)]) -> String {
+        let mut builder = repository.treebuilder(None).unwrap();
+        for &(name, content) in files {
+            let blob_id = repository.blob(content.as_bytes()).unwrap();
+            let _ = builder.insert(name, blob_id, 0o100_644).unwrap();
+        }
+        let tree = repository.find_tree(builder.write().unwrap()).unwrap();
+
+        let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+        let parent = repository.head().ok().map(|head| head.peel_to_commit().unwrap());
+        let parents: Vec<&git2::Commit> = parent.iter().collect();
+        let commit_id = repository
+            .commit(Some("HEAD"), &signature, &signature, "Update", &tree, &parents)
+            .unwrap();
+        super::short_id(&repository.find_commit(commit_id).unwrap())
+    }
+
+    fn run(path: &std::path::Path, addresses: &[&str], document_ids: &[&str]) -> Vec<String> {
+        let options = super::HistoryOptions {
+            repository_path: path.to_str().unwrap().into(),
+            revision: "HEAD".into(),
+            file_path: "dump.csv".into(),
+            addresses: addresses.iter().map(|address| address.to_string()).collect(),
+            document_ids: document_ids.iter().map(|document_id| document_id.to_string()).collect(),
+        };
+        let mut output = Vec::new();
+        super::write_history(&options, &mut output).unwrap();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| line.into())
+            .collect()
+    }
+
+    #[test]
+    fn history() {
+        let path = std::env::temp_dir().join(format!("zicsv-tool-test-history-{}", std::process::id()));
+        let repository = git2::Repository::init(&path).unwrap();
+
+        let first = commit(
+            &repository,
+            &[(
+                "dump.csv",
+                "Updated: 2017-12-01 12:00:00 +0000\n1.2.3.4 | 1.2.3.5;;;Org;1;2017-01-01\n",
+            )],
+        );
+        let second = commit(
+            &repository,
+            &[
+                ("dump.csv", "Updated: 2017-12-02 12:00:00 +0000\n1.2.3.5;;;Org;2;2017-01-01\n"),
+                ("README", "1"),
+            ],
+        );
+        let _ = commit(
+            &repository,
+            &[
+                ("dump.csv", "Updated: 2017-12-02 12:00:00 +0000\n1.2.3.5;;;Org;2;2017-01-01\n"),
+                ("README", "2"),
+            ],
+        );
+        let deleted = commit(&repository, &[("README", "2")]);
+        let broken = commit(&repository, &[("dump.csv", "Broken\n")]);
+        let restored = commit(
+            &repository,
+            &[(
+                "dump.csv",
+                "Updated: 2017-12-04 12:00:00 +0000\n1.2.3.4;;;Org;1;2017-01-01\n",
+            )],
+        );
+
+        let timeline = run(&path, &[], &[]);
+        let tracked = run(&path, &["1.2.3.4", "1.2.3.5"], &["1"]);
+        std::fs::remove_dir_all(&path).unwrap();
+
+        let parse_error = timeline[3].clone();
+        assert!(parse_error.starts_with(&format!(
+            "{}\t-\tunable to parse \"dump.csv\", following changes are against last parsed version: ",
+            broken
+        )));
+        assert_eq!(
+            timeline,
+            vec![
+                format!("{}\t2017-12-01 12:00:00\t1\t2", first),
+                format!("{}\t2017-12-02 12:00:00\t1\t1", second),
+                format!("{}\t-\tfile \"dump.csv\" absent", deleted),
+                parse_error.clone(),
+                format!("{}\t2017-12-04 12:00:00\t1\t1", restored),
+            ]
+        );
+        assert_eq!(
+            tracked,
+            vec![
+                format!("{}\t2017-12-01 12:00:00\tappeared\taddress \"1.2.3.4\"", first),
+                format!("{}\t2017-12-01 12:00:00\tappeared\taddress \"1.2.3.5\"", first),
+                format!("{}\t2017-12-01 12:00:00\tappeared\tdocument \"1\"", first),
+                format!("{}\t2017-12-02 12:00:00\tdisappeared\taddress \"1.2.3.4\"", second),
+                format!("{}\t2017-12-02 12:00:00\tdisappeared\tdocument \"1\"", second),
+                format!("{}\t-\tfile \"dump.csv\" absent", deleted),
+                format!("{}\t-\tdisappeared\taddress \"1.2.3.5\"", deleted),
+                parse_error,
+                format!("{}\t2017-12-04 12:00:00\tappeared\taddress \"1.2.3.4\"", restored),
+                format!("{}\t2017-12-04 12:00:00\tappeared\tdocument \"1\"", restored),
+            ]
+        );
+    }
+}
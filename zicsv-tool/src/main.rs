@@ -11,9 +11,21 @@
 #![cfg_attr(feature = "cargo-clippy", warn(use_self))]
 #![cfg_attr(feature = "cargo-clippy", warn(used_underscore_binding))]
 
+#[cfg(feature = "parquet-export")]
+extern crate arrow;
+
 #[macro_use]
 extern crate failure;
 
+extern crate git2;
+
+#[cfg(feature = "parquet-export")]
+extern crate parquet;
+
+extern crate regex;
+
+extern crate rusqlite;
+
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
@@ -26,6 +38,14 @@ extern crate structopt_derive;
 
 extern crate zicsv;
 
+mod grep;
+mod history;
+
+#[cfg(feature = "parquet-export")]
+mod parquet_export;
+
+mod sqlite;
+
 type Records = std::collections::LinkedList<zicsv::Record>;
 
 #[derive(Serialize)]
@@ -40,6 +60,7 @@ struct SelectOptions {
     domain: bool,
     wildcard_domain: bool,
     url: bool,
+    filter: Option<zicsv::Filter>,
 }
 
 #[derive(StructOpt, Debug)]
@@ -47,6 +68,9 @@ enum Command {
     #[structopt(name = "into-json", about = "Convert into json format")]
     IntoJson {
         #[structopt(short = "P", long = "disable-pretty", help = "Disable pretty-printing")] disable_pretty: bool,
+
+        #[structopt(short = "f", long = "filter", help = "Convert only records matching filter expression")]
+        filter: Option<String>,
     },
 
     #[structopt(name = "select", about = "Print selected types of blocked addresses into stdout")]
@@ -60,9 +84,70 @@ enum Command {
         #[structopt(short = "w", long = "wildcard-domain", help = "Wildcard domain names")] wildcard_domain: bool,
 
         #[structopt(short = "u", long = "url", help = "URLs")] url: bool,
+
+        #[structopt(short = "f", long = "filter",
+                    help = "Print only addresses matching filter expression (all types if no types specified)")]
+        filter: Option<String>,
     },
 
     #[structopt(name = "updated", about = "Print date of last update")] Updated,
+
+    #[structopt(name = "grep", about = "Search domain names and URLs using regular expression or glob")]
+    Grep {
+        #[structopt(short = "g", long = "glob", help = "Pattern is a glob matching whole address")] glob: bool,
+
+        #[structopt(short = "i", long = "ignore-case", help = "Case-insensitive search")] ignore_case: bool,
+
+        #[structopt(short = "O", long = "organization", help = "Search also in organization names")]
+        organization: bool,
+
+        #[structopt(short = "D", long = "document-id", help = "Search also in document IDs")] document_id: bool,
+
+        #[structopt(help = "Regular expression (or glob with --glob)")] pattern: String,
+    },
+
+    #[structopt(name = "history", about = "Walk history of list in local git repository")]
+    History {
+        #[structopt(short = "r", long = "repository", help = "Path to local git repository", default_value = ".")]
+        repository_path: String,
+
+        #[structopt(short = "R", long = "revision", help = "Newest revision", default_value = "HEAD")]
+        revision: String,
+
+        #[structopt(short = "f", long = "file", help = "Path to list inside repository", default_value = "dump.csv")]
+        file_path: String,
+
+        #[structopt(short = "a", long = "address",
+                    help = "Show commits where address appeared or disappeared (may be repeated)")]
+        addresses: Vec<String>,
+
+        #[structopt(short = "d", long = "document-id",
+                    help = "Show commits where document appeared or disappeared (may be repeated)")]
+        document_ids: Vec<String>,
+    },
+
+    #[structopt(name = "into-sqlite", about = "Append list into SQLite database as a new snapshot")]
+    IntoSqlite {
+        #[structopt(help = "Path to database, created if it does not exist")] database_path: String,
+    },
+
+    #[cfg(feature = "parquet-export")]
+    #[structopt(name = "into-parquet", about = "Convert into Parquet format, one row per address")]
+    IntoParquet {
+        #[structopt(help = "Path to output file")] output_path: String,
+    },
+
+    #[structopt(name = "merge", about = "Merge several lists into one, combining records with the same document ID")]
+    Merge {
+        #[structopt(short = "o", long = "output", help = "Write into file instead of stdout")]
+        output_path: Option<String>,
+
+        #[structopt(short = "U", long = "updated",
+                    help = "Date of last update (\"YYYY-MM-DD HH:MM:SS\" UTC), newest from inputs by default")]
+        updated: Option<String>,
+
+        #[structopt(help = "Input files (\"-\" for stdin)")] input_paths: Vec<String>,
+    },
 }
 
 #[derive(StructOpt, Debug)]
@@ -72,24 +157,46 @@ struct Options {
     #[structopt(subcommand)] command: Command,
 }
 
-fn create_reader(options: &Options) -> Result<Box<zicsv::GenericReader>, failure::Error> {
-    Ok(if let Some(input_path) = options.input_path.as_ref() {
+fn create_reader_from_path(input_path: Option<&String>) -> Result<Box<zicsv::GenericReader>, failure::Error> {
+    Ok(if let Some(input_path) = input_path {
         Box::new(zicsv::Reader::from_file(input_path)?)
     } else {
         Box::new(zicsv::Reader::from_reader(std::io::stdin())?)
     })
 }
 
-fn load_records(mut reader: Box<zicsv::GenericReader>) -> Result<List, failure::Error> {
-    let records: Result<Records, failure::Error> = reader.records_boxed().collect();
+fn create_reader(options: &Options) -> Result<Box<zicsv::GenericReader>, failure::Error> {
+    create_reader_from_path(options.input_path.as_ref())
+}
+
+fn parse_filter(filter: Option<&String>) -> Result<Option<zicsv::Filter>, failure::Error> {
+    Ok(if let Some(filter) = filter {
+        Some(zicsv::Filter::parse(filter).map_err(|error| error.context(format!("Filter \"{}\"", filter)))?)
+    } else {
+        None
+    })
+}
+
+fn load_records(mut reader: Box<zicsv::GenericReader>, filter: Option<&zicsv::Filter>) -> Result<List, failure::Error> {
+    let records: Result<Records, failure::Error> = reader
+        .records_boxed()
+        .filter(|record| match *record {
+            Ok(ref record) => filter.is_none_or(|filter| filter.matches_record(record)),
+            Err(_) => true,
+        })
+        .collect();
     Ok(List {
         updated: *reader.get_timestamp(),
         records: records?,
     })
 }
 
-fn conv_into_json(reader: Box<zicsv::GenericReader>, disable_pretty: bool) -> Result<(), failure::Error> {
-    let list = load_records(reader)?;
+fn conv_into_json(
+    reader: Box<zicsv::GenericReader>,
+    disable_pretty: bool,
+    filter: Option<&zicsv::Filter>,
+) -> Result<(), failure::Error> {
+    let list = load_records(reader, filter)?;
 
     let json_str = if disable_pretty {
         serde_json::to_string(&list)?
@@ -120,7 +227,7 @@ fn select(options: &SelectOptions, mut reader: Box<zicsv::GenericReader>) -> Res
                 },
             };
 
-            if selected {
+            if selected && options.filter.as_ref().is_none_or(|filter| filter.matches_address(&record, address)) {
                 println!("{}", address);
             }
         }
@@ -129,15 +236,135 @@ fn select(options: &SelectOptions, mut reader: Box<zicsv::GenericReader>) -> Res
     Ok(())
 }
 
+fn merge_records(
+    merged: &mut Vec<zicsv::Record>,
+    positions: &mut std::collections::HashMap<String, usize>,
+    mut reader: Box<zicsv::GenericReader>,
+) -> Result<(), failure::Error> {
+    for record in reader.records_boxed() {
+        let mut record = record?;
+
+        // Records without document ID can not be matched, so they are always kept as is.
+        let pos = if record.document_id.is_empty() {
+            None
+        } else {
+            positions.get(&record.document_id).cloned()
+        };
+
+        if let Some(pos) = pos {
+            merged[pos].addresses.append(&mut record.addresses);
+        } else {
+            if !record.document_id.is_empty() {
+                let _ = positions.insert(record.document_id.clone(), merged.len());
+            }
+            merged.push(record);
+        }
+    }
+
+    Ok(())
+}
+
+fn merge(
+    input_paths: &[String],
+    output_path: Option<&String>,
+    updated: Option<&String>,
+) -> Result<(), failure::Error> {
+    ensure!(!input_paths.is_empty(), "At least one input file should be specified");
+    ensure!(
+        input_paths.iter().filter(|path| *path == "-").count() <= 1,
+        "Stdin may be specified only once"
+    );
+
+    let mut merged = Vec::new();
+    let mut positions = std::collections::HashMap::new();
+    let mut newest_updated = None;
+    for input_path in input_paths {
+        let path = if input_path == "-" { None } else { Some(input_path) };
+        let reader = create_reader_from_path(path)?;
+
+        let reader_updated = *reader.get_timestamp();
+        newest_updated = Some(newest_updated.map_or(reader_updated, |newest| std::cmp::max(newest, reader_updated)));
+
+        merge_records(&mut merged, &mut positions, reader)?;
+    }
+
+    let updated = if let Some(updated) = updated {
+        zicsv::DateTime::parse_from_str(updated, "%Y-%m-%d %H:%M:%S")
+            .map_err(|error| format_err!("Invalid date and time: \"{}\" ({})", updated, error))?
+    } else {
+        newest_updated.expect("No input lists")
+    };
+
+    for record in &mut merged {
+        record.addresses.sort();
+        record.addresses.dedup();
+    }
+
+    let output: Box<std::io::Write> = if let Some(output_path) = output_path {
+        let file = std::fs::File::create(output_path)
+            .map_err(|error| failure::Error::from(error).context(format!("File \"{}\"", output_path)))?;
+        Box::new(file)
+    } else {
+        Box::new(std::io::stdout())
+    };
+
+    let mut writer = zicsv::Writer::from_writer(output, &updated)?;
+    for record in &merged {
+        writer.write_record(record)?;
+    }
+    writer.flush()
+}
+
 fn real_main() -> Result<(), failure::Error> {
     use structopt::StructOpt;
 
     let options = Options::from_args();
 
+    match options.command {
+        Command::Merge {
+            ref output_path,
+            ref updated,
+            ref input_paths,
+        } => {
+            ensure!(
+                options.input_path.is_none(),
+                "Input files for merge should be specified as positional arguments"
+            );
+            return merge(input_paths, output_path.as_ref(), updated.as_ref());
+        },
+
+        Command::History {
+            ref repository_path,
+            ref revision,
+            ref file_path,
+            ref addresses,
+            ref document_ids,
+        } => {
+            ensure!(
+                options.input_path.is_none(),
+                "History is read from git repository, input file can not be specified"
+            );
+
+            let hopts = history::HistoryOptions {
+                repository_path: repository_path.clone(),
+                revision: revision.clone(),
+                file_path: file_path.clone(),
+                addresses: addresses.clone(),
+                document_ids: document_ids.clone(),
+            };
+            return history::history(&hopts);
+        },
+
+        _ => (),
+    }
+
     let reader = create_reader(&options)?;
 
     match options.command {
-        Command::IntoJson { disable_pretty, .. } => conv_into_json(reader, disable_pretty),
+        Command::IntoJson {
+            disable_pretty,
+            ref filter,
+        } => conv_into_json(reader, disable_pretty, parse_filter(filter.as_ref())?.as_ref()),
 
         Command::Select {
             ipv4,
@@ -145,23 +372,53 @@ fn real_main() -> Result<(), failure::Error> {
             domain,
             wildcard_domain,
             url,
+            ref filter,
         } => {
-            let sopts = SelectOptions {
-                ipv4,
-                ipv4_network,
-                domain,
-                wildcard_domain,
-                url,
-            };
+            let any_type = ipv4 || ipv4_network || domain || wildcard_domain || url;
             ensure!(
-                sopts.ipv4 || sopts.ipv4_network || sopts.domain || sopts.wildcard_domain || sopts.url,
+                any_type || filter.is_some(),
                 "At least one selection should be specified"
             );
 
+            // Filter without explicitly specified types selects addresses of all types.
+            let sopts = SelectOptions {
+                ipv4: ipv4 || !any_type,
+                ipv4_network: ipv4_network || !any_type,
+                domain: domain || !any_type,
+                wildcard_domain: wildcard_domain || !any_type,
+                url: url || !any_type,
+                filter: parse_filter(filter.as_ref())?,
+            };
+
             select(&sopts, reader)
         },
 
+        Command::IntoSqlite { ref database_path } => sqlite::export(reader, database_path),
+
+        #[cfg(feature = "parquet-export")]
+        Command::IntoParquet { ref output_path } => parquet_export::export(reader, output_path),
+
         Command::Updated => Ok(println!("{}", reader.get_timestamp())),
+
+        Command::Grep {
+            glob,
+            ignore_case,
+            organization,
+            document_id,
+            ref pattern,
+        } => {
+            let gopts = grep::GrepOptions {
+                pattern: pattern.clone(),
+                glob,
+                ignore_case,
+                organization,
+                document_id,
+            };
+
+            grep::grep(&gopts, reader)
+        },
+
+        Command::Merge { .. } | Command::History { .. } => unreachable!(),
     }
 }
 
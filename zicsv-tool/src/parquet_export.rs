@@ -0,0 +1,221 @@
+use std;
+
+use arrow;
+use failure;
+use parquet;
+use zicsv;
+
+/// Number of rows (addresses) in one record batch.
+const BATCH_SIZE: usize = 64 * 1024;
+
+struct Columns {
+    address_type: arrow::array::StringBuilder,
+    value: arrow::array::StringBuilder,
+    prefix_len: arrow::array::UInt8Builder,
+    host: arrow::array::StringBuilder,
+    organization: arrow::array::StringBuilder,
+    document_id: arrow::array::StringBuilder,
+    document_date: arrow::array::Date32Builder,
+    updated: arrow::array::TimestampMillisecondBuilder,
+
+    rows: usize,
+}
+
+impl Columns {
+    fn new() -> Self {
+        Self {
+            address_type: arrow::array::StringBuilder::new(BATCH_SIZE),
+            value: arrow::array::StringBuilder::new(BATCH_SIZE),
+            prefix_len: arrow::array::UInt8Builder::new(BATCH_SIZE),
+            host: arrow::array::StringBuilder::new(BATCH_SIZE),
+            organization: arrow::array::StringBuilder::new(BATCH_SIZE),
+            document_id: arrow::array::StringBuilder::new(BATCH_SIZE),
+            document_date: arrow::array::Date32Builder::new(BATCH_SIZE),
+            updated: arrow::array::TimestampMillisecondBuilder::new(BATCH_SIZE),
+
+            rows: 0,
+        }
+    }
+
+    fn schema() -> arrow::datatypes::Schema {
+        use arrow::datatypes::DataType;
+        use arrow::datatypes::Field;
+
+        arrow::datatypes::Schema::new(vec![
+            Field::new("address_type", DataType::Utf8, false),
+            Field::new("value", DataType::Utf8, false),
+            Field::new("prefix_len", DataType::UInt8, true),
+            Field::new("host", DataType::Utf8, true),
+            Field::new("organization", DataType::Utf8, false),
+            Field::new("document_id", DataType::Utf8, false),
+            Field::new("document_date", DataType::Date32, false),
+            Field::new(
+                "updated",
+                DataType::Timestamp(arrow::datatypes::TimeUnit::Millisecond, None),
+                false,
+            ),
+        ])
+    }
+
+    fn append(
+        &mut self,
+        record: &zicsv::Record,
+        address: &zicsv::Address,
+        updated_ms: i64,
+    ) -> Result<(), failure::Error> {
+        let epoch = zicsv::Date::from_ymd(1970, 1, 1);
+
+        self.address_type.append_value(address.address_type().name())?;
+        self.value.append_value(String::from(address))?;
+
+        match *address {
+            zicsv::Address::IPv4(_) => {
+                self.prefix_len.append_value(32)?;
+                self.host.append_null()?;
+            },
+            zicsv::Address::IPv4Network(ref network) => {
+                self.prefix_len.append_value(network.prefix_len())?;
+                self.host.append_null()?;
+            },
+            zicsv::Address::DomainName(ref host) | zicsv::Address::WildcardDomainName(ref host) => {
+                self.prefix_len.append_null()?;
+                self.host.append_value(host)?;
+            },
+            zicsv::Address::URL(ref url) => {
+                self.prefix_len.append_null()?;
+                if let Some(host) = url.host_str() {
+                    self.host.append_value(host)?;
+                } else {
+                    self.host.append_null()?;
+                }
+            },
+            _ => bail!("Unknown address type: \"{:?}\"", address),
+        }
+
+        self.organization.append_value(&record.organization)?;
+        self.document_id.append_value(&record.document_id)?;
+        self.document_date
+            .append_value(record.document_date.signed_duration_since(epoch).num_days() as i32)?;
+        self.updated.append_value(updated_ms)?;
+
+        self.rows += 1;
+        Ok(())
+    }
+
+    fn finish(
+        &mut self,
+        schema: &std::sync::Arc<arrow::datatypes::Schema>,
+    ) -> Result<arrow::record_batch::RecordBatch, failure::Error> {
+        let columns: Vec<arrow::array::ArrayRef> = vec![
+            std::sync::Arc::new(self.address_type.finish()),
+            std::sync::Arc::new(self.value.finish()),
+            std::sync::Arc::new(self.prefix_len.finish()),
+            std::sync::Arc::new(self.host.finish()),
+            std::sync::Arc::new(self.organization.finish()),
+            std::sync::Arc::new(self.document_id.finish()),
+            std::sync::Arc::new(self.document_date.finish()),
+            std::sync::Arc::new(self.updated.finish()),
+        ];
+        self.rows = 0;
+
+        arrow::record_batch::RecordBatch::try_new(schema.clone(), columns).map_err(|error| error.into())
+    }
+}
+
+/// Write list into Parquet file, one row per address.
+pub fn export(mut reader: Box<zicsv::GenericReader>, output_path: &str) -> Result<(), failure::Error> {
+    let updated_ms = reader.get_timestamp().timestamp() * 1000;
+
+    let file = std::fs::File::create(output_path)
+        .map_err(|error| failure::Error::from(error).context(format!("File \"{}\"", output_path)))?;
+
+    let schema = std::sync::Arc::new(Columns::schema());
+    let properties = parquet::file::properties::WriterProperties::builder()
+        .set_compression(parquet::basic::Compression::SNAPPY)
+        // Dictionary encoder of parquet 6.5 hashes values through misaligned slices (`util::hash_util`), which is
+        // undefined behaviour and aborts in debug builds. Lists are small enough to live without dictionaries.
+        .set_dictionary_enabled(false)
+        .build();
+    let mut writer = parquet::arrow::ArrowWriter::try_new(file, schema.clone(), Some(properties))?;
+
+    let mut columns = Columns::new();
+    for record in reader.records_boxed() {
+        let record = record?;
+
+        for address in &record.addresses {
+            columns.append(&record, address, updated_ms)?;
+            if columns.rows >= BATCH_SIZE {
+                writer.write(&columns.finish(&schema)?)?;
+            }
+        }
+    }
+
+    if columns.rows > 0 {
+        writer.write(&columns.finish(&schema)?)?;
+    }
+
+    let _ = writer.close()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+
+    use arrow;
+    use parquet;
+    use zicsv;
+
+    #[test]
+    fn export() {
+        use arrow::array::Array;
+        use parquet::arrow::ArrowReader;
+
+        let path = std::env::temp_dir().join(format!("zicsv-tool-test-parquet-{}", std::process::id()));
+        let reader = zicsv::Reader::from_reader(
+            "\
+             Updated: 2017-12-02 12:00:00 +0000\n\
+             1.2.3.4 | 1.2.3.0/24;example.com;;Org 1;1;2017-01-01\n\
+             ;;http://example.org/test;Org 2;2;2017-01-02\n\
+             "
+                .as_bytes(),
+        ).unwrap();
+        super::export(Box::new(reader), path.to_str().unwrap()).unwrap();
+
+        let file_reader = parquet::file::serialized_reader::SerializedFileReader::new(
+            std::fs::File::open(&path).unwrap(),
+        ).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut arrow_reader = parquet::arrow::ParquetFileArrowReader::new(std::sync::Arc::new(file_reader));
+        let batches: Vec<_> = arrow_reader
+            .get_record_reader(1024)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(batches.len(), 1);
+
+        let batch = &batches[0];
+        assert_eq!(batch.schema(), std::sync::Arc::new(super::Columns::schema()));
+        let strings = |index: usize| -> Vec<Option<String>> {
+            let column = batch
+                .column(index)
+                .as_any()
+                .downcast_ref::<arrow::array::StringArray>()
+                .unwrap();
+            (0..column.len())
+                .map(|row| if column.is_null(row) { None } else { Some(column.value(row).into()) })
+                .collect()
+        };
+        assert_eq!(
+            strings(1),
+            vec![
+                Some("1.2.3.4".into()),
+                Some("1.2.3.0/24".into()),
+                Some("example.com".into()),
+                Some("http://example.org/test".into()),
+            ]
+        );
+        assert_eq!(strings(3), vec![None, None, Some("example.com".into()), Some("example.org".into())]);
+        assert_eq!(strings(4)[3], Some("Org 2".into()));
+    }
+}
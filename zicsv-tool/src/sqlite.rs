@@ -0,0 +1,223 @@
+use std;
+
+use failure;
+use rusqlite;
+use zicsv;
+
+const SCHEMA_VERSION: i64 = 1;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS metadata (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS snapshots (
+        id INTEGER PRIMARY KEY,
+        updated TEXT NOT NULL UNIQUE,
+        imported TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS organizations (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE
+    );
+
+    CREATE TABLE IF NOT EXISTS records (
+        id INTEGER PRIMARY KEY,
+        snapshot_id INTEGER NOT NULL REFERENCES snapshots (id) ON DELETE CASCADE,
+        organization_id INTEGER NOT NULL REFERENCES organizations (id),
+        document_id TEXT NOT NULL,
+        document_date TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS records_snapshot_id ON records (snapshot_id);
+    CREATE INDEX IF NOT EXISTS records_document_id ON records (document_id);
+    CREATE INDEX IF NOT EXISTS records_document_date ON records (document_date);
+
+    CREATE TABLE IF NOT EXISTS addresses (
+        id INTEGER PRIMARY KEY,
+        record_id INTEGER NOT NULL REFERENCES records (id) ON DELETE CASCADE,
+        type TEXT NOT NULL,
+        value TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS addresses_record_id ON addresses (record_id);
+    CREATE INDEX IF NOT EXISTS addresses_type ON addresses (type);
+    CREATE INDEX IF NOT EXISTS addresses_value ON addresses (value);
+";
+
+fn init_schema(connection: &rusqlite::Connection) -> Result<(), failure::Error> {
+    let version: i64 = connection.query_row("PRAGMA user_version", &[], |row| row.get(0))?;
+    match version {
+        // New database.
+        0 => {
+            connection.execute_batch(SCHEMA)?;
+            connection.execute_batch(&format!("PRAGMA user_version = {}", SCHEMA_VERSION))?;
+        },
+
+        SCHEMA_VERSION => (),
+
+        version => bail!(
+            "Unsupported schema version: {} (should be {})",
+            version,
+            SCHEMA_VERSION
+        ),
+    }
+
+    Ok(())
+}
+
+fn get_organization_id(
+    transaction: &rusqlite::Transaction,
+    organizations: &mut std::collections::HashMap<String, i64>,
+    name: &str,
+) -> Result<i64, failure::Error> {
+    if let Some(id) = organizations.get(name) {
+        return Ok(*id);
+    }
+
+    let _ = transaction
+        .prepare_cached("INSERT OR IGNORE INTO organizations (name) VALUES (?)")?
+        .execute(&[&name])?;
+    let id: i64 = transaction.query_row(
+        "SELECT id FROM organizations WHERE name = ?",
+        &[&name],
+        |row| row.get(0),
+    )?;
+
+    let _ = organizations.insert(name.into(), id);
+    Ok(id)
+}
+
+fn insert_snapshot(
+    transaction: &rusqlite::Transaction,
+    mut reader: Box<zicsv::GenericReader>,
+) -> Result<(), failure::Error> {
+    let updated = format!("{}", reader.get_timestamp().format("%Y-%m-%d %H:%M:%S"));
+
+    let exists: i64 = transaction.query_row(
+        "SELECT COUNT(*) FROM snapshots WHERE updated = ?",
+        &[&updated],
+        |row| row.get(0),
+    )?;
+    ensure!(
+        exists == 0,
+        "Snapshot with date of last update {} already exists",
+        updated
+    );
+
+    let _ = transaction.execute(
+        "INSERT INTO snapshots (updated, imported) VALUES (?, datetime('now'))",
+        &[&updated],
+    )?;
+    let snapshot_id = transaction.last_insert_rowid();
+
+    // Snapshots may be appended in any order, metadata describes the latest one.
+    let _ = transaction.execute(
+        "INSERT OR REPLACE INTO metadata (key, value) SELECT 'updated', MAX(updated) FROM snapshots",
+        &[],
+    )?;
+
+    let mut organizations = std::collections::HashMap::new();
+    for record in reader.records_boxed() {
+        let record = record?;
+
+        let organization_id = get_organization_id(transaction, &mut organizations, &record.organization)?;
+        let document_date = format!("{}", record.document_date.format("%Y-%m-%d"));
+        let record_id = transaction
+            .prepare_cached(
+                "INSERT INTO records (snapshot_id, organization_id, document_id, document_date) VALUES (?, ?, ?, ?)",
+            )?
+            .insert(&[
+                &snapshot_id,
+                &organization_id,
+                &record.document_id,
+                &document_date,
+            ])?;
+
+        let mut statement =
+            transaction.prepare_cached("INSERT INTO addresses (record_id, type, value) VALUES (?, ?, ?)")?;
+        for address in &record.addresses {
+            let _ = statement.execute(&[
+                &record_id,
+                &address.address_type().name(),
+                &String::from(address),
+            ])?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Append list into SQLite database as a new snapshot. Database and schema are created if necessary.
+pub fn export(reader: Box<zicsv::GenericReader>, database_path: &str) -> Result<(), failure::Error> {
+    let mut connection = rusqlite::Connection::open(database_path)
+        .map_err(|error| failure::Error::from(error).context(format!("Database \"{}\"", database_path)))?;
+    connection.execute_batch("PRAGMA foreign_keys = ON")?;
+
+    append(&mut connection, reader)
+}
+
+fn append(connection: &mut rusqlite::Connection, reader: Box<zicsv::GenericReader>) -> Result<(), failure::Error> {
+    let transaction = connection.transaction()?;
+    init_schema(&transaction)?;
+    insert_snapshot(&transaction, reader)?;
+    transaction.commit().map_err(|error| error.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite;
+    use zicsv;
+
+    fn reader(text: &'static str) -> Box<zicsv::GenericReader> {
+        Box::new(zicsv::Reader::from_reader(text.as_bytes()).unwrap())
+    }
+
+    fn count(connection: &rusqlite::Connection, table: &str) -> i64 {
+        connection
+            .query_row(&format!("SELECT COUNT(*) FROM {}", table), &[], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn append() {
+        let mut connection = rusqlite::Connection::open_in_memory().unwrap();
+        connection.execute_batch("PRAGMA foreign_keys = ON").unwrap();
+
+        super::append(
+            &mut connection,
+            reader(
+                "\
+                 Updated: 2017-12-02 12:00:00 +0000\n\
+                 1.2.3.4 | 1.2.3.0/24;example.com;;Org 1;1;2017-01-01\n\
+                 ;;http://example.com/;Org 2;2;2017-01-02\n\
+                 ",
+            ),
+        ).unwrap();
+        super::append(
+            &mut connection,
+            reader(
+                "\
+                 Updated: 2017-12-01 12:00:00 +0000\n\
+                 1.2.3.4;;;Org 1;1;2017-01-01\n\
+                 ",
+            ),
+        ).unwrap();
+        assert!(
+            super::append(
+                &mut connection,
+                reader("Updated: 2017-12-01 12:00:00 +0000\n"),
+            ).is_err()
+        );
+
+        assert_eq!(count(&connection, "snapshots"), 2);
+        assert_eq!(count(&connection, "organizations"), 2);
+        assert_eq!(count(&connection, "records"), 3);
+        assert_eq!(count(&connection, "addresses"), 5);
+
+        let updated: String = connection
+            .query_row("SELECT value FROM metadata WHERE key = 'updated'", &[], |row| row.get(0))
+            .unwrap();
+        assert_eq!(updated, "2017-12-02 12:00:00");
+    }
+}
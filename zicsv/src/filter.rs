@@ -0,0 +1,582 @@
+use std;
+
+use failure;
+
+use types;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    LeftParen,
+    RightParen,
+    And,
+    Or,
+    Not,
+    Operator(Operator),
+    Quoted(String),
+    Word(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operator {
+    Equal,
+    NotEqual,
+    Contains,
+    NotContains,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Field {
+    Type,
+    Address,
+    Organization,
+    DocumentId,
+    DocumentDate,
+}
+
+#[derive(Debug)]
+enum Condition {
+    TypeEqual(types::AddressType),
+    AddressEqual(String),
+    AddressContains(String),
+    OrganizationEqual(String),
+    OrganizationContains(String),
+    DocumentIdEqual(String),
+    DocumentIdContains(String),
+    DocumentDate(std::cmp::Ordering, types::Date),
+}
+
+#[derive(Debug)]
+enum Node {
+    Condition(Condition),
+    Not(Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+}
+
+/// Compiled expression for selection of records and addresses.
+///
+/// Syntax: `type == ipv4_network && org ~ "court" && date >= 2017-01-01`.
+///
+/// Fields: `type` (`ipv4`, `ipv4_network`, `domain`, `wildcard_domain`, `url`), `addr`, `org`, `id` and `date`
+/// (`YYYY-MM-DD`). Operators: `==`, `!=`, `~` and `!~` (case-insensitive substring, only for strings),
+/// `<`, `<=`, `>` and `>=` (only for date). Conditions may be combined using `&&`, `||`, `!` and parentheses.
+#[derive(Debug)]
+pub struct Filter {
+    root: Node,
+}
+
+struct Lexer<'a> {
+    expression: &'a str,
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(expression: &'a str) -> Self {
+        Self {
+            expression,
+            chars: expression.char_indices().peekable(),
+        }
+    }
+
+    fn is_word_char(chr: char) -> bool {
+        chr.is_alphanumeric() || "_-.:/*@%+?#".contains(chr)
+    }
+
+    fn next_if(&mut self, expected: char) -> bool {
+        if self.chars.peek().map(|&(_, chr)| chr) == Some(expected) {
+            let _ = self.chars.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, pos: usize, expected: char) -> Result<(), failure::Error> {
+        ensure!(
+            self.next_if(expected),
+            "Position {}: '{}' expected",
+            pos + 1,
+            expected
+        );
+        Ok(())
+    }
+
+    fn quoted(&mut self, pos: usize) -> Result<Token, failure::Error> {
+        let mut value = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '"')) => return Ok(Token::Quoted(value)),
+                Some((_, '\\')) => match self.chars.next() {
+                    Some((_, chr)) => value.push(chr),
+                    None => break,
+                },
+                Some((_, chr)) => value.push(chr),
+                None => break,
+            }
+        }
+
+        bail!("Position {}: unterminated string", pos + 1)
+    }
+
+    fn word(&mut self, pos: usize) -> Token {
+        let mut end = self.expression.len();
+        while let Some(&(next_pos, chr)) = self.chars.peek() {
+            if !Self::is_word_char(chr) {
+                end = next_pos;
+                break;
+            }
+            let _ = self.chars.next();
+        }
+
+        Token::Word(self.expression[pos..end].into())
+    }
+
+    fn next_token(&mut self) -> Option<Result<(usize, Token), failure::Error>> {
+        while let Some(&(_, chr)) = self.chars.peek() {
+            if !chr.is_whitespace() {
+                break;
+            }
+            let _ = self.chars.next();
+        }
+
+        let (pos, chr) = self.chars.next()?;
+        let token = match chr {
+            '(' => Ok(Token::LeftParen),
+            ')' => Ok(Token::RightParen),
+            '&' => self.expect(pos, '&').map(|_| Token::And),
+            '|' => self.expect(pos, '|').map(|_| Token::Or),
+            '~' => Ok(Token::Operator(Operator::Contains)),
+            '!' => Ok(if self.next_if('=') {
+                Token::Operator(Operator::NotEqual)
+            } else if self.next_if('~') {
+                Token::Operator(Operator::NotContains)
+            } else {
+                Token::Not
+            }),
+            '=' => self.expect(pos, '=').map(|_| Token::Operator(Operator::Equal)),
+            '<' => Ok(Token::Operator(if self.next_if('=') {
+                Operator::LessOrEqual
+            } else {
+                Operator::Less
+            })),
+            '>' => Ok(Token::Operator(if self.next_if('=') {
+                Operator::GreaterOrEqual
+            } else {
+                Operator::Greater
+            })),
+            '"' => self.quoted(pos),
+            chr if Self::is_word_char(chr) => Ok(self.word(pos)),
+            chr => Err(format_err!("Position {}: unexpected character '{}'", pos + 1, chr)),
+        };
+
+        Some(token.map(|token| (pos, token)))
+    }
+}
+
+/// Maximum nesting depth of negations and parentheses. Parser is recursive, so deeper expressions could overflow stack.
+const MAX_DEPTH: usize = 64;
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    end: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, token)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |&(pos, _)| pos) + 1
+    }
+
+    fn next(&mut self) -> Result<Token, failure::Error> {
+        let token = self.tokens
+            .get(self.pos)
+            .map(|(_, token)| token.clone())
+            .ok_or_else(|| format_err!("Position {}: unexpected end of expression", self.end + 1))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn parse_or(&mut self) -> Result<Node, failure::Error> {
+        let mut node = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            node = Node::Or(Box::new(node), Box::new(self.parse_and()?));
+        }
+        Ok(node)
+    }
+
+    fn parse_and(&mut self) -> Result<Node, failure::Error> {
+        let mut node = self.parse_unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            node = Node::And(Box::new(node), Box::new(self.parse_unary()?));
+        }
+        Ok(node)
+    }
+
+    fn parse_unary(&mut self) -> Result<Node, failure::Error> {
+        match self.peek() {
+            Some(&Token::Not) | Some(&Token::LeftParen) => {
+                ensure!(
+                    self.depth < MAX_DEPTH,
+                    "Position {}: expression is nested too deeply (should be not more than {} levels)",
+                    self.position(),
+                    MAX_DEPTH
+                );
+                self.depth += 1;
+                let node = self.parse_nested();
+                self.depth -= 1;
+                node
+            },
+
+            _ => self.parse_condition(),
+        }
+    }
+
+    fn parse_nested(&mut self) -> Result<Node, failure::Error> {
+        match self.next()? {
+            Token::Not => Ok(Node::Not(Box::new(self.parse_unary()?))),
+
+            _ => {
+                let node = self.parse_or()?;
+                let pos = self.position();
+                match self.next()? {
+                    Token::RightParen => Ok(node),
+                    token => bail!("Position {}: ')' expected, got {:?}", pos, token),
+                }
+            },
+        }
+    }
+
+    fn parse_field(&mut self) -> Result<Field, failure::Error> {
+        let pos = self.position();
+        match self.next()? {
+            Token::Word(ref word) => match word.as_str() {
+                "type" => Ok(Field::Type),
+                "addr" | "address" => Ok(Field::Address),
+                "org" | "organization" => Ok(Field::Organization),
+                "id" | "document_id" => Ok(Field::DocumentId),
+                "date" | "document_date" => Ok(Field::DocumentDate),
+                _ => bail!("Position {}: unknown field \"{}\"", pos, word),
+            },
+            token => bail!("Position {}: field name expected, got {:?}", pos, token),
+        }
+    }
+
+    fn parse_condition(&mut self) -> Result<Node, failure::Error> {
+        let field = self.parse_field()?;
+
+        let pos = self.position();
+        let operator = match self.next()? {
+            Token::Operator(operator) => operator,
+            token => bail!("Position {}: operator expected, got {:?}", pos, token),
+        };
+
+        let pos = self.position();
+        let value = match self.next()? {
+            Token::Quoted(value) | Token::Word(value) => value,
+            token => bail!("Position {}: value expected, got {:?}", pos, token),
+        };
+
+        let (condition, negate) = Self::compile_condition(field, operator, value)
+            .map_err(|error| format_err!("Position {}: {}", pos, error))?;
+        let node = Node::Condition(condition);
+        Ok(if negate { Node::Not(Box::new(node)) } else { node })
+    }
+
+    fn compile_condition(
+        field: Field,
+        operator: Operator,
+        value: String,
+    ) -> Result<(Condition, bool), failure::Error> {
+        use std::str::FromStr;
+
+        let (equal, negate) = match operator {
+            Operator::Equal => (true, false),
+            Operator::NotEqual => (true, true),
+            Operator::Contains => (false, false),
+            Operator::NotContains => (false, true),
+
+            Operator::Less | Operator::LessOrEqual | Operator::Greater | Operator::GreaterOrEqual => {
+                ensure!(
+                    field == Field::DocumentDate,
+                    "Operator {:?} is supported only for date",
+                    operator
+                );
+                (false, false)
+            },
+        };
+
+        let condition = match field {
+            Field::Type if equal => Condition::TypeEqual(types::AddressType::from_str(&value)?),
+            Field::Type => bail!("Operator {:?} is not supported for type", operator),
+
+            Field::Address if equal => Condition::AddressEqual(value),
+            Field::Address => Condition::AddressContains(value.to_lowercase()),
+
+            Field::Organization if equal => Condition::OrganizationEqual(value),
+            Field::Organization => Condition::OrganizationContains(value.to_lowercase()),
+
+            Field::DocumentId if equal => Condition::DocumentIdEqual(value),
+            Field::DocumentId => Condition::DocumentIdContains(value.to_lowercase()),
+
+            Field::DocumentDate => {
+                let date = types::Date::parse_from_str(&value, "%Y-%m-%d")
+                    .map_err(|error| format_err!("Invalid date \"{}\" ({})", value, error))?;
+
+                // "a >= b" is "!(a < b)" and "a <= b" is "!(a > b)".
+                let (ordering, negate) = match operator {
+                    Operator::Equal => (std::cmp::Ordering::Equal, false),
+                    Operator::NotEqual => (std::cmp::Ordering::Equal, true),
+                    Operator::Less => (std::cmp::Ordering::Less, false),
+                    Operator::GreaterOrEqual => (std::cmp::Ordering::Less, true),
+                    Operator::Greater => (std::cmp::Ordering::Greater, false),
+                    Operator::LessOrEqual => (std::cmp::Ordering::Greater, true),
+                    Operator::Contains | Operator::NotContains => {
+                        bail!("Operator {:?} is not supported for date", operator)
+                    },
+                };
+                return Ok((Condition::DocumentDate(ordering, date), negate));
+            },
+        };
+
+        Ok((condition, negate))
+    }
+}
+
+impl Condition {
+    fn contains(haystack: &str, needle_lowercase: &str) -> bool {
+        haystack.to_lowercase().contains(needle_lowercase)
+    }
+
+    /// `None` if condition is on address, but there is no address.
+    fn matches(&self, record: &types::Record, address: Option<&types::Address>) -> Option<bool> {
+        match *self {
+            Condition::TypeEqual(address_type) => address.map(|address| address.address_type() == address_type),
+            Condition::AddressEqual(ref value) => address.map(|address| String::from(address) == *value),
+            Condition::AddressContains(ref value) => {
+                address.map(|address| Self::contains(&String::from(address), value))
+            },
+
+            Condition::OrganizationEqual(ref value) => Some(record.organization == *value),
+            Condition::OrganizationContains(ref value) => Some(Self::contains(&record.organization, value)),
+
+            Condition::DocumentIdEqual(ref value) => Some(record.document_id == *value),
+            Condition::DocumentIdContains(ref value) => Some(Self::contains(&record.document_id, value)),
+
+            Condition::DocumentDate(ordering, ref value) => Some(record.document_date.cmp(value) == ordering),
+        }
+    }
+}
+
+impl Node {
+    /// Three-valued logic, as in SQL: `None` is unknown result of condition on missing address. It stays unknown
+    /// under negation, so neither condition nor its negation matches.
+    fn matches(&self, record: &types::Record, address: Option<&types::Address>) -> Option<bool> {
+        match *self {
+            Node::Condition(ref condition) => condition.matches(record, address),
+            Node::Not(ref node) => node.matches(record, address).map(|result| !result),
+            Node::And(ref left, ref right) => match (left.matches(record, address), right.matches(record, address)) {
+                (Some(false), _) | (_, Some(false)) => Some(false),
+                (Some(true), Some(true)) => Some(true),
+                _ => None,
+            },
+            Node::Or(ref left, ref right) => match (left.matches(record, address), right.matches(record, address)) {
+                (Some(true), _) | (_, Some(true)) => Some(true),
+                (Some(false), Some(false)) => Some(false),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl Filter {
+    /// Parse and compile expression.
+    pub fn parse(expression: &str) -> Result<Self, failure::Error> {
+        let mut lexer = Lexer::new(expression);
+        let mut tokens = Vec::new();
+        while let Some(token) = lexer.next_token() {
+            tokens.push(token?);
+        }
+        ensure!(!tokens.is_empty(), "Empty expression");
+
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            end: expression.len(),
+            depth: 0,
+        };
+        let root = parser.parse_or()?;
+        if let Some(token) = parser.peek() {
+            bail!("Position {}: unexpected {:?}", parser.position(), token);
+        }
+
+        Ok(Self { root })
+    }
+
+    /// Check whether single address from record matches.
+    pub fn matches_address(&self, record: &types::Record, address: &types::Address) -> bool {
+        self.root.matches(record, Some(address)).unwrap_or(false)
+    }
+
+    /// Check whether record matches. Record matches if at least one of its addresses matches. Conditions on
+    /// addresses are unknown for records without addresses: neither they nor their negations match, so such record
+    /// matches only if expression holds regardless of them.
+    pub fn matches_record(&self, record: &types::Record) -> bool {
+        if record.addresses.is_empty() {
+            self.root.matches(record, None).unwrap_or(false)
+        } else {
+            record
+                .addresses
+                .iter()
+                .any(|address| self.matches_address(record, address))
+        }
+    }
+}
+
+impl std::str::FromStr for Filter {
+    type Err = failure::Error;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        Self::parse(expression)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono;
+
+    use types;
+
+    use super::{Filter, MAX_DEPTH};
+
+    fn record() -> types::Record {
+        types::Record {
+            addresses: vec![
+                types::Address::IPv4("1.2.3.4".parse().unwrap()),
+                types::Address::IPv4Network("1.2.3.0/24".parse().unwrap()),
+                types::Address::DomainName("example.com".into()),
+                types::Address::URL("http://example.com/test".parse().unwrap()),
+            ],
+            organization: "\u{421}\u{443}\u{434} (Court)".into(),
+            document_id: "2-1234/2017".into(),
+            document_date: chrono::NaiveDate::from_ymd(2017, 3, 4),
+
+            __may_be_extended: (),
+        }
+    }
+
+    fn matches(expression: &str) -> bool {
+        Filter::parse(expression).unwrap().matches_record(&record())
+    }
+
+    fn selected(expression: &str) -> Vec<String> {
+        let filter = Filter::parse(expression).unwrap();
+        let record = record();
+        record
+            .addresses
+            .iter()
+            .filter(|address| filter.matches_address(&record, address))
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn match_fields() {
+        assert!(matches("type == ipv4"));
+        assert!(!matches("type == wildcard_domain"));
+        assert!(matches("type != ipv4"));
+        assert!(matches("addr == 1.2.3.0/24"));
+        assert!(matches("addr ~ EXAMPLE"));
+        assert!(!matches("addr ~ example.org"));
+        assert!(matches("org ~ \"\u{441}\u{443}\u{434}\""));
+        assert!(matches("org == \"\u{421}\u{443}\u{434} (Court)\""));
+        assert!(!matches("org !~ court"));
+        assert!(matches("id == 2-1234/2017"));
+        assert!(matches("id ~ 1234"));
+        assert!(matches("date == 2017-03-04"));
+        assert!(matches("date >= 2017-03-04"));
+        assert!(matches("date <= 2017-03-04"));
+        assert!(!matches("date < 2017-03-04"));
+        assert!(!matches("date > 2017-03-04"));
+        assert!(matches("date > 2017-01-01"));
+        assert!(matches("date != 2017-01-01"));
+    }
+
+    #[test]
+    fn match_combinations() {
+        assert!(matches("type == ipv4_network && org ~ court && date >= 2017-01-01"));
+        assert!(!matches("type == ipv4_network && org ~ court && date >= 2018-01-01"));
+        assert!(matches("type == wildcard_domain || date < 2018-01-01"));
+        assert!(matches("!(type == wildcard_domain)"));
+        assert!(!matches("!type == ipv4 && type == wildcard_domain"));
+        assert!(matches("(type == ipv4 || type == url) && !(id == x)"));
+        assert!(matches("type==ipv4&&addr==1.2.3.4"));
+    }
+
+    #[test]
+    fn match_addresses() {
+        assert_eq!(selected("type == ipv4 || type == ipv4_network"), vec!["1.2.3.4", "1.2.3.0/24"]);
+        assert_eq!(selected("addr ~ example && type != url"), vec!["example.com"]);
+        assert_eq!(selected("type == url && addr ~ \"/test\""), vec!["http://example.com/test"]);
+        assert!(selected("type == ipv4 && type == url").is_empty());
+    }
+
+    #[test]
+    fn match_no_addresses() {
+        let mut record = record();
+        record.addresses.clear();
+
+        let filter = Filter::parse("type == ipv4 || org ~ court").unwrap();
+        assert!(filter.matches_record(&record));
+
+        let filter = Filter::parse("type == ipv4 && org ~ court").unwrap();
+        assert!(!filter.matches_record(&record));
+
+        let filter = Filter::parse("!(type == ipv4)").unwrap();
+        assert!(!filter.matches_record(&record));
+
+        let filter = Filter::parse("type != ipv4 && org ~ court").unwrap();
+        assert!(!filter.matches_record(&record));
+
+        let filter = Filter::parse("!(type == ipv4 && org ~ example)").unwrap();
+        assert!(filter.matches_record(&record));
+
+        let filter = Filter::parse("!(type == ipv4 || org ~ court)").unwrap();
+        assert!(!filter.matches_record(&record));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(Filter::parse("").is_err());
+        assert!(Filter::parse("type").is_err());
+        assert!(Filter::parse("type ==").is_err());
+        assert!(Filter::parse("type == ipv6").is_err());
+        assert!(Filter::parse("type ~ ipv4").is_err());
+        assert!(Filter::parse("unknown == x").is_err());
+        assert!(Filter::parse("org < x").is_err());
+        assert!(Filter::parse("date ~ 2017").is_err());
+        assert!(Filter::parse("date == 2017-13-01").is_err());
+        assert!(Filter::parse("org == \"x").is_err());
+        assert!(Filter::parse("(type == ipv4").is_err());
+        assert!(Filter::parse("type == ipv4)").is_err());
+        assert!(Filter::parse("type == ipv4 & org == x").is_err());
+        assert!(Filter::parse("type == ipv4 org == x").is_err());
+        assert!(Filter::parse("type = ipv4").is_err());
+
+        let nested = |depth| format!("{}type == ipv4{}", "(!".repeat(depth), ")".repeat(depth));
+        assert!(Filter::parse(&nested(MAX_DEPTH / 2)).is_ok());
+        assert!(Filter::parse(&nested(MAX_DEPTH / 2 + 1)).is_err());
+        assert!(Filter::parse(&"!".repeat(100_000)).is_err());
+        assert!(Filter::parse(&"(".repeat(100_000)).is_err());
+    }
+}
@@ -44,8 +44,14 @@ extern crate url_serde;
 #[cfg(feature = "serialization")]
 mod ipnet_serde;
 
+mod filter;
+pub use filter::*;
+
 mod reader;
 pub use reader::*;
 
 mod types;
 pub use types::*;
+
+mod writer;
+pub use writer::*;
@@ -1,6 +1,7 @@
 use std;
 
 use chrono;
+use failure;
 use ipnet;
 
 use url;
@@ -32,6 +33,27 @@ pub enum Address {
     __NonExhaustive,
 }
 
+/// Type of blocked address.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+pub enum AddressType {
+    /// `Address::IPv4`, "ipv4".
+    #[cfg_attr(feature = "serialization", serde(rename = "ipv4"))]
+    IPv4,
+    /// `Address::IPv4Network`, "ipv4_network".
+    #[cfg_attr(feature = "serialization", serde(rename = "ipv4_network"))]
+    IPv4Network,
+    /// `Address::DomainName`, "domain".
+    #[cfg_attr(feature = "serialization", serde(rename = "domain"))]
+    DomainName,
+    /// `Address::WildcardDomainName`, "wildcard_domain".
+    #[cfg_attr(feature = "serialization", serde(rename = "wildcard_domain"))]
+    WildcardDomainName,
+    /// `Address::URL`, "url".
+    #[cfg_attr(feature = "serialization", serde(rename = "url"))]
+    URL,
+}
+
 pub type Addresses = Vec<Address>;
 pub type Date = chrono::NaiveDate;
 
@@ -56,22 +78,81 @@ pub struct Record {
 
 pub type DateTime = chrono::NaiveDateTime;
 
-impl<'a> From<&'a Address> for String {
+impl From<&Address> for String {
     fn from(address: &Address) -> Self {
-        #[allow(non_snake_case)]
-        match address {
-            &Address::IPv4(value) => format!("{}", value),
-            &Address::IPv4Network(value) => format!("{}/{}", value.addr(), value.prefix_len()),
+        match *address {
+            Address::IPv4(value) => format!("{}", value),
+            Address::IPv4Network(value) => format!("{}/{}", value.addr(), value.prefix_len()),
 
-            &Address::DomainName(ref value) | &Address::WildcardDomainName(ref value) => value.clone(),
+            Address::DomainName(ref value) | Address::WildcardDomainName(ref value) => value.clone(),
+
+            Address::URL(ref value) => value.as_str().into(),
+
+            Address::__NonExhaustive => unreachable!(),
+        }
+    }
+}
+
+impl Address {
+    /// Type of this address.
+    pub fn address_type(&self) -> AddressType {
+        match *self {
+            Address::IPv4(_) => AddressType::IPv4,
+            Address::IPv4Network(_) => AddressType::IPv4Network,
+            Address::DomainName(_) => AddressType::DomainName,
+            Address::WildcardDomainName(_) => AddressType::WildcardDomainName,
+            Address::URL(_) => AddressType::URL,
+
+            Address::__NonExhaustive => unreachable!(),
+        }
+    }
+}
 
-            &Address::URL(ref value) => value.as_str().into(),
+impl AddressType {
+    /// All known types of addresses.
+    pub fn all() -> &'static [Self] {
+        &[
+            AddressType::IPv4,
+            AddressType::IPv4Network,
+            AddressType::DomainName,
+            AddressType::WildcardDomainName,
+            AddressType::URL,
+        ]
+    }
 
-            __NonExhaustive => unreachable!(),
+    /// Short name used in command line options, configuration files and filter expressions.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            AddressType::IPv4 => "ipv4",
+            AddressType::IPv4Network => "ipv4_network",
+            AddressType::DomainName => "domain",
+            AddressType::WildcardDomainName => "wildcard_domain",
+            AddressType::URL => "url",
         }
     }
 }
 
+impl std::str::FromStr for AddressType {
+    type Err = failure::Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::all()
+            .iter()
+            .find(|address_type| address_type.name() == name)
+            .cloned()
+            .ok_or_else(|| {
+                let names: Vec<&str> = Self::all().iter().map(|address_type| address_type.name()).collect();
+                format_err!("Unknown address type \"{}\" (should be one of: {})", name, names.join(", "))
+            })
+    }
+}
+
+impl std::fmt::Display for AddressType {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter, "{}", self.name())
+    }
+}
+
 impl std::fmt::Display for Address {
     fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(formatter, "{}", String::from(self))
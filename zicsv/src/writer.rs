@@ -0,0 +1,198 @@
+use std;
+
+use csv;
+use encoding;
+use failure;
+
+use types;
+
+pub struct Writer<StreamWriter>
+where
+    StreamWriter: std::io::Write,
+{
+    csv_writer: csv::Writer<StreamWriter>,
+}
+
+impl<StreamWriter> Writer<StreamWriter>
+where
+    StreamWriter: std::io::Write,
+{
+    fn str_into_cp1251(value: &str) -> Result<Vec<u8>, failure::Error> {
+        use encoding::Encoding;
+
+        encoding::all::WINDOWS_1251
+            .encode(value, encoding::EncoderTrap::Strict)
+            .map_err(|error| format_err!("String not representable in CP1251: \"{}\" ({})", value, error))
+    }
+
+    fn join_addresses<SelectFn>(addresses: &[types::Address], mut select: SelectFn) -> String
+    where
+        SelectFn: FnMut(&types::Address) -> bool,
+    {
+        let parts: Vec<String> = addresses
+            .iter()
+            .filter(|address| select(address))
+            .map(String::from)
+            .collect();
+
+        // " | " is the only delimiter that is safe for all types of addresses (URL itself may contain '|').
+        parts.join(" | ")
+    }
+
+    /// Write list into stream. Line with date of last update is written immediately.
+    pub fn from_writer(mut writer: StreamWriter, updated: &types::DateTime) -> Result<Self, failure::Error> {
+        writeln!(writer, "Updated: {} +0000", updated.format("%Y-%m-%d %H:%M:%S"))?;
+
+        Ok(Self {
+            csv_writer: csv::Writer::from_writer(writer).delimiter(b';').flexible(true),
+        })
+    }
+
+    /// Write one record.
+    pub fn write_record(&mut self, record: &types::Record) -> Result<(), failure::Error> {
+        let ipv4 = Self::join_addresses(&record.addresses, |address| {
+            matches!(*address, types::Address::IPv4(_) | types::Address::IPv4Network(_))
+        });
+        let domains = Self::join_addresses(&record.addresses, |address| {
+            matches!(*address, types::Address::DomainName(_) | types::Address::WildcardDomainName(_))
+        });
+        let urls = Self::join_addresses(&record.addresses, |address| matches!(*address, types::Address::URL(_)));
+
+        let fields = [
+            Self::str_into_cp1251(&ipv4)?,
+            Self::str_into_cp1251(&domains)?,
+            Self::str_into_cp1251(&urls)?,
+            Self::str_into_cp1251(&record.organization)?,
+            Self::str_into_cp1251(&record.document_id)?,
+            Self::str_into_cp1251(&format!("{}", record.document_date.format("%Y-%m-%d")))?,
+        ];
+
+        self.csv_writer
+            .write(fields.iter().map(|field| &field[..]))
+            .map_err(|error| error.into())
+    }
+
+    /// Flush buffered data into underlying stream.
+    pub fn flush(&mut self) -> Result<(), failure::Error> {
+        self.csv_writer.flush().map_err(|error| error.into())
+    }
+}
+
+impl Writer<std::fs::File> {
+    fn from_file_no_context<Path: AsRef<std::path::Path>>(
+        path: Path,
+        updated: &types::DateTime,
+    ) -> Result<Self, failure::Error> {
+        Self::from_writer(std::fs::File::create(path)?, updated)
+    }
+
+    /// Write list into file specified by path. File is truncated if it exists.
+    pub fn from_file<Path: AsRef<std::path::Path>>(
+        path: Path,
+        updated: &types::DateTime,
+    ) -> Result<Self, failure::Error> {
+        let path_str = format!("{}", path.as_ref().to_string_lossy());
+        Self::from_file_no_context(path, updated)
+            .map_err(|error| error.context(format!("File \"{}\"", path_str)).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+
+    use chrono;
+    use failure;
+
+    use types;
+
+    fn write_list(updated: &types::DateTime, records: &[types::Record]) -> Result<Vec<u8>, failure::Error> {
+        let mut buffer = Vec::new();
+        {
+            let mut writer = super::Writer::from_writer(&mut buffer, updated)?;
+            for record in records {
+                writer.write_record(record)?;
+            }
+            writer.flush()?;
+        }
+        Ok(buffer)
+    }
+
+    #[test]
+    fn write_no_records() {
+        let updated = chrono::NaiveDate::from_ymd(2017, 11, 29).and_hms(13, 34, 56);
+        let data = write_list(&updated, &[]).unwrap();
+        assert_eq!(
+            String::from_utf8(data).unwrap(),
+            "Updated: 2017-11-29 13:34:56 +0000\n"
+        );
+    }
+
+    #[test]
+    fn write_valid_records() {
+        let updated = chrono::NaiveDate::from_ymd(2017, 11, 29).and_hms(13, 34, 56);
+        let records = vec![
+            types::Record {
+                addresses: vec![
+                    types::Address::IPv4("1.2.3.4".parse().unwrap()),
+                    types::Address::IPv4Network("1.2.3.0/24".parse().unwrap()),
+                    types::Address::DomainName("example.com".into()),
+                    types::Address::WildcardDomainName("*.example.com".into()),
+                    types::Address::URL("http://example.com/?test=x|y".parse().unwrap()),
+                    types::Address::URL("http://example.com/?test=z".parse().unwrap()),
+                ],
+                organization: "org;string".into(),
+                document_id: "id string".into(),
+                document_date: chrono::NaiveDate::from_ymd(2017, 1, 2),
+
+                __may_be_extended: (),
+            },
+            types::Record {
+                addresses: vec![],
+                organization: "".into(),
+                document_id: "".into(),
+                document_date: chrono::NaiveDate::from_ymd(2017, 1, 3),
+
+                __may_be_extended: (),
+            },
+        ];
+        let data = write_list(&updated, &records).unwrap();
+        assert_eq!(
+            String::from_utf8(data.clone()).unwrap(),
+            "\
+             Updated: 2017-11-29 13:34:56 +0000\n\
+             1.2.3.4 | 1.2.3.0/24;example.com | *.example.com;\
+             http://example.com/?test=x|y | http://example.com/?test=z;\"org;string\";id string;2017-01-02\n\
+             ;;;;;2017-01-03\n\
+             "
+        );
+
+        // Written list should be parsed back into the same records.
+        let mut reader = ::reader::Reader::from_reader(std::io::Cursor::new(data)).unwrap();
+        assert_eq!(*::reader::GenericReader::get_timestamp(&reader), updated);
+        let parsed: Vec<types::Record> = reader.records().map(|record| record.unwrap()).collect();
+        assert_eq!(parsed.len(), records.len());
+        for (parsed, record) in parsed.iter().zip(records.iter()) {
+            assert_eq!(parsed.addresses, record.addresses);
+            assert_eq!(parsed.organization, record.organization);
+            assert_eq!(parsed.document_id, record.document_id);
+            assert_eq!(parsed.document_date, record.document_date);
+        }
+    }
+
+    #[test]
+    fn write_non_cp1251_record() {
+        let updated = chrono::NaiveDate::from_ymd(2017, 11, 29).and_hms(13, 34, 56);
+        let records = vec![
+            types::Record {
+                addresses: vec![],
+                organization: "\u{1f600}".into(),
+                document_id: "".into(),
+                document_date: chrono::NaiveDate::from_ymd(2017, 1, 2),
+
+                __may_be_extended: (),
+            },
+        ];
+        assert!(write_list(&updated, &records).is_err());
+    }
+}